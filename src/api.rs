@@ -1,15 +1,101 @@
-use crate::statistics::Statistics;
+use crate::config::Config;
+use crate::plugins::{CacheKeyStats, OverrideAction, SharedPlugin, TxtValue, UpstreamHealth};
+use crate::statistics::{Histogram, Statistics, TIMING_BUCKETS};
 use anyhow::Result;
-use axum::{routing::get, Json, Router};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{header, StatusCode};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use axum::{Json, Router};
+use std::collections::{BTreeMap, HashMap};
 use std::net::SocketAddr;
 use std::sync::{Arc, RwLock};
 use tokio::net::TcpListener;
 use tracing::info;
 
-pub async fn start_api_server(stats: Arc<RwLock<Statistics>>, port: u16) -> Result<()> {
-    let app = Router::new().route("/stats", get(move || get_stats(stats)));
+type Registry = Arc<HashMap<String, SharedPlugin>>;
+
+/// Shared state handed to every API route.
+///
+/// Clones share the plugin registry and config, so `update` on one (after a
+/// config reload) is seen by all routes.
+#[derive(Clone)]
+pub struct ApiState {
+    pub stats: Arc<RwLock<Statistics>>,
+    plugins: Arc<RwLock<Registry>>,
+    /// Redacted effective config served on `/config`.
+    config: Arc<RwLock<Option<Arc<serde_json::Value>>>>,
+    /// Bearer token of the mutating routes, from `api_token`.
+    token: Arc<RwLock<Option<String>>>,
+}
+
+impl ApiState {
+    pub fn new(stats: Arc<RwLock<Statistics>>) -> Self {
+        Self {
+            stats,
+            plugins: Arc::default(),
+            config: Arc::default(),
+            token: Arc::default(),
+        }
+    }
+
+    pub fn with_plugins(self, plugins: HashMap<String, SharedPlugin>) -> Self {
+        *self.plugins.write().unwrap() = Arc::new(plugins);
+        self
+    }
+
+    pub fn with_config(self, config: &Config) -> Result<Self> {
+        *self.config.write().unwrap() = Some(Arc::new(config.redacted()?));
+        *self.token.write().unwrap() = config.api_token.clone();
+        Ok(self)
+    }
+
+    /// Require `token` on the mutating routes.
+    pub fn with_token(self, token: impl Into<String>) -> Self {
+        *self.token.write().unwrap() = Some(token.into());
+        self
+    }
+
+    /// Serve a freshly built registry and its config from now on.
+    pub fn update(&self, plugins: HashMap<String, SharedPlugin>, config: &Config) -> Result<()> {
+        let redacted = config.redacted()?;
+        *self.plugins.write().unwrap() = Arc::new(plugins);
+        *self.config.write().unwrap() = Some(Arc::new(redacted));
+        *self.token.write().unwrap() = config.api_token.clone();
+        Ok(())
+    }
+
+    fn plugins(&self) -> Registry {
+        self.plugins.read().unwrap().clone()
+    }
+}
+
+pub async fn start_api_server(stats: Arc<RwLock<Statistics>>, addr: SocketAddr) -> Result<()> {
+    serve(ApiState::new(stats), addr).await
+}
+
+pub async fn serve(state: ApiState, addr: SocketAddr) -> Result<()> {
+    // Routes that change state need the bearer token.
+    let mutating = Router::new()
+        .route("/plugins/:tag/reload", post(reload_plugin))
+        .route(
+            "/plugins/:tag/overrides/:name",
+            put(set_override).delete(remove_override),
+        )
+        .route("/txt/:name", post(add_txt).delete(remove_txt))
+        .route_layer(middleware::from_fn_with_state(state.clone(), require_token));
+    let app = Router::new()
+        .route("/stats", get(get_stats))
+        .route("/stats/top", get(get_stats_top))
+        .route("/metrics", get(get_metrics))
+        .route("/config", get(get_config))
+        .route("/cache/top", get(get_cache_top))
+        .route("/upstreams/health", get(get_upstream_health))
+        .route("/plugins/:tag/overrides", get(list_overrides))
+        .merge(mutating)
+        .with_state(state);
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], port));
     info!("API server listening on {}", addr);
 
     let listener = TcpListener::bind(addr).await?;
@@ -18,10 +104,430 @@ pub async fn start_api_server(stats: Arc<RwLock<Statistics>>, port: u16) -> Resu
     Ok(())
 }
 
-async fn get_stats(stats: Arc<RwLock<Statistics>>) -> Json<Statistics> {
+async fn get_config(State(state): State<ApiState>) -> Result<Json<serde_json::Value>, ApiError> {
+    let config = state.config.read().unwrap().clone();
+    config
+        .map(|config| Json(config.as_ref().clone()))
+        .ok_or_else(|| api_error(StatusCode::NOT_FOUND, "No config loaded".to_string()))
+}
+
+/// Parse the `n` query parameter, falling back to `default` when absent or invalid.
+fn limit_param(params: &HashMap<String, String>, default: usize) -> usize {
+    params
+        .get("n")
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(default)
+}
+
+async fn get_stats(State(state): State<ApiState>) -> Json<Statistics> {
     let data = {
-        let s = stats.read().unwrap();
+        let s = state.stats.read().unwrap();
         s.clone()
     };
     Json(data)
 }
+
+/// Request counters of a single domain, as listed by `/stats/top`.
+#[derive(Debug, serde::Serialize)]
+struct TopDomain {
+    domain: String,
+    count: usize,
+    cache_hits: usize,
+}
+
+/// The `n` most queried domains, busiest first.
+async fn get_stats_top(
+    State(state): State<ApiState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<Vec<TopDomain>> {
+    let n = limit_param(&params, 50);
+    let mut domains: Vec<TopDomain> = {
+        let stats = state.stats.read().unwrap();
+        stats
+            .domains
+            .iter()
+            .map(|(domain, s)| TopDomain {
+                domain: domain.clone(),
+                count: s.count,
+                cache_hits: s.cache_hits,
+            })
+            .collect()
+    };
+    let busiest_first =
+        |a: &TopDomain, b: &TopDomain| b.count.cmp(&a.count).then_with(|| a.domain.cmp(&b.domain));
+    if n < domains.len() {
+        domains.select_nth_unstable_by(n, busiest_first);
+        domains.truncate(n);
+    }
+    domains.sort_unstable_by(busiest_first);
+    Json(domains)
+}
+
+/// Statistics in the Prometheus text exposition format.
+async fn get_metrics(State(state): State<ApiState>) -> impl IntoResponse {
+    let body = render_metrics(&state.stats.read().unwrap());
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+fn render_metrics(stats: &Statistics) -> String {
+    use std::fmt::Write;
+
+    let mut out = String::new();
+
+    write_header(
+        &mut out,
+        "clean_dns_queries_total",
+        "counter",
+        "Queries received.",
+    );
+    let _ = writeln!(out, "clean_dns_queries_total {}", stats.total_queries);
+    write_header(
+        &mut out,
+        "clean_dns_cache_hits_total",
+        "counter",
+        "Queries answered from a cache.",
+    );
+    let _ = writeln!(out, "clean_dns_cache_hits_total {}", stats.total_cache_hits);
+    write_header(
+        &mut out,
+        "clean_dns_queries_per_second",
+        "gauge",
+        "Average query rate over the trailing window.",
+    );
+    for (window, rate) in stats.qps.rates() {
+        let _ = writeln!(
+            out,
+            "clean_dns_queries_per_second{{window=\"{}\"}} {}",
+            window, rate
+        );
+    }
+    write_header(
+        &mut out,
+        "clean_dns_queries_in_flight",
+        "gauge",
+        "Queries being handled right now.",
+    );
+    let _ = writeln!(out, "clean_dns_queries_in_flight {}", stats.in_flight.get());
+    write_header(
+        &mut out,
+        "clean_dns_malformed_packets_total",
+        "counter",
+        "Packets dropped because they were not a parseable query.",
+    );
+    let _ = writeln!(
+        out,
+        "clean_dns_malformed_packets_total {}",
+        stats.malformed_packets
+    );
+
+    write_header(
+        &mut out,
+        "clean_dns_responses_total",
+        "counter",
+        "Responses sent, by rcode.",
+    );
+    for (rcode, count) in &stats.responses {
+        let _ = writeln!(
+            out,
+            "clean_dns_responses_total{{rcode=\"{}\"}} {}",
+            escape_label(rcode),
+            count
+        );
+    }
+
+    write_header(
+        &mut out,
+        "clean_dns_upstream_queries_total",
+        "counter",
+        "Upstream exchanges, by protocol.",
+    );
+    for (protocol, s) in &stats.upstream_protocols {
+        let _ = writeln!(
+            out,
+            "clean_dns_upstream_queries_total{{protocol=\"{}\"}} {}",
+            escape_label(protocol),
+            s.queries
+        );
+    }
+    write_header(
+        &mut out,
+        "clean_dns_upstream_failures_total",
+        "counter",
+        "Failed upstream exchanges, by protocol.",
+    );
+    for (protocol, s) in &stats.upstream_protocols {
+        let _ = writeln!(
+            out,
+            "clean_dns_upstream_failures_total{{protocol=\"{}\"}} {}",
+            escape_label(protocol),
+            s.failures
+        );
+    }
+    write_header(
+        &mut out,
+        "clean_dns_upstream_latency_seconds",
+        "histogram",
+        "Upstream exchange latency, by protocol.",
+    );
+    for (protocol, s) in &stats.upstream_protocols {
+        write_histogram(
+            &mut out,
+            "clean_dns_upstream_latency_seconds",
+            &format!("protocol=\"{}\"", escape_label(protocol)),
+            &s.latency,
+        );
+    }
+
+    if !stats.plugin_timings.is_empty() {
+        write_header(
+            &mut out,
+            "clean_dns_plugin_duration_seconds",
+            "histogram",
+            "Plugin execution time, including nested plugins.",
+        );
+    }
+    for (tag, histogram) in &stats.plugin_timings {
+        write_histogram(
+            &mut out,
+            "clean_dns_plugin_duration_seconds",
+            &format!("tag=\"{}\"", escape_label(tag)),
+            histogram,
+        );
+    }
+    out
+}
+
+fn write_header(out: &mut String, name: &str, kind: &str, help: &str) {
+    use std::fmt::Write;
+
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+/// Write the `_bucket`, `_sum` and `_count` series of one labelled histogram.
+fn write_histogram(out: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    use std::fmt::Write;
+
+    let mut cumulative = 0;
+    for (i, bound) in TIMING_BUCKETS.iter().enumerate() {
+        cumulative += histogram.buckets.get(i).copied().unwrap_or_default();
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{},le=\"{}\"}} {}",
+            name, labels, bound, cumulative
+        );
+    }
+    let _ = writeln!(
+        out,
+        "{}_bucket{{{},le=\"+Inf\"}} {}",
+        name, labels, histogram.count
+    );
+    let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum_seconds);
+    let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Most-hit keys of every cache plugin, keyed by plugin tag.
+async fn get_cache_top(
+    State(state): State<ApiState>,
+    Query(params): Query<HashMap<String, String>>,
+) -> Json<BTreeMap<String, Vec<CacheKeyStats>>> {
+    let n = limit_param(&params, 20);
+    let mut caches = BTreeMap::new();
+    for (tag, plugin) in state.plugins().iter() {
+        if let Some(cache) = plugin.as_cache_store() {
+            caches.insert(tag.clone(), cache.top_keys(n));
+        }
+    }
+    Json(caches)
+}
+
+/// Health of every forwarding plugin's upstreams, keyed by plugin tag.
+async fn get_upstream_health(
+    State(state): State<ApiState>,
+) -> Json<BTreeMap<String, Vec<UpstreamHealth>>> {
+    let mut upstreams = BTreeMap::new();
+    for (tag, plugin) in state.plugins().iter() {
+        if let Some(reporter) = plugin.as_upstream_health() {
+            upstreams.insert(tag.clone(), reporter.upstream_health());
+        }
+    }
+    Json(upstreams)
+}
+
+/// Rebuild one plugin from its stored config and swap it in.
+async fn reload_plugin(
+    State(state): State<ApiState>,
+    Path(tag): Path<String>,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let plugins = state.plugins();
+    let Some(plugin) = plugins.get(&tag) else {
+        return (
+            StatusCode::NOT_FOUND,
+            Json(serde_json::json!({ "error": format!("Plugin not found: {}", tag) })),
+        );
+    };
+    if plugin.as_reloadable().is_none() {
+        return (
+            StatusCode::BAD_REQUEST,
+            Json(serde_json::json!({ "error": format!("Plugin {} cannot be reloaded", tag) })),
+        );
+    }
+    // Plugins may read files while rebuilding; keep that off the async workers.
+    let plugin = plugin.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        plugin
+            .as_reloadable()
+            .map_or(Ok(()), |reloadable| reloadable.reload(&plugins))
+    })
+    .await
+    .unwrap_or_else(|e| Err(e.into()));
+    match result {
+        Ok(()) => (
+            StatusCode::OK,
+            Json(serde_json::json!({ "tag": tag, "reloaded": true })),
+        ),
+        Err(e) => (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(serde_json::json!({ "error": format!("{:#}", e) })),
+        ),
+    }
+}
+
+type ApiError = (StatusCode, Json<serde_json::Value>);
+
+fn api_error(status: StatusCode, message: String) -> ApiError {
+    (status, Json(serde_json::json!({ "error": message })))
+}
+
+/// Let the request through only with `Authorization: Bearer <api_token>`.
+async fn require_token(
+    State(state): State<ApiState>,
+    request: Request,
+    next: Next,
+) -> Result<Response, ApiError> {
+    let Some(token) = state.token.read().unwrap().clone() else {
+        return Err(api_error(
+            StatusCode::FORBIDDEN,
+            "Set api_token to enable this route".to_string(),
+        ));
+    };
+    let presented = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    if presented != Some(token.as_str()) {
+        return Err(api_error(
+            StatusCode::UNAUTHORIZED,
+            "Missing or wrong bearer token".to_string(),
+        ));
+    }
+    Ok(next.run(request).await)
+}
+
+/// The plugin tagged `tag`, provided it keeps runtime overrides.
+fn override_plugin(state: &ApiState, tag: &str) -> Result<SharedPlugin, ApiError> {
+    let plugin =
+        state.plugins().get(tag).cloned().ok_or_else(|| {
+            api_error(StatusCode::NOT_FOUND, format!("Plugin not found: {}", tag))
+        })?;
+    if plugin.as_override_store().is_none() {
+        return Err(api_error(
+            StatusCode::BAD_REQUEST,
+            format!("Plugin {} does not hold overrides", tag),
+        ));
+    }
+    Ok(plugin)
+}
+
+async fn list_overrides(
+    State(state): State<ApiState>,
+    Path(tag): Path<String>,
+) -> Result<Json<BTreeMap<String, OverrideAction>>, ApiError> {
+    let plugin = override_plugin(&state, &tag)?;
+    let overrides = plugin
+        .as_override_store()
+        .map(|o| o.overrides())
+        .unwrap_or_default();
+    Ok(Json(overrides))
+}
+
+async fn set_override(
+    State(state): State<ApiState>,
+    Path((tag, name)): Path<(String, String)>,
+    Json(action): Json<OverrideAction>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let plugin = override_plugin(&state, &tag)?;
+    if let Some(store) = plugin.as_override_store() {
+        store.set_override(&name, action);
+    }
+    Ok(Json(serde_json::json!({ "tag": tag, "name": name })))
+}
+
+async fn remove_override(
+    State(state): State<ApiState>,
+    Path((tag, name)): Path<(String, String)>,
+) -> Result<StatusCode, ApiError> {
+    let plugin = override_plugin(&state, &tag)?;
+    if plugin
+        .as_override_store()
+        .is_some_and(|o| o.remove_override(&name))
+    {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(api_error(
+            StatusCode::NOT_FOUND,
+            format!("No override for {}", name),
+        ))
+    }
+}
+
+/// Add a TXT value to the first `txt_store` plugin (by tag) that takes `name`.
+async fn add_txt(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+    Json(value): Json<TxtValue>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let plugins = state.plugins();
+    let mut tags: Vec<&String> = plugins.keys().collect();
+    tags.sort();
+    for tag in tags {
+        if let Some(store) = plugins[tag].as_txt_store() {
+            if store.accepts(&name) {
+                store.add_txt(&name, value);
+                return Ok(Json(serde_json::json!({ "tag": tag, "name": name })));
+            }
+        }
+    }
+    Err(api_error(
+        StatusCode::NOT_FOUND,
+        format!("No TXT store takes {}", name),
+    ))
+}
+
+async fn remove_txt(
+    State(state): State<ApiState>,
+    Path(name): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    let mut removed = false;
+    for plugin in state.plugins().values() {
+        if let Some(store) = plugin.as_txt_store() {
+            removed |= store.remove_txt(&name);
+        }
+    }
+    if removed {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(api_error(
+            StatusCode::NOT_FOUND,
+            format!("No TXT records for {}", name),
+        ))
+    }
+}
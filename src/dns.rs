@@ -0,0 +1,301 @@
+//! Small helpers for building and fixing up DNS messages.
+//!
+//! Name handling invariant: internal keys (statistics, cache keys, domain lists)
+//! always use [`canonical_name`] — lowercase, no trailing dot — while messages on
+//! the wire keep exactly the form the client sent.
+
+use anyhow::{bail, Context, Result};
+use hickory_proto::op::{Edns, Message, MessageType, ResponseCode};
+use hickory_proto::rr::rdata::opt::{ClientSubnet, EdnsCode, EdnsOption};
+use ipnet::IpNet;
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Canonical form of a domain name used for internal keys: ASCII lowercase,
+/// without the trailing root dot. The root itself becomes the empty string.
+pub fn canonical_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Compile a `regex:` domain pattern, matched against canonical names.
+pub fn domain_regex(pattern: &str) -> Result<regex::Regex> {
+    regex::Regex::new(pattern).with_context(|| format!("Invalid domain regex: {}", pattern))
+}
+
+/// Whether `name` (canonical) equals `domain` (canonical) or is a subdomain of it.
+pub fn is_subdomain(name: &str, domain: &str) -> bool {
+    name == domain
+        || (name.len() > domain.len()
+            && name.ends_with(domain)
+            && name.as_bytes()[name.len() - domain.len() - 1] == b'.')
+}
+
+/// Mnemonic of an rcode as used in zone files and dig output, e.g. `NXDOMAIN`.
+pub fn rcode_name(rcode: ResponseCode) -> String {
+    match rcode {
+        ResponseCode::NoError => "NOERROR".to_string(),
+        ResponseCode::FormErr => "FORMERR".to_string(),
+        ResponseCode::ServFail => "SERVFAIL".to_string(),
+        ResponseCode::NXDomain => "NXDOMAIN".to_string(),
+        ResponseCode::NotImp => "NOTIMP".to_string(),
+        ResponseCode::Refused => "REFUSED".to_string(),
+        other => format!("RCODE{}", u16::from(other)),
+    }
+}
+
+/// Size of the fixed DNS message header.
+pub const HEADER_LEN: usize = 12;
+
+/// Cheap header check before a full parse: long enough for a header, flagged as
+/// a query (QR clear) and with at most one question.
+pub fn looks_like_query(buf: &[u8]) -> bool {
+    buf.len() >= HEADER_LEN && buf[2] & 0x80 == 0 && u16::from_be_bytes([buf[4], buf[5]]) <= 1
+}
+
+/// Build an empty response carrying `rcode` that echoes the request id and question.
+pub fn empty_response(request: &Message, rcode: ResponseCode) -> Message {
+    let mut response = Message::new();
+    response.set_header(*request.header());
+    response.set_message_type(MessageType::Response);
+    response.set_recursion_available(true);
+    response.set_response_code(rcode);
+    response.add_queries(request.queries().to_vec());
+    response
+}
+
+/// Make `response` echo the question exactly as the client sent it.
+///
+/// Names compare case-insensitively, so a cached or upstream response may carry a
+/// differently-cased question. Answer owner names matching the question are
+/// rewritten to the client's spelling as well.
+pub fn echo_question(request: &Message, response: &mut Message) {
+    response.take_queries();
+    response.add_queries(request.queries().to_vec());
+
+    let mut answers = response.take_answers();
+    for record in answers.iter_mut() {
+        if let Some(query) = request.queries().iter().find(|q| q.name() == record.name()) {
+            record.set_name(query.name().clone());
+        }
+    }
+    response.insert_answers(answers);
+}
+
+/// Largest UDP response `request`'s sender accepts: its EDNS payload size, or
+/// 512 bytes without EDNS (RFC 6891 section 6.2.5).
+pub fn udp_payload_limit(request: &Message) -> usize {
+    request
+        .extensions()
+        .as_ref()
+        .map_or(512, |edns| usize::from(edns.max_payload().max(512)))
+}
+
+/// `response` cut down to its header, question and EDNS record, with TC set so
+/// the client retries over TCP.
+pub fn truncated(response: &Message) -> Message {
+    let mut truncated = response.clone();
+    truncated.take_answers();
+    truncated.take_name_servers();
+    truncated.take_additionals();
+    truncated.set_truncated(true);
+    truncated
+}
+
+/// Drop the authority and additional records past the given counts; `None`
+/// leaves a section as it is. The EDNS OPT record is not counted.
+pub fn cap_sections(
+    response: &mut Message,
+    max_authority: Option<usize>,
+    max_additional: Option<usize>,
+) {
+    if let Some(max) = max_authority {
+        response.name_servers_mut().truncate(max);
+    }
+    if let Some(max) = max_additional {
+        response.additionals_mut().truncate(max);
+    }
+}
+
+/// Bytes of a hex string as written in zone files; whitespace is ignored.
+pub fn decode_hex(s: &str) -> Result<Vec<u8>> {
+    let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    // Slicing below works on bytes, so a multi-byte character would panic.
+    if !s.is_ascii() {
+        bail!("invalid hex digit");
+    }
+    if !s.len().is_multiple_of(2) {
+        bail!("odd number of hex digits");
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(Into::into))
+        .collect()
+}
+
+/// Longest source prefixes of EDNS Client Subnet options (RFC 7871) this server
+/// sends or passes on, per address family.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EcsPrefixes {
+    pub ipv4: u8,
+    pub ipv6: u8,
+}
+
+impl Default for EcsPrefixes {
+    fn default() -> Self {
+        Self { ipv4: 24, ipv6: 56 }
+    }
+}
+
+impl EcsPrefixes {
+    pub fn new(ipv4: u8, ipv6: u8) -> Result<Self> {
+        if ipv4 > 32 || ipv6 > 128 {
+            bail!("Invalid ECS prefix: ipv4 must be <= /32 and ipv6 <= /128");
+        }
+        Ok(Self { ipv4, ipv6 })
+    }
+
+    /// The subnet of `ip` at the prefix for its family; `None` for loopback,
+    /// private and link-local addresses, which mean nothing to an upstream.
+    pub fn subnet(&self, ip: IpAddr) -> Option<ClientSubnet> {
+        let local = match ip {
+            IpAddr::V4(v4) => {
+                v4.is_loopback() || v4.is_private() || v4.is_link_local() || v4.is_unspecified()
+            }
+            IpAddr::V6(v6) => {
+                v6.is_loopback()
+                    || v6.is_unique_local()
+                    || v6.is_unicast_link_local()
+                    || v6.is_unspecified()
+            }
+        };
+        if local {
+            return None;
+        }
+        self.shortened(ip, u8::MAX)
+    }
+
+    /// `subnet` shortened to the prefix for its family; never widened.
+    pub fn truncate(&self, subnet: &ClientSubnet) -> Option<ClientSubnet> {
+        let (addr, source_prefix) = subnet_parts(subnet)?;
+        self.shortened(addr, source_prefix)
+    }
+
+    fn shortened(&self, ip: IpAddr, prefix: u8) -> Option<ClientSubnet> {
+        let limit = match ip {
+            IpAddr::V4(_) => self.ipv4,
+            IpAddr::V6(_) => self.ipv6,
+        };
+        let net = IpNet::new(ip, prefix.min(limit)).ok()?;
+        Some(ClientSubnet::from(net.trunc()))
+    }
+}
+
+/// `ClientSubnet` keeps its fields private, so read them back from the wire form.
+fn subnet_parts(subnet: &ClientSubnet) -> Option<(IpAddr, u8)> {
+    let bytes = Vec::<u8>::try_from(subnet).ok()?;
+    let source_prefix = *bytes.get(2)?;
+    let addr = bytes.get(4..)?;
+    let addr = match u16::from_be_bytes([bytes[0], bytes[1]]) {
+        1 => {
+            let mut octets = [0u8; 4];
+            octets[..addr.len()].copy_from_slice(addr);
+            IpAddr::from(octets)
+        }
+        2 => {
+            let mut octets = [0u8; 16];
+            octets[..addr.len()].copy_from_slice(addr);
+            IpAddr::from(octets)
+        }
+        _ => return None,
+    };
+    Some((addr, source_prefix))
+}
+
+/// Whether the request carries the EDNS TCP keepalive option (RFC 7828).
+pub fn has_tcp_keepalive(request: &Message) -> bool {
+    request
+        .extensions()
+        .as_ref()
+        .is_some_and(|edns| edns.option(EdnsCode::Keepalive).is_some())
+}
+
+/// Advertise `timeout` in the response's EDNS TCP keepalive option.
+///
+/// The option carries the timeout in units of 100 milliseconds.
+pub fn set_tcp_keepalive(response: &mut Message, timeout: Duration) {
+    let units = (timeout.as_millis() / 100).min(u16::MAX as u128) as u16;
+    let edns = response.extensions_mut().get_or_insert_with(Edns::new);
+    edns.options_mut().insert(EdnsOption::Unknown(
+        u16::from(EdnsCode::Keepalive),
+        units.to_be_bytes().to_vec(),
+    ));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::cache::Cache;
+    use crate::plugins::matcher::Matcher;
+    use crate::plugins::{Condition, Context};
+    use crate::statistics::Statistics;
+    use hickory_proto::op::Query;
+    use hickory_proto::rr::{Name, RecordType};
+    use std::collections::HashMap;
+    use std::sync::{Arc, RwLock};
+
+    const FORMS: [&str; 4] = ["example.com", "example.com.", "Example.COM", "EXAMPLE.com."];
+
+    fn make_ctx(name: &str) -> Context {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        Context::new(
+            "127.0.0.1:1234".parse().unwrap(),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[test]
+    fn test_canonical_name() {
+        for form in FORMS {
+            assert_eq!(canonical_name(form), "example.com");
+        }
+        assert_eq!(canonical_name("."), "");
+        assert!(is_subdomain("www.example.com", "example.com"));
+        assert!(is_subdomain("example.com", "example.com"));
+        assert!(!is_subdomain("badexample.com", "example.com"));
+    }
+
+    #[test]
+    fn test_decode_hex() {
+        assert_eq!(decode_hex("0a FF\n10").unwrap(), [0x0a, 0xff, 0x10]);
+        assert!(decode_hex("abc").is_err());
+        assert!(decode_hex("zz").is_err());
+        assert!(decode_hex("éa").is_err());
+    }
+
+    #[test]
+    fn test_consistent_keys_across_components() {
+        let mut stats = Statistics::new();
+        let config: serde_yaml::Value = serde_yaml::from_str("size: 16").unwrap();
+        let cache = Cache::new(Some(&config), &HashMap::new()).unwrap();
+        let config: serde_yaml::Value = serde_yaml::from_str("domain: [\"Example.COM.\"]").unwrap();
+        let matcher = Matcher::new(Some(&config), &HashMap::new()).unwrap();
+
+        let cache_key = cache.get_key(&make_ctx(FORMS[0]).request);
+        for form in FORMS {
+            stats.record_request(form.to_string());
+            assert_eq!(
+                cache.get_key(&make_ctx(form).request),
+                cache_key,
+                "{}",
+                form
+            );
+            assert!(matcher.check(&make_ctx(form)), "{}", form);
+        }
+
+        assert_eq!(stats.domains.len(), 1);
+        assert_eq!(stats.domains["example.com"].count, FORMS.len());
+        assert_eq!(cache_key.as_deref(), Some("example.com-A-IN"));
+    }
+}
@@ -1,8 +1,11 @@
 pub mod api;
 pub mod config;
+pub mod dns;
+pub mod pipeline;
 pub mod plugins;
 pub mod server;
 pub mod statistics;
+pub mod transport;
 
 pub mod proto {
     include!(concat!(env!("OUT_DIR"), "/clean_dns.proto.rs"));
@@ -20,73 +23,345 @@ pub use statistics::Statistics;
 
 // Helper to initialize registry (logic moved from main)
 pub fn create_plugin_registry(config: &Config) -> anyhow::Result<HashMap<String, SharedPlugin>> {
+    use plugins::reloadable::Reloadable;
+
+    let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+
+    for plugin_conf in &config.plugins {
+        // info!("Loading plugin {} (type: {})", tag, type_); // Removed logging here, caller can log or we can pass logger?
+        // Actually, main initialized tracing, so we can log.
+        tracing::info!(
+            "Loading plugin {} (type: {})",
+            plugin_conf.tag,
+            plugin_conf.type_
+        );
+
+        check_chain(plugin_conf, config).map_err(|e| {
+            anyhow::anyhow!(
+                "plugin '{}' (tag: {}): {}",
+                plugin_conf.type_,
+                plugin_conf.tag,
+                e
+            )
+        })?;
+        let Some(plugin) = build_plugin(plugin_conf, &registry)? else {
+            tracing::warn!("Unknown plugin type: {}", plugin_conf.type_);
+            continue;
+        };
+        // Wrapped so the plugin can be rebuilt later without touching its dependents.
+        let plugin = Arc::new(Reloadable::new(plugin_conf.clone(), plugin));
+        registry.insert(plugin_conf.tag.clone(), plugin);
+    }
+    Ok(registry)
+}
+
+/// Move the live entries of each cache in `old` into the cache with the same
+/// tag in `new`, so a config reload doesn't start from a cold cache. Caches
+/// whose plugin config changed start empty, since their entries may no longer
+/// be what the new chain would answer.
+pub fn carry_over_caches(
+    old: &HashMap<String, SharedPlugin>,
+    old_config: &Config,
+    new: &HashMap<String, SharedPlugin>,
+    new_config: &Config,
+) {
+    for (tag, plugin) in new {
+        let (Some(to), Some(from)) = (
+            plugin.as_cache_store(),
+            old.get(tag).and_then(|p| p.as_cache_store()),
+        ) else {
+            continue;
+        };
+        let find = |config: &Config| config.plugins.iter().find(|p| p.tag == *tag).cloned();
+        if find(old_config) != find(new_config) {
+            tracing::info!("Cache {} changed; not carrying over its entries", tag);
+            continue;
+        }
+        let entries = from.export_entries();
+        tracing::info!("Carrying over {} entries of cache {}", entries.len(), tag);
+        to.import_entries(entries);
+    }
+}
+
+/// Arg keys holding lists of plugin tags run in order.
+const CHAIN_KEYS: &[&str] = &["exec", "else_exec"];
+
+/// Arg keys holding a single plugin tag.
+const REF_KEYS: &[&str] = &["if", "primary", "secondary", "trusted"];
+
+/// Reject plugins that reference themselves or whose `exec` lists exceed
+/// `max_chain_length`, warning above `warn_chain_length`.
+fn check_chain(plugin_conf: &config::PluginConfig, config: &Config) -> anyhow::Result<()> {
+    let Some(serde_yaml::Value::Mapping(args)) = &plugin_conf.args else {
+        return Ok(());
+    };
+    let is_self = |v: &serde_yaml::Value| v.as_str() == Some(plugin_conf.tag.as_str());
+
+    for key in REF_KEYS {
+        if args.get(*key).is_some_and(is_self) {
+            anyhow::bail!("'{}' references the plugin itself", key);
+        }
+    }
+    for key in CHAIN_KEYS {
+        let Some(serde_yaml::Value::Sequence(tags)) = args.get(*key) else {
+            continue;
+        };
+        if tags
+            .iter()
+            .any(|t| is_self(t) || t.get("goto").is_some_and(is_self))
+        {
+            anyhow::bail!("'{}' references the plugin itself", key);
+        }
+        if tags.len() > config.max_chain_length {
+            anyhow::bail!(
+                "'{}' lists {} plugins, more than max_chain_length ({})",
+                key,
+                tags.len(),
+                config.max_chain_length
+            );
+        }
+        if tags.len() > config.warn_chain_length {
+            tracing::warn!(
+                "Plugin {} runs {} plugins in '{}' (warn_chain_length is {})",
+                plugin_conf.tag,
+                tags.len(),
+                key,
+                config.warn_chain_length
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Construct a single plugin from its config, resolving references through `registry`.
+///
+/// Returns `None` for unknown plugin types. Errors name the offending plugin, e.g.
+/// `plugin 'cache' (tag: main_cache): missing required field 'size'`.
+pub fn build_plugin(
+    plugin_conf: &config::PluginConfig,
+    registry: &HashMap<String, SharedPlugin>,
+) -> anyhow::Result<Option<SharedPlugin>> {
+    construct_plugin(plugin_conf, registry).map_err(|e| {
+        let detail = match e.downcast_ref::<serde_yaml::Error>() {
+            // serde's wording refers to Rust fields; rephrase it for config authors.
+            Some(yaml) => yaml
+                .to_string()
+                .replace("missing field", "missing required field")
+                .replace('`', "'"),
+            None => format!("{:#}", e),
+        };
+        anyhow::anyhow!(
+            "plugin '{}' (tag: {}): {}",
+            plugin_conf.type_,
+            plugin_conf.tag,
+            detail
+        )
+    })
+}
+
+fn construct_plugin(
+    plugin_conf: &config::PluginConfig,
+    registry: &HashMap<String, SharedPlugin>,
+) -> anyhow::Result<Option<SharedPlugin>> {
+    use plugins::alias::Alias;
+    use plugins::allowlist::Allowlist;
     use plugins::cache::Cache;
+    use plugins::chaos::ChaosPlugin;
+    use plugins::cname_flatten::CnameFlatten;
+    use plugins::dedupe::Dedupe;
     use plugins::delay_plugin::DelayPlugin;
+    use plugins::dns64::Dns64;
+    use plugins::dnssec_ok::DnssecOk;
     use plugins::domain_set::DomainSetPlugin;
+    use plugins::drop::DropPlugin;
+    use plugins::ecs::EcsPlugin;
+    use plugins::edns_option::EdnsOptionCondition;
     use plugins::fallback::FallbackPlugin;
     use plugins::forward::Forward;
+    use plugins::geo_steer::GeoSteer;
+    use plugins::geoip::GeoipPlugin;
     use plugins::geosite::GeositePlugin;
     use plugins::hosts::Hosts;
     use plugins::if_plugin::IfPlugin;
+    use plugins::ip_pin::IpPin;
     use plugins::ip_set::IpSetPlugin;
     use plugins::matcher::Matcher;
+    use plugins::min_records::MinRecords;
+    use plugins::nodata_soa::NodataSoa;
+    use plugins::order::Order;
+    use plugins::override_plugin::OverridePlugin;
+    use plugins::rate_limit::RateLimit;
     use plugins::reject_plugin::RejectPlugin;
     use plugins::return_plugin::ReturnPlugin;
+    use plugins::root_hints::RootHints;
     use plugins::sequence::Sequence;
+    use plugins::sinkhole::Sinkhole;
+    use plugins::special_use::SpecialUse;
+    use plugins::static_response::StaticResponse;
     use plugins::system::System;
+    use plugins::trust_anchor::TrustAnchor;
     use plugins::ttl::TtlPlugin;
+    use plugins::txt_store::TxtStorePlugin;
+    use plugins::valid_tld::ValidTld;
+    use plugins::webhook::Webhook;
+    use plugins::weighted_answer::WeightedAnswer;
 
-    let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+    let args = plugin_conf.args.as_ref();
+    let plugin: SharedPlugin = match plugin_conf.type_.as_str() {
+        "forward" => {
+            let forward = Arc::new(Forward::new(args)?);
+            forward.spawn_health_check();
+            forward
+        }
+        "sequence" => Arc::new(Sequence::new(args, registry)?),
+        "matcher" => Arc::new(Matcher::new(args, registry)?),
+        "hosts" => Arc::new(Hosts::new(args)?),
+        "cache" => Arc::new(Cache::new(args, registry)?),
+        "domain_set" => Arc::new(DomainSetPlugin::new(args)?),
+        "ip_set" => Arc::new(IpSetPlugin::new(args)?),
+        "if" => Arc::new(IfPlugin::new(args, registry)?),
+        "return" => Arc::new(ReturnPlugin::new(args)?),
+        "reject" => Arc::new(RejectPlugin::new(args)?),
+        "drop" => Arc::new(DropPlugin::new(args)?),
+        "system" => Arc::new(System::new(args)?),
+        "delay" => Arc::new(DelayPlugin::new(args)?),
+        "fallback" => Arc::new(FallbackPlugin::new(args, registry)?),
+        "ttl" => Arc::new(TtlPlugin::new(args)?),
+        "order" => Arc::new(Order::new(args)?),
+        "geosite" => Arc::new(GeositePlugin::new(args)?),
+        "geoip" => Arc::new(GeoipPlugin::new(args)?),
+        "geo_steer" => Arc::new(GeoSteer::new(args)?),
+        "root_hints" => Arc::new(RootHints::new(args)?),
+        "trust_anchor" => Arc::new(TrustAnchor::new(args)?),
+        "ecs" => Arc::new(EcsPlugin::new(args)?),
+        "special_use" => Arc::new(SpecialUse::new(args)?),
+        "chaos" => Arc::new(ChaosPlugin::new(args)?),
+        "weighted_answer" => Arc::new(WeightedAnswer::new(args)?),
+        "cname_flatten" => Arc::new(CnameFlatten::new(args, registry)?),
+        "dns64" => Arc::new(Dns64::new(args, registry)?),
+        "dnssec_ok" => Arc::new(DnssecOk::new(args)?),
+        "valid_tld" => Arc::new(ValidTld::new(args)?),
+        "edns_option" => Arc::new(EdnsOptionCondition::new(args)?),
+        "override" => Arc::new(OverridePlugin::new(args)?),
+        "min_records" => Arc::new(MinRecords::new(args, registry)?),
+        "nodata_soa" => Arc::new(NodataSoa::new(args)?),
+        "static_response" => Arc::new(StaticResponse::new(args)?),
+        "dedupe" => Arc::new(Dedupe::new(args, registry)?),
+        "ip_pin" => Arc::new(IpPin::new(args, registry)?),
+        "webhook" => Arc::new(Webhook::new(args)?),
+        "sinkhole" => Arc::new(Sinkhole::new(args)?),
+        "allowlist" => Arc::new(Allowlist::new(args, registry)?),
+        "alias" => Arc::new(Alias::new(args, registry)?),
+        "txt_store" => Arc::new(TxtStorePlugin::new(args)?),
+        "rate_limit" => Arc::new(RateLimit::new(args)?),
+        _ => return Ok(None),
+    };
+    Ok(Some(plugin))
+}
 
-    for plugin_conf in &config.plugins {
-        let tag = plugin_conf.tag.clone();
-        let type_ = plugin_conf.type_.as_str();
+/// Entry plugins for each server, in the order of [`Config::all_listeners`].
+/// Listeners without an `entry` share the top-level one.
+pub fn listener_entries(
+    config: &Config,
+    registry: &HashMap<String, SharedPlugin>,
+) -> anyhow::Result<Vec<SharedPlugin>> {
+    let default = get_entry_plugin(config, registry)?;
+    config
+        .all_listeners()?
+        .iter()
+        .map(|listener| match &listener.entry {
+            Some(tag) => registry.get(tag).cloned().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Entry plugin '{}' for listener {} not found",
+                    tag,
+                    listener.bind
+                )
+            }),
+            None => Ok(default.clone()),
+        })
+        .collect()
+}
 
-        // info!("Loading plugin {} (type: {})", tag, type_); // Removed logging here, caller can log or we can pass logger?
-        // Actually, main initialized tracing, so we can log.
-        tracing::info!("Loading plugin {} (type: {})", tag, type_);
-
-        let plugin: SharedPlugin = match type_ {
-            "forward" => Arc::new(Forward::new(plugin_conf.args.as_ref())?),
-            "sequence" => Arc::new(Sequence::new(plugin_conf.args.as_ref(), &registry)?),
-            "matcher" => Arc::new(Matcher::new(plugin_conf.args.as_ref(), &registry)?),
-            "hosts" => Arc::new(Hosts::new(plugin_conf.args.as_ref())?),
-            "cache" => Arc::new(Cache::new(plugin_conf.args.as_ref(), &registry)?),
-            "domain_set" => Arc::new(DomainSetPlugin::new(plugin_conf.args.as_ref())?),
-            "ip_set" => Arc::new(IpSetPlugin::new(plugin_conf.args.as_ref())?),
-            "if" => Arc::new(IfPlugin::new(plugin_conf.args.as_ref(), &registry)?),
-            "return" => Arc::new(ReturnPlugin::new(plugin_conf.args.as_ref())?),
-            "reject" => Arc::new(RejectPlugin::new(plugin_conf.args.as_ref())?),
-            "system" => Arc::new(System::new(plugin_conf.args.as_ref())?),
-            "delay" => Arc::new(DelayPlugin::new(plugin_conf.args.as_ref())?),
-            "fallback" => Arc::new(FallbackPlugin::new(plugin_conf.args.as_ref(), &registry)?),
-            "ttl" => Arc::new(TtlPlugin::new(plugin_conf.args.as_ref())?),
-            "geosite" => Arc::new(GeositePlugin::new(plugin_conf.args.as_ref())?),
-            _ => {
-                tracing::warn!("Unknown plugin type: {}", type_);
-                continue;
+/// One server per listen address, in the order of [`listener_entries`], all
+/// recording into `statistics`.
+pub fn build_servers(
+    config: &Config,
+    registry: &HashMap<String, SharedPlugin>,
+    statistics: Arc<std::sync::RwLock<Statistics>>,
+) -> anyhow::Result<Vec<Server>> {
+    let entries = listener_entries(config, registry)?;
+    let options = server::ServerOptions::from_config(config);
+    config
+        .all_listeners()?
+        .iter()
+        .zip(entries)
+        .map(|(listener, entry)| {
+            let mut options = options.clone();
+            if let Some(protocols) = &listener.protocols {
+                options.protocols = protocols.clone();
             }
-        };
-        registry.insert(tag, plugin);
+            Ok(Server::new(listener.bind_addr()?, entry, statistics.clone()).with_options(options))
+        })
+        .collect()
+}
+
+/// Hand each running server the entry plugin from `entries` (in the order of
+/// [`listener_entries`]) of the listener with the same address and transports.
+/// Servers without such a listener keep their entry; listeners without a
+/// server are only logged, as both need a restart.
+pub fn update_entry_plugins(
+    servers: &[Server],
+    config: &Config,
+    entries: Vec<SharedPlugin>,
+) -> anyhow::Result<()> {
+    let mut by_listener = HashMap::new();
+    for (listener, entry) in config.all_listeners()?.iter().zip(entries) {
+        let protocols = listener.protocols.as_ref().unwrap_or(&config.protocols);
+        by_listener.insert((listener.bind_addr()?, protocols.clone()), entry);
     }
-    Ok(registry)
+    for server in servers {
+        match by_listener.remove(&(server.addr(), server.protocols().to_vec())) {
+            Some(entry) => server.set_entry_plugin(entry),
+            None => tracing::warn!(
+                "Listener {} was removed or changed; it keeps its entry plugin until restart",
+                server.addr()
+            ),
+        }
+    }
+    for (addr, _) in by_listener.keys() {
+        tracing::warn!("Listener {} is new or changed and needs a restart", addr);
+    }
+    Ok(())
 }
 
+/// Resolve the plugin that handles every query.
+///
+/// Without an explicit `entry`, falls back to `default_entry`, then `main`, then
+/// the last plugin in the config that was registered.
 pub fn get_entry_plugin(
     config: &Config,
     registry: &HashMap<String, SharedPlugin>,
 ) -> anyhow::Result<SharedPlugin> {
-    if config.entry.is_empty() {
-        tracing::warn!("No entry plugin specified, using 'main' or the last loaded one");
-        registry
-            .get("main")
-            .cloned()
-            .or_else(|| None)
-            .ok_or_else(|| anyhow::anyhow!("No entry plugin found"))
-    } else {
-        registry
+    if !config.entry.is_empty() {
+        return registry
             .get(&config.entry)
             .cloned()
-            .ok_or_else(|| anyhow::anyhow!("Entry plugin '{}' not found", config.entry))
+            .ok_or_else(|| anyhow::anyhow!("Entry plugin '{}' not found", config.entry));
+    }
+
+    let candidates = config
+        .default_entry
+        .iter()
+        .map(String::as_str)
+        .chain(["main"])
+        .chain(config.plugins.iter().rev().map(|p| p.tag.as_str()));
+    for tag in candidates {
+        if let Some(plugin) = registry.get(tag) {
+            tracing::warn!("No entry plugin specified, using '{}'", tag);
+            return Ok(plugin.clone());
+        }
     }
+    Err(anyhow::anyhow!(
+        "No entry plugin specified and no plugins are configured"
+    ))
 }
@@ -1,16 +1,19 @@
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
 use prost::Message;
-// use std::collections::HashMap;
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::{BufRead, BufReader, Write};
-use std::net::SocketAddr;
 use std::path::Path;
 use std::sync::{Arc, RwLock};
+use std::time::Duration;
 use tracing::{error, info};
 
 use clean_dns::proto;
-use clean_dns::{api, config, create_plugin_registry, get_entry_plugin, Server, Statistics};
+use clean_dns::{
+    api, build_servers, carry_over_caches, config, create_plugin_registry, listener_entries,
+    statistics, update_entry_plugins, SharedPlugin, Statistics,
+};
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -41,50 +44,168 @@ enum Commands {
     },
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    tracing_subscriber::fmt::init();
+// The runtime is built by hand so `runtime_threads` can be read from the
+// config before any worker threads exist.
+fn main() -> Result<()> {
+    // `RUST_LOG` picks levels per module (e.g. `clean_dns::server=info`); info by default.
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
     let args = Args::parse();
 
     match args.command {
         Some(Commands::MakeGeosite { source, output }) => {
-            make_geosite(source, output).await?;
-        }
-        Some(Commands::Run { config }) => {
-            run_server(config).await?;
+            tokio::runtime::Runtime::new()?.block_on(make_geosite(source, output))?
         }
+        Some(Commands::Run { config }) => start_server(config)?,
         None => {
             // Default behavior: run server with top-level config arg
-            run_server(args.config).await?;
+            start_server(args.config)?;
         }
     }
 
     Ok(())
 }
 
-async fn run_server(config_path: String) -> Result<()> {
+fn start_server(config_path: String) -> Result<()> {
     let config = config::Config::from_file(&config_path)?;
     info!("Loaded config from {}", config_path);
+    let runtime = config.build_runtime()?;
+    runtime.block_on(run_server(config, config_path))
+}
 
+async fn run_server(config: config::Config, config_path: String) -> Result<()> {
+    let config = Arc::new(config);
     let registry = create_plugin_registry(&config)?;
-    let entry_plugin = get_entry_plugin(&config, &registry)?;
 
-    let statistics = Arc::new(RwLock::new(Statistics::new()));
-    let api_port = config.api_port.unwrap_or(3000);
-    let stats_for_api = statistics.clone();
+    let statistics = match &config.stats_persist_path {
+        Some(path) => {
+            let stats = Statistics::load(path)?;
+            info!("Restored statistics for {} domains", stats.domains.len());
+            stats
+        }
+        None => Statistics::new(),
+    };
+    let statistics = Arc::new(RwLock::new(statistics));
+    if let Some(path) = &config.stats_persist_path {
+        statistics::spawn_persist(
+            statistics.clone(),
+            path.clone(),
+            Duration::from_secs(config.stats_persist_interval.max(1)),
+        );
+    }
+    let servers = build_servers(&config, &registry, statistics.clone())?;
+
+    let api_addr = config.api_addr();
+    #[cfg(unix)]
+    let mut running = (config.clone(), registry.clone());
+    let api_state = api::ApiState::new(statistics.clone())
+        .with_plugins(registry)
+        .with_config(&config)?;
+    #[cfg(unix)]
+    let reload_api_state = api_state.clone();
     tokio::spawn(async move {
-        if let Err(e) = api::start_api_server(stats_for_api, api_port).await {
+        if let Err(e) = api::serve(api_state, api_addr).await {
             error!("Failed to start API server: {}", e);
         }
     });
 
-    let bind_addr: SocketAddr = config.bind.parse().context("Invalid bind address")?;
-    let server = Server::new(bind_addr, entry_plugin, statistics);
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut hangups = signal(SignalKind::hangup())?;
+        let servers = servers.clone();
+        tokio::spawn(async move {
+            while hangups.recv().await.is_some() {
+                // Loading lists and geo files blocks, so keep it off the runtime.
+                let path = config_path.clone();
+                let (old_config, old) = running.clone();
+                let reloaded =
+                    tokio::task::spawn_blocking(move || reload(&path, &old_config, &old))
+                        .await
+                        .map_err(anyhow::Error::from)
+                        .and_then(|r| r);
+                match reloaded {
+                    Ok((config, registry, entries)) => {
+                        if let Err(e) = update_entry_plugins(&servers, &config, entries) {
+                            error!("Failed to update the listeners' entry plugins: {:#}", e);
+                        }
+                        if let Err(e) = reload_api_state.update(registry.clone(), &config) {
+                            error!("Reloaded plugins but failed to update the API: {:#}", e);
+                        }
+                        running = (Arc::new(config), registry);
+                        info!("Reloaded config from {}", config_path);
+                    }
+                    Err(e) => error!(
+                        "Config reload from {} failed, keeping the running config: {:#}",
+                        config_path, e
+                    ),
+                }
+            }
+        });
+    }
+
+    let stopping = servers.clone();
+    tokio::spawn(async move {
+        if let Err(e) = shutdown_signal().await {
+            // Keep serving; the process can still be killed outright.
+            error!("Failed to listen for shutdown signals: {}", e);
+            return;
+        }
+        info!("Shutting down");
+        for server in &stopping {
+            server.shutdown();
+        }
+    });
+    futures::future::try_join_all(servers.into_iter().map(|s| s.run())).await?;
+
+    if let Some(path) = &config.stats_persist_path {
+        statistics.read().unwrap().save(path)?;
+        info!("Saved statistics to {}", path.display());
+    }
+    Ok(())
+}
 
-    server.run().await?;
+/// Resolves on Ctrl-C or, on unix, SIGTERM.
+async fn shutdown_signal() -> Result<()> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result?,
+            _ = terminate.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await?;
     Ok(())
 }
 
+type Reloaded = (
+    config::Config,
+    HashMap<String, SharedPlugin>,
+    Vec<SharedPlugin>,
+);
+
+/// Build a new plugin chain from the config file, keeping the contents of
+/// unchanged caches from the `running` one, loaded from `running_config`. Only
+/// plugins are reloaded; listener and server options still need a restart.
+fn reload(
+    config_path: &str,
+    running_config: &config::Config,
+    running: &HashMap<String, SharedPlugin>,
+) -> Result<Reloaded> {
+    let config = config::Config::from_file(config_path)?;
+    let registry = create_plugin_registry(&config)?;
+    carry_over_caches(running, running_config, &registry, &config);
+    let entries = listener_entries(&config, &registry)?;
+    Ok((config, registry, entries))
+}
+
 async fn make_geosite(source: String, output: String) -> Result<()> {
     info!("Compiling geosite from {} to {}", source, output);
     let source_path = Path::new(&source);
@@ -157,7 +278,7 @@ fn load_domain_file(path: &Path, domains: &mut Vec<proto::Domain>) -> Result<()>
             load_domain_file(&included_path, domains)?;
             continue;
         } else if let Some(val) = line.strip_prefix("full:") {
-            (proto::domain::Type::Regex, val)
+            (proto::domain::Type::Full, val)
         } else if let Some(val) = line.strip_prefix("regexp:") {
             (proto::domain::Type::Regex, val)
         } else if let Some(val) = line.strip_prefix("domain:") {
@@ -183,3 +304,51 @@ fn load_domain_file(path: &Path, domains: &mut Vec<proto::Domain>) -> Result<()>
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clean_dns::plugins::geosite::GeositePlugin;
+    use clean_dns::plugins::DomainSet;
+
+    #[tokio::test]
+    async fn test_make_geosite_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("data");
+        std::fs::create_dir(&source).unwrap();
+        std::fs::write(
+            source.join("example"),
+            "# comment\nfull:exact.example.com\ndomain:suffix.example.org @ads\n\
+             regexp:^re[0-9]+\\.example\\.net$\ninclude:extra\n",
+        )
+        .unwrap();
+        std::fs::write(source.join("extra"), "plain.example.io\n").unwrap();
+        let output = dir.path().join("geosite.dat");
+        make_geosite(
+            source.to_string_lossy().to_string(),
+            output.to_string_lossy().to_string(),
+        )
+        .await
+        .unwrap();
+
+        let config = serde_yaml::from_str(&format!(
+            "file: {}\ncode: example",
+            output.to_string_lossy()
+        ))
+        .unwrap();
+        let plugin = GeositePlugin::new(Some(&config)).unwrap();
+        for (domain, expected) in [
+            ("exact.example.com", true),
+            ("www.exact.example.com", false),
+            ("suffix.example.org", true),
+            ("a.suffix.example.org", true),
+            ("re42.example.net", true),
+            ("rex.example.net", false),
+            ("plain.example.io", true),
+            ("www.plain.example.io", true),
+            ("other.example", false),
+        ] {
+            assert_eq!(plugin.contains(domain), expected, "{}", domain);
+        }
+    }
+}
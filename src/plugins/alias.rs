@@ -0,0 +1,201 @@
+use super::{exec, Context, Plugin, SharedPlugin};
+use crate::dns::{canonical_name, echo_question};
+use anyhow::{Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use hickory_proto::rr::rdata::CNAME;
+use hickory_proto::rr::{Name, RData, Record};
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::debug;
+
+#[derive(Deserialize)]
+struct AliasConfig {
+    /// Names answered as a CNAME to another name, keyed by the alias.
+    aliases: HashMap<String, String>,
+    /// Chain that resolves the target names (and unaliased queries).
+    exec: Vec<String>,
+    /// TTL of the synthesized CNAME; the server's `default_ttl` if unset.
+    #[serde(default)]
+    ttl: Option<u32>,
+}
+
+/// Aliases names at the resolver: a query for an alias is resolved as a query
+/// for its target, and the answer gets a CNAME from the alias to the target in
+/// front of the target's records.
+pub struct Alias {
+    aliases: HashMap<String, Name>,
+    ttl: Option<u32>,
+    plugins: Vec<SharedPlugin>,
+}
+
+impl Alias {
+    pub fn new(
+        config: Option<&serde_yaml::Value>,
+        registry: &HashMap<String, SharedPlugin>,
+    ) -> Result<Self> {
+        let config: AliasConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            return Err(anyhow::anyhow!("Alias requires config"));
+        };
+
+        let mut aliases = HashMap::new();
+        for (from, to) in config.aliases {
+            let mut target =
+                Name::from_ascii(&to).with_context(|| format!("Invalid alias target: {}", to))?;
+            target.set_fqdn(true);
+            aliases.insert(canonical_name(&from), target);
+        }
+
+        let mut plugins = Vec::new();
+        for tag in config.exec {
+            let p = registry
+                .get(&tag)
+                .ok_or_else(|| anyhow::anyhow!("Plugin not found: {}", tag))?;
+            plugins.push(p.clone());
+        }
+
+        Ok(Self {
+            aliases,
+            ttl: config.ttl,
+            plugins,
+        })
+    }
+
+    async fn resolve(&self, ctx: &mut Context) -> Result<()> {
+        for plugin in &self.plugins {
+            exec(plugin, ctx).await?;
+            if ctx.response.is_some() || ctx.abort {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Plugin for Alias {
+    fn name(&self) -> &str {
+        "alias"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        let target = ctx
+            .request
+            .query()
+            .and_then(|q| self.aliases.get(&canonical_name(&q.name().to_ascii())))
+            .cloned();
+        let Some(target) = target else {
+            return self.resolve(ctx).await;
+        };
+
+        let original = ctx.request.clone();
+        let alias = original.queries()[0].name().clone();
+        debug!("Resolving alias {} as {}", alias, target);
+        ctx.request.queries_mut()[0].set_name(target.clone());
+        let result = self.resolve(ctx).await;
+        ctx.request = original;
+        result?;
+
+        if let Some(response) = &mut ctx.response {
+            echo_question(&ctx.request, response);
+            let ttl = self.ttl.unwrap_or(ctx.default_ttl);
+            let mut answers = vec![Record::from_rdata(alias, ttl, RData::CNAME(CNAME(target)))];
+            answers.extend(response.take_answers());
+            response.insert_answers(answers);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::empty_response;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Message, Query, ResponseCode};
+    use hickory_proto::rr::RecordType;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::{Arc, RwLock};
+
+    /// Answers `new.example.com` only.
+    struct Target;
+
+    #[async_trait]
+    impl Plugin for Target {
+        fn name(&self) -> &str {
+            "target"
+        }
+
+        async fn next(&self, ctx: &mut Context) -> Result<()> {
+            let name = ctx.request.query().unwrap().name().clone();
+            if name.to_ascii() != "new.example.com." {
+                ctx.response = Some(empty_response(&ctx.request, ResponseCode::NXDomain));
+                return Ok(());
+            }
+            let mut response = empty_response(&ctx.request, ResponseCode::NoError);
+            response.add_answer(Record::from_rdata(
+                name,
+                300,
+                RData::A(Ipv4Addr::new(192, 0, 2, 80).into()),
+            ));
+            ctx.response = Some(response);
+            Ok(())
+        }
+    }
+
+    fn make_ctx(name: &str) -> Context {
+        let mut msg = Message::new();
+        msg.set_id(77);
+        msg.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_alias_round_trip() {
+        let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+        registry.insert("target".to_string(), Arc::new(Target));
+        let config: serde_yaml::Value = serde_yaml::from_str(
+            "aliases:\n  old.example.com: new.example.com\nexec: [target]\nttl: 60",
+        )
+        .unwrap();
+        let plugin = Alias::new(Some(&config), &registry).unwrap();
+
+        let mut ctx = make_ctx("Old.Example.com.");
+        plugin.next(&mut ctx).await.unwrap();
+        // The client's question is restored.
+        assert_eq!(
+            ctx.request.query().unwrap().name().to_ascii(),
+            "Old.Example.com."
+        );
+        let response = ctx.response.unwrap();
+        assert_eq!(response.id(), 77);
+        assert_eq!(response.queries(), ctx.request.queries());
+        let answers = response.answers();
+        assert_eq!(answers.len(), 2);
+        assert_eq!(answers[0].name().to_ascii(), "Old.Example.com.");
+        assert_eq!(answers[0].ttl(), 60);
+        assert_eq!(
+            answers[0].data(),
+            Some(&RData::CNAME(CNAME(
+                Name::from_ascii("new.example.com.").unwrap()
+            )))
+        );
+        assert_eq!(answers[1].name().to_ascii(), "new.example.com.");
+        assert_eq!(
+            answers[1].data(),
+            Some(&RData::A(Ipv4Addr::new(192, 0, 2, 80).into()))
+        );
+
+        // Other names are resolved as asked.
+        let mut ctx = make_ctx("other.example.com.");
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(response.answers().is_empty());
+    }
+}
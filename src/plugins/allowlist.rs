@@ -0,0 +1,182 @@
+use super::{Context, Plugin, SharedPlugin};
+use crate::dns::{canonical_name, empty_response, is_subdomain};
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::op::ResponseCode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::debug;
+
+/// Answer given to names outside the allowlist.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DenyRcode {
+    #[default]
+    Refused,
+    Nxdomain,
+}
+
+impl From<DenyRcode> for ResponseCode {
+    fn from(rcode: DenyRcode) -> Self {
+        match rcode {
+            DenyRcode::Refused => ResponseCode::Refused,
+            DenyRcode::Nxdomain => ResponseCode::NXDomain,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct AllowlistConfig {
+    /// Allowed domains (subdomains included) or `provider:<tag>` domain sets.
+    domains: Vec<String>,
+    #[serde(default)]
+    rcode: DenyRcode,
+}
+
+/// Deny-by-default policy: queries for names outside the allowlist are
+/// answered with `rcode` and end the chain; allowed ones pass on untouched.
+pub struct Allowlist {
+    domains: Vec<String>,
+    domain_providers: Vec<SharedPlugin>,
+    rcode: ResponseCode,
+}
+
+impl Allowlist {
+    pub fn new(
+        config: Option<&serde_yaml::Value>,
+        registry: &HashMap<String, SharedPlugin>,
+    ) -> Result<Self> {
+        let config: AllowlistConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            return Err(anyhow::anyhow!("Allowlist requires config"));
+        };
+
+        let mut domains = Vec::new();
+        let mut domain_providers = Vec::new();
+        for d in config.domains {
+            if let Some(tag) = d.strip_prefix("provider:") {
+                let p = registry
+                    .get(tag)
+                    .ok_or_else(|| anyhow::anyhow!("Provider plugin not found: {}", tag))?;
+                if p.as_domain_set().is_none() {
+                    return Err(anyhow::anyhow!("Plugin {} is not a DomainSet", tag));
+                }
+                domain_providers.push(p.clone());
+            } else {
+                domains.push(canonical_name(&d));
+            }
+        }
+
+        Ok(Self {
+            domains,
+            domain_providers,
+            rcode: config.rcode.into(),
+        })
+    }
+
+    fn allows(&self, name: &str) -> bool {
+        self.domains.iter().any(|d| is_subdomain(name, d))
+            || self
+                .domain_providers
+                .iter()
+                .any(|p| p.as_domain_set().is_some_and(|ds| ds.contains(name)))
+    }
+}
+
+#[async_trait]
+impl Plugin for Allowlist {
+    fn name(&self) -> &str {
+        "allowlist"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        let Some(query) = ctx.request.query() else {
+            return Ok(());
+        };
+        let name = canonical_name(&query.name().to_ascii());
+        if self.allows(&name) {
+            return Ok(());
+        }
+        debug!("{} is not allowlisted, answering {}", name, self.rcode);
+        ctx.response = Some(empty_response(&ctx.request, self.rcode));
+        ctx.abort = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::domain_set::DomainSetPlugin;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::{Name, RecordType};
+    use std::io::Write;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, RwLock};
+    use tempfile::NamedTempFile;
+
+    fn make_ctx(name: &str) -> Context {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_only_allowlisted_names_pass() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "corp.example").unwrap();
+        let config: serde_yaml::Value =
+            serde_yaml::from_str(&format!("files: [\"{}\"]", file.path().display())).unwrap();
+        let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+        registry.insert(
+            "corp".to_string(),
+            Arc::new(DomainSetPlugin::new(Some(&config)).unwrap()),
+        );
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("domains: [\"provider:corp\", updates.vendor.example]").unwrap();
+        let plugin = Allowlist::new(Some(&config), &registry).unwrap();
+
+        for name in [
+            "corp.example.",
+            "mail.corp.example.",
+            "Updates.Vendor.Example.",
+            "eu.updates.vendor.example.",
+        ] {
+            let mut ctx = make_ctx(name);
+            plugin.next(&mut ctx).await.unwrap();
+            assert!(ctx.response.is_none(), "{}", name);
+            assert!(!ctx.abort, "{}", name);
+        }
+
+        for name in ["example.com.", "vendor.example.", "notcorp.example."] {
+            let mut ctx = make_ctx(name);
+            plugin.next(&mut ctx).await.unwrap();
+            assert!(ctx.abort, "{}", name);
+            assert_eq!(
+                ctx.response.unwrap().response_code(),
+                ResponseCode::Refused,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nxdomain_rcode() {
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("domains: [corp.example]\nrcode: nxdomain").unwrap();
+        let plugin = Allowlist::new(Some(&config), &HashMap::new()).unwrap();
+        let mut ctx = make_ctx("example.com.");
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(
+            ctx.response.unwrap().response_code(),
+            ResponseCode::NXDomain
+        );
+    }
+}
@@ -1,28 +1,114 @@
-use super::{Context, Plugin, SharedPlugin};
+use super::{exec, CacheKeyStats, CacheSnapshotEntry, CacheStore, Context, Plugin, SharedPlugin};
 use anyhow::Result;
 use async_trait::async_trait;
-use hickory_proto::op::Message;
+use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::rr::{RData, Record};
+use lru::LruCache;
 use serde::Deserialize;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::num::NonZeroUsize;
 use std::sync::Mutex;
 use std::time::{Duration, Instant};
 use tracing::info;
 
+fn default_max_ttl() -> u32 {
+    3600
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_admit_after() -> u8 {
+    1
+}
+
+/// Longest time error responses such as SERVFAIL or REFUSED are kept, so an
+/// upstream outage is not served from cache for long (RFC 2308 section 7).
+const ERROR_TTL: u32 = 5;
+
 #[derive(Deserialize)]
 struct CacheConfig {
+    /// Maximum number of entries; the least recently used one is evicted.
     size: usize,
     #[serde(default)]
     exec: Vec<String>,
+    /// Lower bound applied to record TTLs when caching.
+    #[serde(default)]
+    min_ttl: u32,
+    /// Upper bound applied to record TTLs when caching.
+    #[serde(default = "default_max_ttl")]
+    max_ttl: u32,
+    /// Cache NXDOMAIN/NODATA answers for their SOA's negative TTL (RFC 2308).
+    #[serde(default = "default_true")]
+    cache_negative: bool,
+    /// Number of misses a key needs before its response is stored, so names
+    /// queried only once don't push out ones that are reused.
+    #[serde(default = "default_admit_after")]
+    admit_after: u8,
 }
 
 struct CacheEntry {
+    /// Response with record TTLs clamped to `min_ttl..=max_ttl` at `stored_at`.
     response: Message,
+    stored_at: Instant,
     valid_until: Instant,
+    hits: u64,
+}
+
+/// Count-min sketch of recent key frequencies (TinyLFU style): four rows of
+/// saturating counters, all halved every `reset_at` increments so that old
+/// popularity fades.
+struct FrequencySketch {
+    counters: Vec<u8>,
+    mask: usize,
+    additions: usize,
+    reset_at: usize,
+}
+
+impl FrequencySketch {
+    const ROWS: u64 = 4;
+
+    fn new(size: usize) -> Self {
+        let width = (size.max(1) * 4).next_power_of_two();
+        Self {
+            counters: vec![0; width * Self::ROWS as usize],
+            mask: width - 1,
+            additions: 0,
+            reset_at: size.max(1) * 10,
+        }
+    }
+
+    /// Count one more sighting of `key` and return its estimated frequency.
+    fn increment(&mut self, key: &str) -> u8 {
+        let width = self.mask + 1;
+        let mut estimate = u8::MAX;
+        for row in 0..Self::ROWS {
+            let mut hasher = DefaultHasher::new();
+            row.hash(&mut hasher);
+            key.hash(&mut hasher);
+            let idx = row as usize * width + (hasher.finish() as usize & self.mask);
+            self.counters[idx] = self.counters[idx].saturating_add(1);
+            estimate = estimate.min(self.counters[idx]);
+        }
+        self.additions += 1;
+        if self.additions >= self.reset_at {
+            self.counters.iter_mut().for_each(|c| *c /= 2);
+            self.additions = 0;
+        }
+        estimate
+    }
 }
 
 pub struct Cache {
-    cache: Mutex<HashMap<String, CacheEntry>>,
-    ttl: Duration,
+    cache: Mutex<LruCache<String, CacheEntry>>,
+    min_ttl: u32,
+    max_ttl: u32,
+    cache_negative: bool,
+    /// Admission filter; `None` when every response is cached (`admit_after: 1`).
+    admission: Option<(u8, Mutex<FrequencySketch>)>,
     plugins: Vec<SharedPlugin>,
 }
 
@@ -37,6 +123,10 @@ impl Cache {
             CacheConfig {
                 size: 1024,
                 exec: vec![],
+                min_ttl: 0,
+                max_ttl: default_max_ttl(),
+                cache_negative: true,
+                admit_after: default_admit_after(),
             }
         };
 
@@ -49,17 +139,77 @@ impl Cache {
         }
 
         Ok(Self {
-            cache: Mutex::new(HashMap::with_capacity(config.size)), // TODO: Real LRU
-            ttl: Duration::from_secs(60),                           // Default TTL cap
+            cache: Mutex::new(LruCache::new(
+                NonZeroUsize::new(config.size).unwrap_or(NonZeroUsize::MIN),
+            )),
+            min_ttl: config.min_ttl,
+            max_ttl: config.max_ttl.max(config.min_ttl),
+            cache_negative: config.cache_negative,
+            admission: (config.admit_after > 1).then(|| {
+                (
+                    config.admit_after,
+                    Mutex::new(FrequencySketch::new(config.size)),
+                )
+            }),
             plugins,
         })
     }
 
-    fn get_key(&self, request: &Message) -> Option<String> {
+    /// Record a miss for `key` and tell whether its response may be stored.
+    fn admit(&self, key: &str) -> bool {
+        match &self.admission {
+            Some((threshold, sketch)) => sketch.lock().unwrap().increment(key) >= *threshold,
+            None => true,
+        }
+    }
+
+    /// Prepare a response for storage: clamp its record TTLs and work out how
+    /// long it stays valid (the smallest answer TTL, or the negative TTL).
+    ///
+    /// Returns `None` for responses that must not be cached.
+    fn entry_for(&self, response: &Message) -> Option<CacheEntry> {
+        let mut response = response.clone();
+        for_each_record(&mut response, |record| {
+            record.set_ttl(record.ttl().clamp(self.min_ttl, self.max_ttl));
+        });
+        let negative = match response.response_code() {
+            ResponseCode::NXDomain => true,
+            ResponseCode::NoError => response.answers().is_empty(),
+            _ => false,
+        };
+        let ttl = if !matches!(
+            response.response_code(),
+            ResponseCode::NoError | ResponseCode::NXDomain
+        ) {
+            ERROR_TTL.min(self.max_ttl)
+        } else if negative {
+            if !self.cache_negative {
+                return None;
+            }
+            // Without an SOA the negative TTL is unknown, so don't cache (RFC 2308 section 5).
+            negative_ttl(&response)?.clamp(self.min_ttl, self.max_ttl)
+        } else {
+            response
+                .answers()
+                .iter()
+                .map(|r| r.ttl())
+                .min()
+                .unwrap_or(self.min_ttl)
+        };
+        let now = Instant::now();
+        Some(CacheEntry {
+            response,
+            stored_at: now,
+            valid_until: now + Duration::from_secs(ttl.into()),
+            hits: 0,
+        })
+    }
+
+    pub(crate) fn get_key(&self, request: &Message) -> Option<String> {
         if let Some(query) = request.query() {
             return Some(format!(
-                "{:?}-{:?}-{:?}",
-                query.name(),
+                "{}-{}-{}",
+                crate::dns::canonical_name(&query.name().to_string()),
                 query.query_type(),
                 query.query_class()
             ));
@@ -79,11 +229,20 @@ impl Plugin for Cache {
 
         if let Some(k) = &key {
             let mut cache = self.cache.lock().unwrap();
-            if let Some(entry) = cache.get(k) {
+            if let Some(entry) = cache.get_mut(k) {
                 if entry.valid_until > Instant::now() {
+                    entry.hits += 1;
                     let mut response = entry.response.clone();
+                    // Count down TTLs by the time spent in the cache.
+                    let elapsed = entry.stored_at.elapsed().as_secs();
+                    let elapsed = u32::try_from(elapsed).unwrap_or(u32::MAX);
+                    for_each_record(&mut response, |record| {
+                        record.set_ttl(record.ttl().saturating_sub(elapsed));
+                    });
                     response.set_id(ctx.request.id()); // Update ID to match request
+                    crate::dns::echo_question(&ctx.request, &mut response);
                     ctx.response = Some(response);
+                    ctx.cache_hit = true;
                     info!("Cache hit for {}", k);
                     {
                         let mut stats = ctx.stats.write().unwrap();
@@ -93,33 +252,106 @@ impl Plugin for Cache {
                     }
                     return Ok(());
                 } else {
-                    cache.remove(k);
+                    cache.pop(k);
                 }
             }
         }
 
         // Cache miss
         for plugin in &self.plugins {
-            plugin.next(ctx).await?;
-        }
-
-        // Cache response if available
-        if let Some(response) = &ctx.response {
-            if let Some(k) = key {
-                let mut cache = self.cache.lock().unwrap();
-                // Simple TTL logic: check first answer's TTL or default
-                // Keep it simple for now
-                cache.insert(
-                    k,
-                    CacheEntry {
-                        response: response.clone(),
-                        valid_until: Instant::now() + self.ttl,
-                    },
-                );
+            exec(plugin, ctx).await?;
+        }
+
+        // Cache response if available; TTL 0 answers must not be reused, nor
+        // answers tailored to the client's subnet.
+        if let Some(response) = ctx
+            .response
+            .as_ref()
+            .filter(|_| !ctx.ecs_scoped)
+            .filter(|r| !r.answers().iter().any(|a| a.ttl() == 0))
+        {
+            if let Some((k, entry)) = key.zip(self.entry_for(response)) {
+                if self.admit(&k) {
+                    self.cache.lock().unwrap().put(k, entry);
+                }
             }
         }
         Ok(())
     }
+
+    fn as_cache_store(&self) -> Option<&dyn CacheStore> {
+        Some(self)
+    }
+}
+
+/// Negative caching TTL: the smaller of the authority SOA's own TTL and its
+/// MINIMUM field (RFC 2308 section 5).
+fn negative_ttl(response: &Message) -> Option<u32> {
+    response
+        .name_servers()
+        .iter()
+        .find_map(|record| match record.data() {
+            Some(RData::SOA(soa)) => Some(record.ttl().min(soa.minimum())),
+            _ => None,
+        })
+}
+
+/// Apply `f` to the records of every section of `response`.
+fn for_each_record(response: &mut Message, mut f: impl FnMut(&mut Record)) {
+    response.answers_mut().iter_mut().for_each(&mut f);
+    response.name_servers_mut().iter_mut().for_each(&mut f);
+    response.additionals_mut().iter_mut().for_each(&mut f);
+}
+
+impl CacheStore for Cache {
+    fn top_keys(&self, n: usize) -> Vec<CacheKeyStats> {
+        let now = Instant::now();
+        let cache = self.cache.lock().unwrap();
+        let mut keys: Vec<CacheKeyStats> = cache
+            .iter()
+            .filter(|(_, entry)| entry.valid_until > now)
+            .map(|(key, entry)| CacheKeyStats {
+                key: key.clone(),
+                hits: entry.hits,
+                ttl_remaining: entry.valid_until.duration_since(now).as_secs(),
+            })
+            .collect();
+        keys.sort_by(|a, b| b.hits.cmp(&a.hits).then_with(|| a.key.cmp(&b.key)));
+        keys.truncate(n);
+        keys
+    }
+
+    fn export_entries(&self) -> Vec<CacheSnapshotEntry> {
+        let now = Instant::now();
+        let cache = self.cache.lock().unwrap();
+        cache
+            .iter()
+            .rev()
+            .filter(|(_, entry)| entry.valid_until > now)
+            .map(|(key, entry)| CacheSnapshotEntry {
+                key: key.clone(),
+                response: entry.response.clone(),
+                stored_at: entry.stored_at,
+                valid_until: entry.valid_until,
+                hits: entry.hits,
+            })
+            .collect()
+    }
+
+    fn import_entries(&self, entries: Vec<CacheSnapshotEntry>) {
+        let mut cache = self.cache.lock().unwrap();
+        for entry in entries {
+            cache.put(
+                entry.key,
+                CacheEntry {
+                    response: entry.response,
+                    stored_at: entry.stored_at,
+                    valid_until: entry.valid_until,
+                    hits: entry.hits,
+                },
+            );
+        }
+    }
 }
 
 #[cfg(test)]
@@ -151,8 +383,11 @@ mod tests {
     async fn test_cache_miss_hit() {
         // We need a dummy plugin registry for Cache::new if we used exec, but here exec is empty.
         let cache = Cache {
-            cache: Mutex::new(HashMap::new()),
-            ttl: Duration::from_secs(60),
+            cache: Mutex::new(LruCache::new(NonZeroUsize::new(16).unwrap())),
+            min_ttl: 0,
+            max_ttl: 3600,
+            cache_negative: true,
+            admission: None,
             plugins: vec![],
         };
 
@@ -183,11 +418,13 @@ mod tests {
 
         {
             let mut map = cache.cache.lock().unwrap();
-            map.insert(
+            map.put(
                 key,
                 CacheEntry {
                     response: response.clone(),
+                    stored_at: Instant::now(),
                     valid_until: Instant::now() + Duration::from_secs(100),
+                    hits: 0,
                 },
             );
         }
@@ -199,4 +436,307 @@ mod tests {
         assert!(ctx2.response.is_some());
         assert_eq!(ctx2.response.unwrap().answers().len(), 1);
     }
+
+    #[tokio::test]
+    async fn test_zero_ttl_not_cached() {
+        use hickory_proto::rr::{Name, RData, Record};
+        use std::str::FromStr;
+
+        struct ZeroTtl;
+
+        #[async_trait]
+        impl Plugin for ZeroTtl {
+            fn name(&self) -> &str {
+                "zero_ttl"
+            }
+
+            async fn next(&self, ctx: &mut Context) -> Result<()> {
+                let mut response = ctx.request.clone();
+                response.add_answer(Record::from_rdata(
+                    Name::from_str("dyn.example.com.").unwrap(),
+                    0,
+                    RData::A(Ipv4Addr::new(10, 0, 0, 1).into()),
+                ));
+                ctx.response = Some(response);
+                Ok(())
+            }
+        }
+
+        let cache = Cache {
+            cache: Mutex::new(LruCache::new(NonZeroUsize::new(16).unwrap())),
+            min_ttl: 0,
+            max_ttl: 3600,
+            cache_negative: true,
+            admission: None,
+            plugins: vec![Arc::new(ZeroTtl)],
+        };
+
+        let mut ctx = make_ctx("dyn.example.com.");
+        cache.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.is_some());
+        assert!(cache.cache.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_ttl_from_answers() {
+        use hickory_proto::rr::{Name, RData, Record};
+        use std::str::FromStr;
+
+        struct Upstream;
+
+        #[async_trait]
+        impl Plugin for Upstream {
+            fn name(&self) -> &str {
+                "upstream"
+            }
+
+            async fn next(&self, ctx: &mut Context) -> Result<()> {
+                let mut response = ctx.request.clone();
+                for (ttl, last) in [(300, 4), (5000, 5)] {
+                    response.add_answer(Record::from_rdata(
+                        Name::from_str("example.com.").unwrap(),
+                        ttl,
+                        RData::A(Ipv4Addr::new(1, 2, 3, last).into()),
+                    ));
+                }
+                ctx.response = Some(response);
+                Ok(())
+            }
+        }
+
+        let registry: HashMap<String, SharedPlugin> =
+            HashMap::from([("upstream".to_string(), Arc::new(Upstream) as SharedPlugin)]);
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("size: 16\nexec: [upstream]\nmax_ttl: 1000").unwrap();
+        let cache = Cache::new(Some(&config), &registry).unwrap();
+
+        let mut ctx = make_ctx("example.com.");
+        cache.next(&mut ctx).await.unwrap();
+
+        // Valid for the smallest answer TTL, with TTLs above max_ttl clamped.
+        let key = cache.get_key(&ctx.request).unwrap();
+        {
+            let mut map = cache.cache.lock().unwrap();
+            let entry = map.get_mut(&key).unwrap();
+            let lifetime = entry.valid_until - entry.stored_at;
+            assert_eq!(lifetime, Duration::from_secs(300));
+            let ttls: Vec<u32> = entry.response.answers().iter().map(|r| r.ttl()).collect();
+            assert_eq!(ttls, vec![300, 1000]);
+
+            // Pretend the entry was stored 100 seconds ago.
+            entry.stored_at -= Duration::from_secs(100);
+            entry.valid_until -= Duration::from_secs(100);
+        }
+
+        let mut ctx = make_ctx("example.com.");
+        cache.next(&mut ctx).await.unwrap();
+        assert!(ctx.cache_hit);
+        let ttls: Vec<u32> = ctx
+            .response
+            .unwrap()
+            .answers()
+            .iter()
+            .map(|r| r.ttl())
+            .collect();
+        assert_eq!(ttls, vec![200, 900]);
+    }
+
+    #[tokio::test]
+    async fn test_lru_eviction() {
+        use hickory_proto::rr::{RData, Record};
+
+        struct Upstream;
+
+        #[async_trait]
+        impl Plugin for Upstream {
+            fn name(&self) -> &str {
+                "upstream"
+            }
+
+            async fn next(&self, ctx: &mut Context) -> Result<()> {
+                let mut response = ctx.request.clone();
+                let name = ctx.request.query().unwrap().name().clone();
+                response.add_answer(Record::from_rdata(
+                    name,
+                    300,
+                    RData::A(Ipv4Addr::new(1, 2, 3, 4).into()),
+                ));
+                ctx.response = Some(response);
+                Ok(())
+            }
+        }
+
+        let registry: HashMap<String, SharedPlugin> =
+            HashMap::from([("upstream".to_string(), Arc::new(Upstream) as SharedPlugin)]);
+        let config: serde_yaml::Value = serde_yaml::from_str("size: 3\nexec: [upstream]").unwrap();
+        let cache = Cache::new(Some(&config), &registry).unwrap();
+
+        let key = |name| cache.get_key(&make_ctx(name).request).unwrap();
+        for name in ["a.example.", "b.example.", "c.example.", "d.example."] {
+            cache.next(&mut make_ctx(name)).await.unwrap();
+        }
+        {
+            let map = cache.cache.lock().unwrap();
+            assert_eq!(map.len(), 3);
+            assert!(!map.contains(&key("a.example.")));
+        }
+
+        // A hit makes `b` the most recently used, so `c` goes next.
+        let mut ctx = make_ctx("b.example.");
+        cache.next(&mut ctx).await.unwrap();
+        assert!(ctx.cache_hit);
+        cache.next(&mut make_ctx("e.example.")).await.unwrap();
+
+        let map = cache.cache.lock().unwrap();
+        assert!(!map.contains(&key("c.example.")));
+        for name in ["b.example.", "d.example.", "e.example."] {
+            assert!(map.contains(&key(name)));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admission_threshold() {
+        use hickory_proto::rr::{RData, Record};
+
+        struct Upstream;
+
+        #[async_trait]
+        impl Plugin for Upstream {
+            fn name(&self) -> &str {
+                "upstream"
+            }
+
+            async fn next(&self, ctx: &mut Context) -> Result<()> {
+                let mut response = ctx.request.clone();
+                let name = ctx.request.query().unwrap().name().clone();
+                response.add_answer(Record::from_rdata(
+                    name,
+                    300,
+                    RData::A(Ipv4Addr::new(1, 2, 3, 4).into()),
+                ));
+                ctx.response = Some(response);
+                Ok(())
+            }
+        }
+
+        let registry: HashMap<String, SharedPlugin> =
+            HashMap::from([("upstream".to_string(), Arc::new(Upstream) as SharedPlugin)]);
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("size: 16\nadmit_after: 2\nexec: [upstream]").unwrap();
+        let cache = Cache::new(Some(&config), &registry).unwrap();
+        let key = |name| cache.get_key(&make_ctx(name).request).unwrap();
+
+        cache.next(&mut make_ctx("once.example.")).await.unwrap();
+        for _ in 0..2 {
+            let mut ctx = make_ctx("twice.example.");
+            cache.next(&mut ctx).await.unwrap();
+            assert!(!ctx.cache_hit);
+        }
+        {
+            let map = cache.cache.lock().unwrap();
+            assert!(!map.contains(&key("once.example.")));
+            assert!(map.contains(&key("twice.example.")));
+        }
+        let mut ctx = make_ctx("twice.example.");
+        cache.next(&mut ctx).await.unwrap();
+        assert!(ctx.cache_hit);
+    }
+
+    #[tokio::test]
+    async fn test_negative_caching() {
+        use hickory_proto::rr::rdata::SOA;
+        use hickory_proto::rr::Name;
+        use std::str::FromStr;
+
+        /// Answers NXDOMAIN, with an SOA in the authority section unless the
+        /// name starts with `nosoa`.
+        struct Nxdomain;
+
+        #[async_trait]
+        impl Plugin for Nxdomain {
+            fn name(&self) -> &str {
+                "nxdomain"
+            }
+
+            async fn next(&self, ctx: &mut Context) -> Result<()> {
+                let mut response = crate::dns::empty_response(&ctx.request, ResponseCode::NXDomain);
+                let name = ctx.request.query().unwrap().name().to_string();
+                if !name.starts_with("nosoa") {
+                    let zone = Name::from_str("example.").unwrap();
+                    let soa = SOA::new(
+                        Name::from_str("ns.example.").unwrap(),
+                        Name::from_str("hostmaster.example.").unwrap(),
+                        1,
+                        3600,
+                        600,
+                        86400,
+                        120,
+                    );
+                    response.add_name_server(Record::from_rdata(zone, 3600, RData::SOA(soa)));
+                }
+                ctx.response = Some(response);
+                Ok(())
+            }
+        }
+
+        let registry: HashMap<String, SharedPlugin> =
+            HashMap::from([("nxdomain".to_string(), Arc::new(Nxdomain) as SharedPlugin)]);
+        let cache = |extra: &str| {
+            let config: serde_yaml::Value =
+                serde_yaml::from_str(&format!("size: 16\nexec: [nxdomain]\n{}", extra)).unwrap();
+            Cache::new(Some(&config), &registry).unwrap()
+        };
+
+        // Cached for the SOA MINIMUM, the smaller of it and the SOA's TTL.
+        let enabled = cache("");
+        let mut ctx = make_ctx("missing.example.");
+        enabled.next(&mut ctx).await.unwrap();
+        let key = enabled.get_key(&ctx.request).unwrap();
+        {
+            let mut map = enabled.cache.lock().unwrap();
+            let entry = map.get_mut(&key).unwrap();
+            assert_eq!(
+                entry.valid_until - entry.stored_at,
+                Duration::from_secs(120)
+            );
+        }
+        let mut ctx = make_ctx("missing.example.");
+        enabled.next(&mut ctx).await.unwrap();
+        assert!(ctx.cache_hit);
+        assert_eq!(
+            ctx.response.unwrap().response_code(),
+            ResponseCode::NXDomain
+        );
+
+        // Without an SOA the negative TTL is unknown.
+        enabled.next(&mut make_ctx("nosoa.example.")).await.unwrap();
+        assert_eq!(enabled.cache.lock().unwrap().len(), 1);
+
+        let disabled = cache("cache_negative: false");
+        disabled
+            .next(&mut make_ctx("missing.example."))
+            .await
+            .unwrap();
+        assert!(disabled.cache.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_error_responses_short_lived() {
+        let config: serde_yaml::Value = serde_yaml::from_str(
+            "size: 16
+min_ttl: 300",
+        )
+        .unwrap();
+        let cache = Cache::new(Some(&config), &HashMap::new()).unwrap();
+        for rcode in [ResponseCode::ServFail, ResponseCode::Refused] {
+            let response = crate::dns::empty_response(&make_ctx("example.com.").request, rcode);
+            let entry = cache.entry_for(&response).unwrap();
+            assert_eq!(
+                entry.valid_until - entry.stored_at,
+                Duration::from_secs(ERROR_TTL.into()),
+                "{}",
+                rcode
+            );
+        }
+    }
 }
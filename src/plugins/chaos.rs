@@ -0,0 +1,144 @@
+use super::{Context, Plugin};
+use crate::dns;
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::op::ResponseCode;
+use rand::Rng;
+use serde::Deserialize;
+use tracing::debug;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+enum ChaosMode {
+    /// Send no response at all, as if the packet was lost.
+    #[default]
+    Drop,
+    /// Answer with SERVFAIL.
+    Servfail,
+}
+
+#[derive(Deserialize)]
+struct ChaosConfig {
+    /// Percentage (0-100) of queries to disrupt.
+    #[serde(default)]
+    percent: f64,
+    #[serde(default)]
+    mode: ChaosMode,
+}
+
+/// Randomly drops or fails a share of queries, for testing client retries and
+/// fallback chains under loss. Disrupts nothing unless `percent` is set.
+pub struct ChaosPlugin {
+    probability: f64,
+    mode: ChaosMode,
+}
+
+impl ChaosPlugin {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: ChaosConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            ChaosConfig {
+                percent: 0.0,
+                mode: ChaosMode::default(),
+            }
+        };
+
+        if !(0.0..=100.0).contains(&config.percent) {
+            return Err(anyhow::anyhow!(
+                "chaos percent must be between 0 and 100, got {}",
+                config.percent
+            ));
+        }
+
+        Ok(Self {
+            probability: config.percent / 100.0,
+            mode: config.mode,
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for ChaosPlugin {
+    fn name(&self) -> &str {
+        "chaos"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        if self.probability <= 0.0 || !rand::thread_rng().gen_bool(self.probability) {
+            return Ok(());
+        }
+
+        match self.mode {
+            ChaosMode::Drop => {
+                debug!("Chaos: dropping query from {}", ctx.client_addr);
+                ctx.response = None;
+                ctx.dropped = true;
+            }
+            ChaosMode::Servfail => {
+                debug!("Chaos: failing query from {}", ctx.client_addr);
+                ctx.response = Some(dns::empty_response(&ctx.request, ResponseCode::ServFail));
+            }
+        }
+        ctx.abort = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::Message;
+    use std::sync::{Arc, RwLock};
+
+    fn make_ctx() -> Context {
+        use crate::statistics::Statistics;
+        use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+        Context::new(
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 1234),
+            Message::new(),
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_drop_rate() {
+        let config: serde_yaml::Value = serde_yaml::from_str("percent: 30").unwrap();
+        let plugin = ChaosPlugin::new(Some(&config)).unwrap();
+
+        let runs = 10_000;
+        let mut dropped = 0;
+        for _ in 0..runs {
+            let mut ctx = make_ctx();
+            plugin.next(&mut ctx).await.unwrap();
+            if ctx.abort {
+                assert!(ctx.response.is_none());
+                assert!(ctx.dropped);
+                dropped += 1;
+            }
+        }
+        // Expected 3000 with a standard deviation of ~46.
+        assert!((2700..3300).contains(&dropped), "dropped {}", dropped);
+    }
+
+    #[tokio::test]
+    async fn test_servfail_and_default_off() {
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("percent: 100\nmode: servfail").unwrap();
+        let plugin = ChaosPlugin::new(Some(&config)).unwrap();
+        let mut ctx = make_ctx();
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(
+            ctx.response.unwrap().response_code(),
+            ResponseCode::ServFail
+        );
+
+        let plugin = ChaosPlugin::new(None).unwrap();
+        for _ in 0..100 {
+            let mut ctx = make_ctx();
+            plugin.next(&mut ctx).await.unwrap();
+            assert!(!ctx.abort);
+        }
+    }
+}
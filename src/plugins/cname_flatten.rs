@@ -0,0 +1,258 @@
+use super::{exec, Context, Plugin, SharedPlugin};
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::op::{Message, Query};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::debug;
+
+fn default_max_hops() -> usize {
+    8
+}
+
+#[derive(Deserialize)]
+struct CnameFlattenConfig {
+    /// Chain that resolves the query and any CNAME targets missing from the answer.
+    exec: Vec<String>,
+    /// Maximum number of CNAMEs followed before giving up.
+    #[serde(default = "default_max_hops")]
+    max_hops: usize,
+}
+
+/// Replaces a CNAME chain in A/AAAA answers with the final addresses, owned by
+/// the queried name.
+pub struct CnameFlatten {
+    plugins: Vec<SharedPlugin>,
+    max_hops: usize,
+}
+
+impl CnameFlatten {
+    pub fn new(
+        config: Option<&serde_yaml::Value>,
+        registry: &HashMap<String, SharedPlugin>,
+    ) -> Result<Self> {
+        let config: CnameFlattenConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            return Err(anyhow::anyhow!("CnameFlatten requires config"));
+        };
+
+        let mut plugins = Vec::new();
+        for tag in config.exec {
+            let p = registry
+                .get(&tag)
+                .ok_or_else(|| anyhow::anyhow!("Plugin not found: {}", tag))?;
+            plugins.push(p.clone());
+        }
+
+        Ok(Self {
+            plugins,
+            max_hops: config.max_hops,
+        })
+    }
+
+    async fn resolve(&self, ctx: &mut Context) -> Result<()> {
+        for plugin in &self.plugins {
+            exec(plugin, ctx).await?;
+            if ctx.response.is_some() || ctx.abort {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Resolve `name` through the inner chain in a separate context.
+    async fn lookup(
+        &self,
+        ctx: &mut Context,
+        name: &Name,
+        qtype: RecordType,
+    ) -> Result<Vec<Record>> {
+        let mut request = Message::new();
+        request.set_id(ctx.request.id());
+        request.set_recursion_desired(true);
+        request.add_query(Query::query(name.clone(), qtype));
+
+        let mut sub = ctx.clone();
+        sub.request = request;
+        sub.response = None;
+        sub.abort = false;
+        self.resolve(&mut sub).await?;
+        ctx.ecs_scoped |= sub.ecs_scoped;
+        Ok(sub
+            .response
+            .map(|r| r.answers().to_vec())
+            .unwrap_or_default())
+    }
+}
+
+/// Target of the CNAME owned by `name`, if any.
+fn cname_target(records: &[Record], name: &Name) -> Option<(Name, u32)> {
+    records.iter().find_map(|r| match r.data() {
+        Some(RData::CNAME(target)) if r.name() == name => Some((target.0.clone(), r.ttl())),
+        _ => None,
+    })
+}
+
+#[async_trait]
+impl Plugin for CnameFlatten {
+    fn name(&self) -> &str {
+        "cname_flatten"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        self.resolve(ctx).await?;
+
+        let Some(query) = ctx.request.query().cloned() else {
+            return Ok(());
+        };
+        let qtype = query.query_type();
+        if !matches!(qtype, RecordType::A | RecordType::AAAA) {
+            return Ok(());
+        }
+        let Some(response) = &ctx.response else {
+            return Ok(());
+        };
+        let mut records = response.answers().to_vec();
+        if cname_target(&records, query.name()).is_none() {
+            return Ok(());
+        }
+
+        let mut name = query.name().clone();
+        let mut ttl = u32::MAX;
+        let mut hops = 0;
+        while let Some((target, cname_ttl)) = cname_target(&records, &name) {
+            hops += 1;
+            if hops > self.max_hops {
+                debug!("CNAME chain for {} too long, not flattening", query.name());
+                return Ok(());
+            }
+            ttl = ttl.min(cname_ttl);
+            name = target;
+            let resolved = records
+                .iter()
+                .any(|r| r.name() == &name && r.record_type() == qtype);
+            if !resolved && cname_target(&records, &name).is_none() {
+                // The chain leaves the answer; continue it through the inner chain.
+                records = self.lookup(ctx, &name, qtype).await?;
+            }
+        }
+
+        let answers: Vec<Record> = records
+            .into_iter()
+            .filter(|r| r.name() == &name && r.record_type() == qtype)
+            .map(|mut r| {
+                let record_ttl = r.ttl().min(ttl);
+                r.set_name(query.name().clone()).set_ttl(record_ttl);
+                r
+            })
+            .collect();
+        debug!(
+            "Flattened {} via {} CNAME(s) into {} record(s)",
+            query.name(),
+            hops,
+            answers.len()
+        );
+
+        if let Some(response) = &mut ctx.response {
+            response.take_answers();
+            response.insert_answers(answers);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use hickory_proto::rr::rdata::CNAME;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::str::FromStr;
+    use std::sync::{Arc, RwLock};
+
+    fn name(s: &str) -> Name {
+        Name::from_str(s).unwrap()
+    }
+
+    fn cname(owner: &str, target: &str, ttl: u32) -> Record {
+        Record::from_rdata(name(owner), ttl, RData::CNAME(CNAME(name(target))))
+    }
+
+    fn a(owner: &str, ip: [u8; 4], ttl: u32) -> Record {
+        Record::from_rdata(name(owner), ttl, RData::A(Ipv4Addr::from(ip).into()))
+    }
+
+    /// Answers `www.example.com` with a partial chain and resolves its end separately.
+    struct Upstream;
+
+    #[async_trait]
+    impl Plugin for Upstream {
+        fn name(&self) -> &str {
+            "upstream"
+        }
+
+        async fn next(&self, ctx: &mut Context) -> Result<()> {
+            let qname = ctx.request.query().unwrap().name().to_string();
+            let mut response = crate::dns::empty_response(&ctx.request, Default::default());
+            match qname.as_str() {
+                "www.example.com." => {
+                    response.add_answer(cname("www.example.com.", "a.cdn.net.", 300));
+                    response.add_answer(cname("a.cdn.net.", "b.cdn.net.", 120));
+                }
+                "b.cdn.net." => {
+                    response.add_answer(a("b.cdn.net.", [1, 2, 3, 4], 60));
+                    response.add_answer(a("b.cdn.net.", [1, 2, 3, 5], 600));
+                }
+                "direct.example.com." => {
+                    response.add_answer(cname("direct.example.com.", "c.cdn.net.", 30));
+                    response.add_answer(a("c.cdn.net.", [5, 6, 7, 8], 60));
+                }
+                _ => {}
+            }
+            ctx.response = Some(response);
+            Ok(())
+        }
+    }
+
+    fn make_ctx(qname: &str) -> Context {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(name(qname), RecordType::A));
+        Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    fn make_plugin() -> CnameFlatten {
+        let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+        registry.insert("upstream".to_string(), Arc::new(Upstream));
+        let config: serde_yaml::Value = serde_yaml::from_str("exec: [upstream]").unwrap();
+        CnameFlatten::new(Some(&config), &registry).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_flatten_cname_chain() {
+        let plugin = make_plugin();
+
+        let mut ctx = make_ctx("www.example.com.");
+        plugin.next(&mut ctx).await.unwrap();
+        let answers = ctx.response.unwrap().answers().to_vec();
+        assert_eq!(answers.len(), 2);
+        for record in &answers {
+            assert_eq!(record.name(), &name("www.example.com."));
+            assert_eq!(record.record_type(), RecordType::A);
+        }
+        // TTLs are capped by the shortest link in the chain.
+        assert_eq!(answers[0].ttl(), 60);
+        assert_eq!(answers[1].ttl(), 120);
+
+        // A chain fully present in the answer needs no extra lookup.
+        let mut ctx = make_ctx("direct.example.com.");
+        plugin.next(&mut ctx).await.unwrap();
+        let answers = ctx.response.unwrap().answers().to_vec();
+        assert_eq!(answers, vec![a("direct.example.com.", [5, 6, 7, 8], 30)]);
+    }
+}
@@ -0,0 +1,308 @@
+use super::{exec, Context, Plugin, SharedPlugin};
+use crate::dns::{canonical_name, echo_question};
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::op::Message;
+use hickory_proto::rr::RecordType;
+use lru::LruCache;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+fn default_window_ms() -> u64 {
+    1000
+}
+
+fn default_max_entries() -> usize {
+    10000
+}
+
+/// What to do with a repeat seen inside the window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DedupeAction {
+    /// Answer with the first query's response; drop it if that has none yet.
+    #[default]
+    Replay,
+    /// Send no response at all.
+    Drop,
+}
+
+#[derive(Deserialize)]
+struct DedupeConfig {
+    /// Milliseconds after the first query during which identical ones are repeats.
+    #[serde(default = "default_window_ms")]
+    window_ms: u64,
+    #[serde(default)]
+    action: DedupeAction,
+    /// Upper bound on tracked (client, name, type) keys; the least recently seen
+    /// go first.
+    #[serde(default = "default_max_entries")]
+    max_entries: usize,
+    #[serde(default)]
+    exec: Vec<String>,
+}
+
+type DedupeKey = (IpAddr, String, RecordType);
+
+struct Seen {
+    first: Instant,
+    /// Response to the first query, once its `exec` chain has finished.
+    response: Option<Message>,
+}
+
+/// Shields the `exec` chain from clients that resend the same query in a tight
+/// loop: identical (client, name, type) queries within `window_ms` of the first
+/// are replayed from its answer or dropped, without running `exec` again.
+pub struct Dedupe {
+    window: Duration,
+    action: DedupeAction,
+    seen: Mutex<LruCache<DedupeKey, Seen>>,
+    plugins: Vec<SharedPlugin>,
+}
+
+impl Dedupe {
+    pub fn new(
+        config: Option<&serde_yaml::Value>,
+        registry: &HashMap<String, SharedPlugin>,
+    ) -> Result<Self> {
+        let config: DedupeConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            return Err(anyhow::anyhow!("Dedupe requires config"));
+        };
+
+        // With nothing to run, no first query would ever get an answer to replay.
+        if config.exec.is_empty() {
+            return Err(anyhow::anyhow!("Dedupe requires a non-empty exec list"));
+        }
+
+        let mut plugins = Vec::new();
+        for tag in config.exec {
+            let p = registry
+                .get(&tag)
+                .ok_or_else(|| anyhow::anyhow!("Plugin not found: {}", tag))?;
+            plugins.push(p.clone());
+        }
+
+        Ok(Self {
+            window: Duration::from_millis(config.window_ms),
+            action: config.action,
+            seen: Mutex::new(LruCache::new(
+                NonZeroUsize::new(config.max_entries).unwrap_or(NonZeroUsize::MIN),
+            )),
+            plugins,
+        })
+    }
+
+    /// Register the query, or return the earlier one's state if it is a repeat.
+    fn check(&self, key: &DedupeKey) -> Option<Option<Message>> {
+        let now = Instant::now();
+        let mut seen = self.seen.lock().unwrap();
+        if let Some(entry) = seen.get(key) {
+            if now.duration_since(entry.first) < self.window {
+                return Some(entry.response.clone());
+            }
+        }
+        seen.put(
+            key.clone(),
+            Seen {
+                first: now,
+                response: None,
+            },
+        );
+        None
+    }
+}
+
+#[async_trait]
+impl Plugin for Dedupe {
+    fn name(&self) -> &str {
+        "dedupe"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        let key = ctx.request.query().map(|q| {
+            (
+                ctx.client_addr.ip(),
+                canonical_name(&q.name().to_ascii()),
+                q.query_type(),
+            )
+        });
+
+        if let Some(key) = &key {
+            if let Some(previous) = self.check(key) {
+                match previous.filter(|_| self.action == DedupeAction::Replay) {
+                    Some(mut response) => {
+                        debug!("Replaying answer to repeated query from {}", key.0);
+                        response.set_id(ctx.request.id());
+                        echo_question(&ctx.request, &mut response);
+                        ctx.response = Some(response);
+                    }
+                    None => {
+                        debug!("Dropping repeated query from {}", key.0);
+                        ctx.abort = true;
+                        ctx.dropped = true;
+                    }
+                }
+                return Ok(());
+            }
+        }
+
+        for plugin in &self.plugins {
+            exec(plugin, ctx).await?;
+            if ctx.response.is_some() || ctx.abort {
+                break;
+            }
+        }
+
+        if let (Some(key), Some(response)) = (key, &ctx.response) {
+            if let Some(entry) = self.seen.lock().unwrap().peek_mut(&key) {
+                entry.response = Some(response.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::empty_response;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Query, ResponseCode};
+    use hickory_proto::rr::Name;
+    use std::net::SocketAddr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, RwLock};
+
+    struct CountingPlugin {
+        calls: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Plugin for CountingPlugin {
+        fn name(&self) -> &str {
+            "counting"
+        }
+        async fn next(&self, ctx: &mut Context) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            ctx.response = Some(empty_response(&ctx.request, ResponseCode::NoError));
+            Ok(())
+        }
+    }
+
+    fn make_ctx(client: [u8; 4], id: u16) -> Context {
+        make_named_ctx(client, id, "loop.example.")
+    }
+
+    fn make_named_ctx(client: [u8; 4], id: u16, name: &str) -> Context {
+        let mut msg = Message::new();
+        msg.set_id(id);
+        msg.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        Context::new(
+            SocketAddr::from((client, 5353)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    fn make_dedupe(action: &str, calls: &Arc<AtomicUsize>) -> Dedupe {
+        make_dedupe_with(&format!("action: {}", action), calls)
+    }
+
+    fn make_dedupe_with(extra: &str, calls: &Arc<AtomicUsize>) -> Dedupe {
+        let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+        registry.insert(
+            "upstream".to_string(),
+            Arc::new(CountingPlugin {
+                calls: calls.clone(),
+            }),
+        );
+        let config: serde_yaml::Value =
+            serde_yaml::from_str(&format!("window_ms: 60000\n{}\nexec: [upstream]", extra))
+                .unwrap();
+        Dedupe::new(Some(&config), &registry).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_repeats_coalesced() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let plugin = make_dedupe("replay", &calls);
+
+        for id in 1..=5 {
+            let mut ctx = make_ctx([10, 0, 0, 1], id);
+            plugin.next(&mut ctx).await.unwrap();
+            assert_eq!(ctx.response.unwrap().id(), id);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Another client's identical query is not a repeat.
+        let mut ctx = make_ctx([10, 0, 0, 2], 6);
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_repeats_dropped() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let plugin = make_dedupe("drop", &calls);
+
+        let mut ctx = make_ctx([10, 0, 0, 1], 1);
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.is_some());
+        for id in 2..=4 {
+            let mut ctx = make_ctx([10, 0, 0, 1], id);
+            plugin.next(&mut ctx).await.unwrap();
+            assert!(ctx.response.is_none());
+            assert!(ctx.abort);
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_replay_echoes_question() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let plugin = make_dedupe("replay", &calls);
+
+        let mut ctx = make_named_ctx([10, 0, 0, 1], 1, "loop.example.");
+        plugin.next(&mut ctx).await.unwrap();
+        let mut ctx = make_named_ctx([10, 0, 0, 1], 2, "LOOP.Example.");
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.queries()[0].name().to_ascii(), "LOOP.Example.");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_least_recent_evicted() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let plugin = make_dedupe_with("max_entries: 2", &calls);
+
+        for client in [1, 2, 3] {
+            let mut ctx = make_ctx([10, 0, 0, client], 1);
+            plugin.next(&mut ctx).await.unwrap();
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        // The newest client is still tracked; the oldest was evicted.
+        let mut ctx = make_ctx([10, 0, 0, 3], 2);
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        let mut ctx = make_ctx([10, 0, 0, 1], 2);
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_empty_exec_rejected() {
+        let config: serde_yaml::Value = serde_yaml::from_str("window_ms: 500").unwrap();
+        assert!(Dedupe::new(Some(&config), &HashMap::new()).is_err());
+        let config: serde_yaml::Value = serde_yaml::from_str("exec: []").unwrap();
+        assert!(Dedupe::new(Some(&config), &HashMap::new()).is_err());
+    }
+}
@@ -0,0 +1,272 @@
+use super::{exec, Context, Plugin, SharedPlugin};
+use anyhow::{Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::rdata::AAAA;
+use hickory_proto::rr::{RData, Record, RecordType};
+use ipnet::Ipv6Net;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use tracing::debug;
+
+fn default_prefix() -> String {
+    // Well-known prefix (RFC 6052).
+    "64:ff9b::/96".to_string()
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct Dns64Config {
+    /// Chain that resolves the query and the A lookup behind a synthesized AAAA.
+    exec: Vec<String>,
+    /// NAT64 prefix; /32, /40, /48, /56, /64 or /96.
+    #[serde(default = "default_prefix")]
+    prefix: String,
+    /// Synthesize only for clients that queried over IPv6, since dual-stack
+    /// clients reaching us over IPv4 can use the A record directly.
+    #[serde(default = "default_true")]
+    ipv6_clients_only: bool,
+}
+
+/// Answers AAAA queries for IPv4-only names with addresses synthesized from
+/// their A records under a NAT64 prefix (RFC 6147).
+pub struct Dns64 {
+    plugins: Vec<SharedPlugin>,
+    prefix: Ipv6Net,
+    ipv6_clients_only: bool,
+}
+
+impl Dns64 {
+    pub fn new(
+        config: Option<&serde_yaml::Value>,
+        registry: &HashMap<String, SharedPlugin>,
+    ) -> Result<Self> {
+        let config: Dns64Config = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            return Err(anyhow::anyhow!("Dns64 requires config"));
+        };
+
+        let prefix: Ipv6Net = config
+            .prefix
+            .parse()
+            .with_context(|| format!("Invalid NAT64 prefix: {}", config.prefix))?;
+        if ![32, 40, 48, 56, 64, 96].contains(&prefix.prefix_len()) {
+            return Err(anyhow::anyhow!(
+                "Invalid NAT64 prefix length /{}",
+                prefix.prefix_len()
+            ));
+        }
+
+        let mut plugins = Vec::new();
+        for tag in config.exec {
+            let p = registry
+                .get(&tag)
+                .ok_or_else(|| anyhow::anyhow!("Plugin not found: {}", tag))?;
+            plugins.push(p.clone());
+        }
+
+        Ok(Self {
+            plugins,
+            prefix: prefix.trunc(),
+            ipv6_clients_only: config.ipv6_clients_only,
+        })
+    }
+
+    async fn resolve(&self, ctx: &mut Context) -> Result<()> {
+        for plugin in &self.plugins {
+            exec(plugin, ctx).await?;
+            if ctx.response.is_some() || ctx.abort {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the client looks IPv6-only; IPv4-mapped sources are IPv4 clients.
+    fn wants_synthesis(&self, client: IpAddr) -> bool {
+        !self.ipv6_clients_only || matches!(client, IpAddr::V6(v6) if v6.to_ipv4_mapped().is_none())
+    }
+
+    /// Embed `v4` in the prefix as laid out in RFC 6052 section 2.2, skipping
+    /// the reserved bits 64..72.
+    fn synthesize(&self, v4: Ipv4Addr) -> Ipv6Addr {
+        let mut octets = self.prefix.network().octets();
+        let mut idx = usize::from(self.prefix.prefix_len() / 8);
+        for byte in v4.octets() {
+            if idx == 8 {
+                idx += 1;
+            }
+            octets[idx] = byte;
+            idx += 1;
+        }
+        Ipv6Addr::from(octets)
+    }
+}
+
+/// NOERROR without any AAAA for the queried name.
+fn lacks_aaaa(response: &Message) -> bool {
+    response.response_code() == ResponseCode::NoError
+        && !response
+            .answers()
+            .iter()
+            .any(|r| r.record_type() == RecordType::AAAA)
+}
+
+#[async_trait]
+impl Plugin for Dns64 {
+    fn name(&self) -> &str {
+        "dns64"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        self.resolve(ctx).await?;
+
+        let Some(query) = ctx.request.query().cloned() else {
+            return Ok(());
+        };
+        if query.query_type() != RecordType::AAAA
+            || !ctx.response.as_ref().is_some_and(lacks_aaaa)
+            || !self.wants_synthesis(ctx.client_addr.ip())
+        {
+            return Ok(());
+        }
+
+        let mut request = ctx.request.clone();
+        request.take_queries();
+        request.add_query(Query::query(query.name().clone(), RecordType::A));
+        let mut sub = ctx.clone();
+        sub.request = request;
+        sub.response = None;
+        sub.abort = false;
+        self.resolve(&mut sub).await?;
+        ctx.ecs_scoped |= sub.ecs_scoped;
+
+        let synthesized: Vec<Record> = sub
+            .response
+            .iter()
+            .flat_map(|r| r.answers())
+            .filter_map(|r| match r.data() {
+                Some(RData::A(a)) => Some(Record::from_rdata(
+                    r.name().clone(),
+                    r.ttl(),
+                    RData::AAAA(AAAA(self.synthesize(a.0))),
+                )),
+                _ => None,
+            })
+            .collect();
+        if synthesized.is_empty() {
+            return Ok(());
+        }
+        debug!(
+            "Synthesized {} AAAA record(s) for {} from {}",
+            synthesized.len(),
+            query.name(),
+            ctx.client_addr
+        );
+        if let Some(response) = &mut ctx.response {
+            // Keep any CNAMEs leading to the A records.
+            let cnames: Vec<Record> = sub
+                .response
+                .iter()
+                .flat_map(|r| r.answers())
+                .filter(|r| r.record_type() == RecordType::CNAME)
+                .cloned()
+                .collect();
+            response.take_answers();
+            response.take_name_servers();
+            response.insert_answers(cnames);
+            response.insert_answers(synthesized);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use hickory_proto::rr::Name;
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use std::sync::{Arc, RwLock};
+
+    /// Serves A records only, like a name without IPv6.
+    struct Ipv4Only;
+
+    #[async_trait]
+    impl Plugin for Ipv4Only {
+        fn name(&self) -> &str {
+            "upstream"
+        }
+
+        async fn next(&self, ctx: &mut Context) -> Result<()> {
+            let query = ctx.request.query().unwrap().clone();
+            let mut response = crate::dns::empty_response(&ctx.request, ResponseCode::NoError);
+            if query.query_type() == RecordType::A {
+                response.add_answer(Record::from_rdata(
+                    query.name().clone(),
+                    300,
+                    RData::A(Ipv4Addr::new(192, 0, 2, 33).into()),
+                ));
+            }
+            ctx.response = Some(response);
+            Ok(())
+        }
+    }
+
+    fn make_plugin(yaml: &str) -> Dns64 {
+        let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+        registry.insert("upstream".to_string(), Arc::new(Ipv4Only));
+        let config: serde_yaml::Value = serde_yaml::from_str(yaml).unwrap();
+        Dns64::new(Some(&config), &registry).unwrap()
+    }
+
+    async fn resolve_aaaa(plugin: &Dns64, client: &str) -> Message {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(
+            Name::from_str("v4only.example.").unwrap(),
+            RecordType::AAAA,
+        ));
+        let mut ctx = Context::new(
+            client.parse::<SocketAddr>().unwrap(),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        );
+        plugin.next(&mut ctx).await.unwrap();
+        ctx.response.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_synthesize_for_ipv6_clients() {
+        let plugin = make_plugin("exec: [upstream]");
+
+        let response = resolve_aaaa(&plugin, "[2001:db8::10]:5353").await;
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].ttl(), 300);
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&RData::AAAA(AAAA("64:ff9b::c000:221".parse().unwrap())))
+        );
+
+        // IPv4 (and IPv4-mapped) clients are dual-stack: they get the real NODATA.
+        for client in ["192.0.2.10:5353", "[::ffff:192.0.2.10]:5353"] {
+            let response = resolve_aaaa(&plugin, client).await;
+            assert!(response.answers().is_empty(), "{}", client);
+        }
+    }
+
+    #[test]
+    fn test_prefix_embedding() {
+        let plugin = make_plugin("exec: [upstream]\nprefix: \"2001:db8:100::/40\"");
+        // RFC 6052 section 2.4 example.
+        assert_eq!(
+            plugin.synthesize(Ipv4Addr::new(192, 0, 2, 33)),
+            "2001:db8:1c0:2:21::".parse::<Ipv6Addr>().unwrap()
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use super::{Condition, Context, Plugin};
+use anyhow::Result;
+use async_trait::async_trait;
+
+/// Condition that holds when the request's OPT record has the DO bit set, so
+/// `if` can route DNSSEC-aware clients to a validating upstream.
+pub struct DnssecOk;
+
+impl DnssecOk {
+    pub fn new(_config: Option<&serde_yaml::Value>) -> Result<Self> {
+        Ok(Self)
+    }
+}
+
+impl Condition for DnssecOk {
+    fn check(&self, ctx: &Context) -> bool {
+        ctx.request
+            .extensions()
+            .as_ref()
+            .is_some_and(|edns| edns.dnssec_ok())
+    }
+}
+
+#[async_trait]
+impl Plugin for DnssecOk {
+    fn name(&self) -> &str {
+        "dnssec_ok"
+    }
+
+    async fn next(&self, _ctx: &mut Context) -> Result<()> {
+        Ok(())
+    }
+
+    fn as_condition(&self) -> Option<&dyn Condition> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Edns, Message};
+    use std::net::SocketAddr;
+    use std::sync::{Arc, RwLock};
+
+    fn make_ctx(edns: Option<Edns>) -> Context {
+        let mut msg = Message::new();
+        if let Some(edns) = edns {
+            msg.set_edns(edns);
+        }
+        Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[test]
+    fn test_dnssec_ok() {
+        let condition = DnssecOk;
+
+        let mut edns = Edns::new();
+        edns.set_dnssec_ok(true);
+        assert!(condition.check(&make_ctx(Some(edns))));
+
+        let mut edns = Edns::new();
+        edns.set_dnssec_ok(false);
+        assert!(!condition.check(&make_ctx(Some(edns))));
+
+        // No OPT record at all.
+        assert!(!condition.check(&make_ctx(None)));
+    }
+}
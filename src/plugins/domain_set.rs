@@ -1,19 +1,47 @@
 use super::{Context, DomainSet, Plugin};
+use crate::dns::{canonical_name, domain_regex};
 use anyhow::Result;
 use async_trait::async_trait;
+use regex::Regex;
 use serde::Deserialize;
-use std::collections::HashSet;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
+use std::hash::{Hash, Hasher};
 use std::io::{BufRead, BufReader};
 use tracing::{info, warn};
 
 #[derive(Deserialize)]
 struct DomainSetConfig {
     files: Vec<String>,
+    /// Store domains in a bloom filter instead of a hash set.
+    #[serde(default)]
+    bloom: bool,
+    #[serde(default = "default_false_positive_rate")]
+    false_positive_rate: f64,
+    /// Domains that must never match, e.g. to guard against bloom false positives.
+    #[serde(default)]
+    allow: Vec<String>,
+    /// Match listed names only, not their subdomains.
+    #[serde(default)]
+    exact_only: bool,
+}
+
+fn default_false_positive_rate() -> f64 {
+    0.0001
+}
+
+enum Domains {
+    Trie(DomainTrie),
+    Bloom(BloomFilter),
 }
 
 pub struct DomainSetPlugin {
-    domains: HashSet<String>,
+    domains: Domains,
+    allow: HashSet<String>,
+    /// `regex:` lines of the files, matched against the whole canonical name.
+    regexes: Vec<Regex>,
+    exact_only: bool,
 }
 
 impl DomainSetPlugin {
@@ -24,17 +52,49 @@ impl DomainSetPlugin {
             return Err(anyhow::anyhow!("DomainSet requires config"));
         };
 
-        let mut domains = HashSet::new();
+        let (domains, patterns) = if config.bloom {
+            if !(config.false_positive_rate > 0.0 && config.false_positive_rate < 1.0) {
+                return Err(anyhow::anyhow!(
+                    "false_positive_rate must be between 0 and 1, got {}",
+                    config.false_positive_rate
+                ));
+            }
+            // Count first so the filter is sized without holding every domain in memory.
+            let mut count = 0;
+            Self::read_domains(&config.files, |_| count += 1);
+            let mut filter = BloomFilter::new(count, config.false_positive_rate);
+            let patterns = Self::read_domains(&config.files, |d| filter.insert(d));
+            (Domains::Bloom(filter), patterns)
+        } else {
+            let mut trie = DomainTrie::default();
+            let patterns = Self::read_domains(&config.files, |d| trie.insert(d));
+            (Domains::Trie(trie), patterns)
+        };
+        let regexes = patterns
+            .iter()
+            .map(|p| domain_regex(p))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            domains,
+            allow: config.allow.iter().map(|d| canonical_name(d)).collect(),
+            regexes,
+            exact_only: config.exact_only,
+        })
+    }
 
-        for path in config.files {
-            if let Ok(file) = File::open(&path) {
+    /// Feed every listed domain to `f`, returning the `regex:` patterns found.
+    fn read_domains(files: &[String], mut f: impl FnMut(&str)) -> Vec<String> {
+        let mut patterns = Vec::new();
+        for path in files {
+            if let Ok(file) = File::open(path) {
                 let reader = BufReader::new(file);
-                for line in reader.lines() {
-                    if let Ok(l) = line {
-                        let l = l.trim();
-                        if !l.is_empty() && !l.starts_with('#') {
-                            domains.insert(l.to_string());
-                        }
+                for l in reader.lines().map_while(Result::ok) {
+                    let l = l.trim();
+                    if let Some(pattern) = l.strip_prefix("regex:") {
+                        patterns.push(pattern.to_string());
+                    } else if !l.is_empty() && !l.starts_with('#') {
+                        f(&canonical_name(l));
                     }
                 }
                 info!("Loaded domains from {}", path);
@@ -42,34 +102,122 @@ impl DomainSetPlugin {
                 warn!("Failed to open domain file: {}", path);
             }
         }
-
-        Ok(Self { domains })
+        patterns
     }
 }
 
 impl DomainSet for DomainSetPlugin {
     fn contains(&self, domain: &str) -> bool {
-        // Simple exact or suffix match check
-        // Ideally should use Aho-Corasick or a proper Tree
-        if self.domains.contains(domain) {
+        // Allowed names never match, whichever way they are listed.
+        if self.allow.contains(domain) {
+            return false;
+        }
+        if self.regexes.iter().any(|re| re.is_match(domain)) {
             return true;
         }
-
-        // Suffix check: very inefficient for now, but functional for small lists
-        // "google.com" matches "www.google.com" if stored as "google.com"
-        for d in &self.domains {
-            if domain.ends_with(d) {
-                // confirm it's a dot boundary
-                let remainder = domain.len() - d.len();
-                if remainder > 0 && domain.as_bytes()[remainder - 1] == b'.' {
-                    return true;
+        match &self.domains {
+            Domains::Trie(trie) => trie.matches(domain, self.exact_only),
+            Domains::Bloom(filter) => {
+                // Probe the name and each parent domain.
+                let mut suffix = domain;
+                loop {
+                    if filter.contains(suffix) {
+                        return true;
+                    }
+                    if self.exact_only {
+                        return false;
+                    }
+                    match suffix.split_once('.') {
+                        Some((_, parent)) if !parent.is_empty() => suffix = parent,
+                        _ => return false,
+                    }
                 }
             }
         }
+    }
+}
+
+/// Domains stored label by label from the TLD down, so a lookup walks the
+/// query's labels once instead of comparing against every entry.
+#[derive(Default)]
+struct DomainTrie {
+    children: HashMap<Box<str>, DomainTrie>,
+    /// A listed domain ends at this node.
+    terminal: bool,
+}
+
+impl DomainTrie {
+    fn insert(&mut self, domain: &str) {
+        let mut node = self;
+        for label in domain.rsplit('.') {
+            node = node.children.entry(label.into()).or_default();
+        }
+        node.terminal = true;
+    }
+
+    /// True if `domain` is listed or, unless `exact_only`, is a subdomain of a listed one.
+    fn matches(&self, domain: &str, exact_only: bool) -> bool {
+        let mut node = self;
+        let mut labels = domain.rsplit('.').peekable();
+        while let Some(label) = labels.next() {
+            match node.children.get(label) {
+                Some(child) => node = child,
+                None => return false,
+            }
+            if node.terminal && (labels.peek().is_none() || !exact_only) {
+                return true;
+            }
+        }
         false
     }
 }
 
+/// Fixed-size bloom filter using double hashing over SipHash.
+struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let n = expected_items.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-n * false_positive_rate.ln() / (ln2 * ln2))
+            .ceil()
+            .max(64.0) as u64;
+        let num_hashes = ((num_bits as f64 / n) * ln2).round().clamp(1.0, 32.0) as u32;
+        Self {
+            bits: vec![0; num_bits.div_ceil(64) as usize],
+            num_bits,
+            num_hashes,
+        }
+    }
+
+    fn indexes(&self, item: &str) -> impl Iterator<Item = u64> {
+        let hash = |seed: u64| {
+            let mut hasher = DefaultHasher::new();
+            seed.hash(&mut hasher);
+            item.hash(&mut hasher);
+            hasher.finish()
+        };
+        let (h1, h2) = (hash(0), hash(1) | 1);
+        let num_bits = self.num_bits;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    fn insert(&mut self, item: &str) {
+        for idx in self.indexes(item).collect::<Vec<_>>() {
+            self.bits[(idx / 64) as usize] |= 1 << (idx % 64);
+        }
+    }
+
+    fn contains(&self, item: &str) -> bool {
+        self.indexes(item)
+            .all(|idx| self.bits[(idx / 64) as usize] & (1 << (idx % 64)) != 0)
+    }
+}
+
 #[async_trait]
 impl Plugin for DomainSetPlugin {
     fn name(&self) -> &str {
@@ -119,4 +267,123 @@ mod tests {
         assert!(plugin.contains("www.google.com")); // Suffix match
         assert!(!plugin.contains("yahoo.com"));
     }
+
+    #[test]
+    fn test_regex_lines() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "example.org").unwrap();
+        writeln!(file, r"regex:^ad[0-9]+\.example\.com$").unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+
+        for bloom in [false, true] {
+            let yaml = format!("files: [\"{}\"]\nbloom: {}", path, bloom);
+            let config: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+            let plugin = DomainSetPlugin::new(Some(&config)).unwrap();
+            assert!(plugin.contains("ad3.example.com"));
+            assert!(!plugin.contains("ads.example.com"));
+            assert!(plugin.contains("www.example.org"));
+        }
+
+        writeln!(file, "regex:[z-a]").unwrap();
+        let config: serde_yaml::Value =
+            serde_yaml::from_str(&format!("files: [\"{}\"]", path)).unwrap();
+        assert!(DomainSetPlugin::new(Some(&config)).is_err());
+    }
+
+    #[test]
+    fn test_bloom_domain_set() {
+        let mut file = NamedTempFile::new().unwrap();
+        for i in 0..10_000 {
+            writeln!(file, "blocked{}.example", i).unwrap();
+        }
+        writeln!(file, "critical.example").unwrap();
+
+        let yaml = format!(
+            r#"
+            files: ["{}"]
+            bloom: true
+            false_positive_rate: 0.01
+            allow: ["critical.example"]
+            "#,
+            file.path().to_str().unwrap()
+        );
+        let config: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        let plugin = DomainSetPlugin::new(Some(&config)).unwrap();
+
+        for i in 0..10_000 {
+            assert!(plugin.contains(&format!("blocked{}.example", i)));
+        }
+        assert!(plugin.contains("www.blocked42.example")); // Suffix match
+        assert!(!plugin.contains("critical.example")); // Exact allow wins
+
+        let false_positives = (0..10_000)
+            .filter(|i| plugin.contains(&format!("allowed{}.test", i)))
+            .count();
+        // 1% target per probe; each name probes itself and its parent.
+        assert!(false_positives < 300, "{} false positives", false_positives);
+    }
+
+    #[test]
+    fn test_allow() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "example.com").unwrap();
+        writeln!(file, r"regex:^ad[0-9]+\.example\.org$").unwrap();
+        let path = file.path().to_str().unwrap();
+
+        for bloom in [false, true] {
+            let yaml = format!(
+                "files: [\"{}\"]\nallow: [WWW.example.com, ad2.example.org]\nbloom: {}",
+                path, bloom
+            );
+            let config: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+            let plugin = DomainSetPlugin::new(Some(&config)).unwrap();
+
+            assert!(plugin.contains("example.com"), "bloom: {}", bloom);
+            assert!(!plugin.contains("www.example.com"), "bloom: {}", bloom);
+            assert!(plugin.contains("mail.example.com"), "bloom: {}", bloom);
+            assert!(plugin.contains("ad1.example.org"), "bloom: {}", bloom);
+            assert!(!plugin.contains("ad2.example.org"), "bloom: {}", bloom);
+        }
+    }
+
+    #[test]
+    fn test_exact_only() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "google.com").unwrap();
+        let path = file.path().to_str().unwrap();
+
+        for bloom in [false, true] {
+            let yaml = format!("files: [\"{}\"]\nexact_only: true\nbloom: {}", path, bloom);
+            let config: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+            let plugin = DomainSetPlugin::new(Some(&config)).unwrap();
+
+            assert!(plugin.contains("google.com"));
+            assert!(!plugin.contains("www.google.com"));
+            assert!(!plugin.contains("evil-google.com"));
+        }
+    }
+
+    #[test]
+    fn test_trie_large_list() {
+        let mut file = NamedTempFile::new().unwrap();
+        for i in 0..10_000 {
+            writeln!(file, "ads{}.tracker{}.example", i, i % 100).unwrap();
+        }
+        writeln!(file, "Blocked.TEST.").unwrap();
+        let yaml = format!("files: [\"{}\"]", file.path().display());
+        let config: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        let plugin = DomainSetPlugin::new(Some(&config)).unwrap();
+
+        for i in 0..10_000 {
+            let listed = format!("ads{}.tracker{}.example", i, i % 100);
+            assert!(plugin.contains(&listed));
+            assert!(plugin.contains(&format!("cdn.{}", listed)));
+            // Parents and label-prefix lookalikes of listed names don't match.
+            assert!(!plugin.contains(&format!("tracker{}.example", i % 100)));
+            assert!(!plugin.contains(&format!("xads{}.tracker{}.example", i, i % 100)));
+        }
+        assert!(plugin.contains("deep.sub.blocked.test"));
+        assert!(!plugin.contains("test"));
+        assert!(!plugin.contains("example"));
+    }
 }
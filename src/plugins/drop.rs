@@ -0,0 +1,56 @@
+use super::{Context, Plugin};
+use anyhow::Result;
+use async_trait::async_trait;
+
+/// Ends the chain without a response, so the query goes unanswered
+/// (e.g. to blackhole scanners). Any response set earlier is discarded.
+pub struct DropPlugin;
+
+impl DropPlugin {
+    pub fn new(_config: Option<&serde_yaml::Value>) -> Result<Self> {
+        Ok(Self)
+    }
+}
+
+#[async_trait]
+impl Plugin for DropPlugin {
+    fn name(&self) -> &str {
+        "drop"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        ctx.response = None;
+        ctx.abort = true;
+        ctx.dropped = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, RwLock};
+
+    fn make_ctx() -> Context {
+        use crate::statistics::Statistics;
+        use hickory_proto::op::Message;
+        use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+        Context::new(
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 1234),
+            Message::new(),
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_drop_plugin() {
+        let plugin = DropPlugin::new(None).unwrap();
+        let mut ctx = make_ctx();
+        ctx.response = Some(ctx.request.clone());
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.is_none());
+        assert!(ctx.abort);
+        assert!(ctx.dropped);
+    }
+}
@@ -0,0 +1,165 @@
+use super::{Context, Plugin};
+use crate::dns::EcsPrefixes;
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::rr::rdata::opt::{EdnsCode, EdnsOption};
+use serde::Deserialize;
+use tracing::debug;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+enum EcsMode {
+    /// Remove any client-supplied ECS option.
+    #[default]
+    Strip,
+    /// Keep the option but shorten its source prefix.
+    Truncate,
+}
+
+fn default_ipv4_prefix() -> u8 {
+    EcsPrefixes::default().ipv4
+}
+
+fn default_ipv6_prefix() -> u8 {
+    EcsPrefixes::default().ipv6
+}
+
+#[derive(Deserialize)]
+struct EcsConfig {
+    #[serde(default)]
+    mode: EcsMode,
+    #[serde(default = "default_ipv4_prefix")]
+    ipv4_prefix: u8,
+    #[serde(default = "default_ipv6_prefix")]
+    ipv6_prefix: u8,
+}
+
+/// Rewrites the EDNS Client Subnet option of the request before it is forwarded,
+/// so precise client addresses don't leak to upstreams.
+pub struct EcsPlugin {
+    mode: EcsMode,
+    prefixes: EcsPrefixes,
+}
+
+impl EcsPlugin {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: EcsConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            EcsConfig {
+                mode: EcsMode::default(),
+                ipv4_prefix: default_ipv4_prefix(),
+                ipv6_prefix: default_ipv6_prefix(),
+            }
+        };
+
+        Ok(Self {
+            mode: config.mode,
+            prefixes: EcsPrefixes::new(config.ipv4_prefix, config.ipv6_prefix)?,
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for EcsPlugin {
+    fn name(&self) -> &str {
+        "ecs"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        let Some(edns) = ctx.request.extensions_mut().as_mut() else {
+            return Ok(());
+        };
+        let Some(EdnsOption::Subnet(subnet)) = edns.option(EdnsCode::Subnet).cloned() else {
+            return Ok(());
+        };
+
+        let options = edns.options_mut();
+        options.remove(EdnsCode::Subnet);
+        if self.mode == EcsMode::Truncate {
+            if let Some(truncated) = self.prefixes.truncate(&subnet) {
+                debug!("Truncated client ECS {:?} to {:?}", subnet, truncated);
+                options.insert(EdnsOption::Subnet(truncated));
+                return Ok(());
+            }
+        }
+        debug!("Stripped client ECS {:?}", subnet);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Edns, Message, Query};
+    use hickory_proto::rr::rdata::opt::ClientSubnet;
+    use hickory_proto::rr::{Name, RecordType};
+    use std::str::FromStr;
+    use std::sync::{Arc, RwLock};
+
+    fn make_ctx(subnet: &str) -> Context {
+        use crate::statistics::Statistics;
+        use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+        let mut msg = Message::new();
+        msg.add_query(Query::query(
+            Name::from_str("example.com.").unwrap(),
+            RecordType::A,
+        ));
+        let mut edns = Edns::new();
+        edns.options_mut()
+            .insert(EdnsOption::Subnet(ClientSubnet::from_str(subnet).unwrap()));
+        msg.set_edns(edns);
+
+        Context::new(
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 1234),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    fn sent_subnet(ctx: &Context) -> Option<ClientSubnet> {
+        // Round-trip through the wire format to see what an upstream would receive.
+        let msg = Message::from_vec(&ctx.request.to_vec().unwrap()).unwrap();
+        match msg.extensions().as_ref()?.option(EdnsCode::Subnet) {
+            Some(EdnsOption::Subnet(subnet)) => Some(*subnet),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_strip() {
+        let plugin = EcsPlugin::new(None).unwrap();
+        let mut ctx = make_ctx("203.0.113.77/32");
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(sent_subnet(&ctx), None);
+    }
+
+    #[tokio::test]
+    async fn test_truncate() {
+        let config: serde_yaml::Value = serde_yaml::from_str("mode: truncate").unwrap();
+        let plugin = EcsPlugin::new(Some(&config)).unwrap();
+
+        let mut ctx = make_ctx("203.0.113.77/32");
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(
+            sent_subnet(&ctx),
+            Some(ClientSubnet::from_str("203.0.113.0/24").unwrap())
+        );
+
+        // Already coarser than the limit: left as is.
+        let mut ctx = make_ctx("198.51.0.0/16");
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(
+            sent_subnet(&ctx),
+            Some(ClientSubnet::from_str("198.51.0.0/16").unwrap())
+        );
+
+        let mut ctx = make_ctx("2001:db8:1:2:3::1/128");
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(
+            sent_subnet(&ctx),
+            Some(ClientSubnet::from_str("2001:db8:1::/56").unwrap())
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use super::{Condition, Context, Plugin};
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::rr::rdata::opt::EdnsCode;
+use serde::Deserialize;
+
+/// Option codes accepted by name (IANA "DNS EDNS0 Option Codes" registry).
+const NAMED_CODES: &[(&str, u16)] = &[
+    ("nsid", 3),
+    ("subnet", 8),
+    ("expire", 9),
+    ("cookie", 10),
+    ("keepalive", 11),
+    ("padding", 12),
+];
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OptionCode {
+    Number(u16),
+    Name(String),
+}
+
+#[derive(Deserialize)]
+struct EdnsOptionConfig {
+    /// Option code, numeric or one of the names in `NAMED_CODES`.
+    code: OptionCode,
+}
+
+/// Condition that holds when the request carries a given EDNS option.
+pub struct EdnsOptionCondition {
+    code: u16,
+}
+
+impl EdnsOptionCondition {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: EdnsOptionConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            return Err(anyhow::anyhow!("EdnsOption requires config"));
+        };
+        let code = match config.code {
+            OptionCode::Number(code) => code,
+            OptionCode::Name(name) => NAMED_CODES
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(&name))
+                .map(|(_, code)| *code)
+                .ok_or_else(|| anyhow::anyhow!("Unknown EDNS option: {}", name))?,
+        };
+        Ok(Self { code })
+    }
+}
+
+impl Condition for EdnsOptionCondition {
+    fn check(&self, ctx: &Context) -> bool {
+        ctx.request
+            .extensions()
+            .as_ref()
+            .is_some_and(|edns| edns.option(EdnsCode::from(self.code)).is_some())
+    }
+}
+
+#[async_trait]
+impl Plugin for EdnsOptionCondition {
+    fn name(&self) -> &str {
+        "edns_option"
+    }
+
+    async fn next(&self, _ctx: &mut Context) -> Result<()> {
+        Ok(())
+    }
+
+    fn as_condition(&self) -> Option<&dyn Condition> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Edns, Message};
+    use hickory_proto::rr::rdata::opt::EdnsOption;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, RwLock};
+
+    fn make_ctx(options: &[(u16, Vec<u8>)]) -> Context {
+        let mut msg = Message::new();
+        let mut edns = Edns::new();
+        for (code, data) in options {
+            edns.options_mut()
+                .insert(EdnsOption::Unknown(*code, data.clone()));
+        }
+        msg.set_edns(edns);
+        Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    fn condition(code: &str) -> EdnsOptionCondition {
+        let config: serde_yaml::Value = serde_yaml::from_str(&format!("code: {}", code)).unwrap();
+        EdnsOptionCondition::new(Some(&config)).unwrap()
+    }
+
+    #[test]
+    fn test_edns_option_presence() {
+        let cookie = condition("cookie");
+        let with_cookie = make_ctx(&[(10, vec![1, 2, 3, 4, 5, 6, 7, 8])]);
+        let without = make_ctx(&[(12, vec![0; 4])]);
+        assert!(cookie.check(&with_cookie));
+        assert!(!cookie.check(&without));
+
+        // Numeric codes work for options without a name.
+        let padding = condition("12");
+        assert!(padding.check(&without));
+        assert!(!padding.check(&with_cookie));
+
+        let config: serde_yaml::Value = serde_yaml::from_str("code: bogus").unwrap();
+        assert!(EdnsOptionCondition::new(Some(&config)).is_err());
+    }
+}
@@ -1,6 +1,8 @@
-use super::{Context, Plugin, SharedPlugin};
+use super::{exec, Context, Plugin, SharedPlugin};
+use crate::dns::empty_response;
 use anyhow::Result;
 use async_trait::async_trait;
+use hickory_proto::op::ResponseCode;
 use serde::Deserialize;
 use std::collections::HashMap;
 use tracing::warn;
@@ -48,13 +50,24 @@ impl Plugin for FallbackPlugin {
     }
 
     async fn next(&self, ctx: &mut Context) -> Result<()> {
-        if let Err(e) = self.primary.next(ctx).await {
+        if let Err(e) = exec(&self.primary, ctx).await {
+            // Switching counts against the query's retry budget.
+            if ctx.retry_budget.as_ref().is_some_and(|b| b.take(1) == 0) {
+                warn!(
+                    "Primary plugin {} failed: {}. Retry budget exhausted, answering SERVFAIL.",
+                    self.primary.name(),
+                    e
+                );
+                ctx.response = Some(empty_response(&ctx.request, ResponseCode::ServFail));
+                ctx.abort = true;
+                return Ok(());
+            }
             warn!(
                 "Primary plugin {} failed: {}. Switching to secondary.",
                 self.primary.name(),
                 e
             );
-            self.secondary.next(ctx).await
+            exec(&self.secondary, ctx).await
         } else {
             Ok(())
         }
@@ -159,4 +172,65 @@ mod tests {
         assert!(*p1_called.lock().unwrap());
         assert!(*p2_called.lock().unwrap());
     }
+
+    #[tokio::test]
+    async fn test_retry_budget_bounds_attempts() {
+        use crate::plugins::forward::Forward;
+        use crate::plugins::RetryBudget;
+        use crate::statistics::Statistics;
+        use hickory_proto::op::{Message, Query};
+        use hickory_proto::rr::{Name, RecordType};
+        use std::net::SocketAddr;
+        use std::sync::RwLock;
+
+        // Nothing listens there, so every exchange fails at once.
+        let dead = || {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("tcp://{}", listener.local_addr().unwrap())
+        };
+        let attempts = |budget: usize| {
+            let primary = format!(
+                "upstreams: [\"{}\", \"{}\", \"{}\"]\nstrategy: first",
+                dead(),
+                dead(),
+                dead()
+            );
+            let secondary = format!("upstreams: [\"{}\"]", dead());
+            async move {
+                let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+                for (tag, yaml) in [("p1", primary), ("p2", secondary)] {
+                    let config: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+                    registry.insert(
+                        tag.to_string(),
+                        Arc::new(Forward::new(Some(&config)).unwrap()),
+                    );
+                }
+                let config: serde_yaml::Value =
+                    serde_yaml::from_str("primary: p1\nsecondary: p2").unwrap();
+                let plugin = FallbackPlugin::new(Some(&config), &registry).unwrap();
+
+                let mut msg = Message::new();
+                msg.add_query(Query::query(
+                    Name::from_ascii("example.com.").unwrap(),
+                    RecordType::A,
+                ));
+                let stats = Arc::new(RwLock::new(Statistics::new()));
+                let mut ctx = Context::new(SocketAddr::from(([127, 0, 0, 1], 1234)), msg, stats);
+                ctx.retry_budget = Some(RetryBudget::new(budget));
+                plugin.next(&mut ctx).await.unwrap();
+                assert!(ctx.abort);
+                assert_eq!(
+                    ctx.response.unwrap().response_code(),
+                    ResponseCode::ServFail
+                );
+                let stats = ctx.stats.read().unwrap();
+                stats.upstream_protocols["tcp"].queries
+            }
+        };
+
+        // Stops inside the primary's rounds...
+        assert_eq!(attempts(2).await, 2);
+        // ...or after the switch to the secondary used up the rest.
+        assert_eq!(attempts(4).await, 3);
+    }
 }
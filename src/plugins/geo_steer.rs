@@ -0,0 +1,198 @@
+use super::geoip::{load_list, GeoipPlugin};
+use super::{Context, IpSet, Plugin};
+use crate::dns::{self, canonical_name};
+use anyhow::{anyhow, Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{RData, Record, RecordType};
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::net::IpAddr;
+use tracing::debug;
+
+#[derive(Deserialize)]
+struct GeoSteerConfig {
+    /// v2ray-style `geoip.dat` the client's country is looked up in.
+    file: String,
+    /// Names answered by country.
+    names: Vec<String>,
+    /// Country code to the addresses its clients get; the first listed country
+    /// containing the client wins.
+    countries: serde_yaml::Mapping,
+    /// Addresses for clients in none of the countries; such queries pass on if empty.
+    #[serde(default)]
+    default: Vec<IpAddr>,
+    /// TTL of answers; the server's `default_ttl` if unset.
+    #[serde(default)]
+    ttl: Option<u32>,
+}
+
+/// Geo-steering without a GSLB: A/AAAA queries for the configured names are
+/// answered with the addresses configured for the client's country.
+pub struct GeoSteer {
+    names: HashSet<String>,
+    countries: Vec<(String, GeoipPlugin, Vec<IpAddr>)>,
+    default: Vec<IpAddr>,
+    ttl: Option<u32>,
+}
+
+impl GeoSteer {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: GeoSteerConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            return Err(anyhow!("GeoSteer requires config"));
+        };
+
+        let list = load_list(&config.file)?;
+        let mut countries = Vec::new();
+        for (code, answers) in config.countries {
+            let code = code
+                .as_str()
+                .ok_or_else(|| anyhow!("Country codes must be strings"))?
+                .to_uppercase();
+            let answers: Vec<IpAddr> = serde_yaml::from_value(answers)
+                .with_context(|| format!("Invalid addresses for country {}", code))?;
+            let ranges = GeoipPlugin::from_list(&list, &code);
+            countries.push((code, ranges, answers));
+        }
+
+        Ok(Self {
+            names: config.names.iter().map(|n| canonical_name(n)).collect(),
+            countries,
+            default: config.default,
+            ttl: config.ttl,
+        })
+    }
+
+    /// Addresses for a client at `ip`, or `None` to let the query pass on.
+    fn answers_for(&self, ip: IpAddr) -> Option<&[IpAddr]> {
+        match self.countries.iter().find(|(_, set, _)| set.contains(ip)) {
+            Some((code, _, answers)) => {
+                debug!("Client {} is in {}", ip, code);
+                Some(answers)
+            }
+            None if !self.default.is_empty() => Some(&self.default),
+            None => None,
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for GeoSteer {
+    fn name(&self) -> &str {
+        "geo_steer"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        if ctx.response.is_some() {
+            return Ok(());
+        }
+        let Some(query) = ctx.request.query() else {
+            return Ok(());
+        };
+        if !matches!(query.query_type(), RecordType::A | RecordType::AAAA)
+            || !self
+                .names
+                .contains(&canonical_name(&query.name().to_ascii()))
+        {
+            return Ok(());
+        }
+        let Some(answers) = self.answers_for(ctx.client_addr.ip()) else {
+            return Ok(());
+        };
+
+        let ttl = self.ttl.unwrap_or(ctx.default_ttl);
+        let mut response = dns::empty_response(&ctx.request, ResponseCode::NoError);
+        for ip in answers {
+            let rdata = match (ip, query.query_type()) {
+                (IpAddr::V4(v4), RecordType::A) => RData::A(A(*v4)),
+                (IpAddr::V6(v6), RecordType::AAAA) => RData::AAAA(AAAA(*v6)),
+                _ => continue,
+            };
+            response.add_answer(Record::from_rdata(query.name().clone(), ttl, rdata));
+        }
+        ctx.response = Some(response);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::Name;
+    use ipnet::IpNet;
+    use prost::Message as _;
+    use std::io::Write;
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+    use std::sync::{Arc, RwLock};
+    use tempfile::NamedTempFile;
+
+    fn geoip(code: &str, net: &str) -> proto::GeoIp {
+        let net = IpNet::from_str(net).unwrap();
+        let ip = match net.network() {
+            IpAddr::V4(v4) => v4.octets().to_vec(),
+            IpAddr::V6(v6) => v6.octets().to_vec(),
+        };
+        proto::GeoIp {
+            country_code: code.into(),
+            cidr: vec![proto::Cidr {
+                ip,
+                prefix: net.prefix_len() as u32,
+            }],
+            reverse_match: false,
+        }
+    }
+
+    async fn resolve(plugin: &GeoSteer, client: &str, name: &str) -> Option<Vec<String>> {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        let mut ctx = Context::new(
+            SocketAddr::new(client.parse().unwrap(), 1234),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        );
+        plugin.next(&mut ctx).await.unwrap();
+        ctx.response.map(|r| {
+            r.answers()
+                .iter()
+                .map(|a| a.data().unwrap().to_string())
+                .collect()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_answer_by_client_country() {
+        let list = proto::GeoIpList {
+            entry: vec![geoip("CN", "1.0.1.0/24"), geoip("US", "8.8.8.0/24")],
+        };
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&list.encode_to_vec()).unwrap();
+        let yaml = format!(
+            r#"
+            file: "{}"
+            names: [www.example.com]
+            countries:
+              cn: ["203.0.113.1"]
+              us: ["198.51.100.1", "2001:db8::1"]
+            default: ["192.0.2.1"]
+            "#,
+            file.path().display()
+        );
+        let plugin = GeoSteer::new(Some(&serde_yaml::from_str(&yaml).unwrap())).unwrap();
+
+        let answers = resolve(&plugin, "1.0.1.7", "www.example.com.").await;
+        assert_eq!(answers.unwrap(), ["203.0.113.1"]);
+        let answers = resolve(&plugin, "8.8.8.8", "WWW.Example.com.").await;
+        assert_eq!(answers.unwrap(), ["198.51.100.1"]);
+        let answers = resolve(&plugin, "9.9.9.9", "www.example.com.").await;
+        assert_eq!(answers.unwrap(), ["192.0.2.1"]);
+        // Other names are not steered.
+        assert!(resolve(&plugin, "1.0.1.7", "example.com.").await.is_none());
+    }
+}
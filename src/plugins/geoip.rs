@@ -0,0 +1,199 @@
+use super::{Context, IpSet, Plugin};
+use crate::proto;
+use anyhow::{Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use ipnet::{IpNet, Ipv4Net, Ipv6Net};
+use prost::Message;
+use serde::Deserialize;
+use std::fs::read;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use tracing::{info, warn};
+
+#[derive(Deserialize)]
+struct GeoipConfig {
+    file: String,
+    code: String,
+}
+
+/// Sorted, non-overlapping inclusive ranges, searched by binary search.
+struct Ranges<T>(Vec<(T, T)>);
+
+impl<T: Ord + Copy> Ranges<T> {
+    fn new(mut ranges: Vec<(T, T)>) -> Self {
+        ranges.sort_unstable();
+        let mut merged: Vec<(T, T)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        Self(merged)
+    }
+
+    fn contains(&self, value: T) -> bool {
+        let idx = self.0.partition_point(|&(start, _)| start <= value);
+        idx > 0 && value <= self.0[idx - 1].1
+    }
+}
+
+/// Loads one country's ranges from a v2ray-style `geoip.dat` and exposes them
+/// as an `IpSet`.
+pub struct GeoipPlugin {
+    v4: Ranges<u32>,
+    v6: Ranges<u128>,
+    reverse: bool,
+}
+
+impl GeoipPlugin {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: GeoipConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            return Err(anyhow::anyhow!("Geoip requires config"));
+        };
+
+        let list = load_list(&config.file)?;
+        Ok(Self::from_list(&list, &config.code))
+    }
+
+    /// The ranges of country `code` (case-insensitive) in `list`; empty, with a
+    /// warning, if the list doesn't have it.
+    pub(crate) fn from_list(list: &proto::GeoIpList, code: &str) -> Self {
+        let code = code.to_uppercase();
+        let mut v4 = Vec::new();
+        let mut v6 = Vec::new();
+        let mut reverse = false;
+        if let Some(geoip) = list.entry.iter().find(|g| g.country_code == code) {
+            info!(
+                "Loaded geoip code: {} with {} ranges",
+                code,
+                geoip.cidr.len()
+            );
+            reverse = geoip.reverse_match;
+            for cidr in &geoip.cidr {
+                match parse_cidr(cidr) {
+                    Some(IpNet::V4(net)) => {
+                        v4.push((u32::from(net.network()), u32::from(net.broadcast())))
+                    }
+                    Some(IpNet::V6(net)) => {
+                        v6.push((u128::from(net.network()), u128::from(net.broadcast())))
+                    }
+                    None => warn!("Invalid CIDR in geoip code {}", code),
+                }
+            }
+        } else {
+            warn!("Geoip code {} not found in file", code);
+        }
+
+        Self {
+            v4: Ranges::new(v4),
+            v6: Ranges::new(v6),
+            reverse,
+        }
+    }
+}
+
+/// Decode a v2ray-style `geoip.dat`.
+pub(crate) fn load_list(path: &str) -> Result<proto::GeoIpList> {
+    let data = read(path).with_context(|| format!("Failed to read geoip file {}", path))?;
+    Ok(proto::GeoIpList::decode(&data[..])?)
+}
+
+fn parse_cidr(cidr: &proto::Cidr) -> Option<IpNet> {
+    let prefix = u8::try_from(cidr.prefix).ok()?;
+    match cidr.ip.len() {
+        4 => {
+            let octets: [u8; 4] = cidr.ip[..].try_into().ok()?;
+            Ipv4Net::new(Ipv4Addr::from(octets), prefix)
+                .ok()
+                .map(IpNet::V4)
+        }
+        16 => {
+            let octets: [u8; 16] = cidr.ip[..].try_into().ok()?;
+            Ipv6Net::new(Ipv6Addr::from(octets), prefix)
+                .ok()
+                .map(IpNet::V6)
+        }
+        _ => None,
+    }
+}
+
+impl IpSet for GeoipPlugin {
+    fn contains(&self, ip: IpAddr) -> bool {
+        let found = match ip {
+            IpAddr::V4(v4) => self.v4.contains(u32::from(v4)),
+            IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+                Some(v4) => self.v4.contains(u32::from(v4)),
+                None => self.v6.contains(u128::from(v6)),
+            },
+        };
+        found != self.reverse
+    }
+}
+
+#[async_trait]
+impl Plugin for GeoipPlugin {
+    fn name(&self) -> &str {
+        "geoip"
+    }
+
+    async fn next(&self, _ctx: &mut Context) -> Result<()> {
+        Ok(())
+    }
+
+    fn as_ip_set(&self) -> Option<&dyn IpSet> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::str::FromStr;
+    use tempfile::NamedTempFile;
+
+    fn cidr(net: &str) -> proto::Cidr {
+        let net = IpNet::from_str(net).unwrap();
+        let ip = match net.network() {
+            IpAddr::V4(v4) => v4.octets().to_vec(),
+            IpAddr::V6(v6) => v6.octets().to_vec(),
+        };
+        proto::Cidr {
+            ip,
+            prefix: net.prefix_len() as u32,
+        }
+    }
+
+    #[test]
+    fn test_geoip_contains() {
+        let list = proto::GeoIpList {
+            entry: vec![
+                proto::GeoIp {
+                    country_code: "CN".into(),
+                    cidr: vec![cidr("1.0.1.0/24"), cidr("1.0.2.0/23"), cidr("240e::/20")],
+                    reverse_match: false,
+                },
+                proto::GeoIp {
+                    country_code: "US".into(),
+                    cidr: vec![cidr("8.8.8.0/24")],
+                    reverse_match: false,
+                },
+            ],
+        };
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&list.encode_to_vec()).unwrap();
+
+        let yaml = format!("file: \"{}\"\ncode: cn", file.path().display());
+        let config: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        let plugin = GeoipPlugin::new(Some(&config)).unwrap();
+
+        for ip in ["1.0.1.1", "1.0.3.255", "240e:3b0::1", "::ffff:1.0.2.7"] {
+            assert!(plugin.contains(IpAddr::from_str(ip).unwrap()), "{}", ip);
+        }
+        for ip in ["1.0.0.255", "1.0.4.0", "8.8.8.8", "2001:db8::1"] {
+            assert!(!plugin.contains(IpAddr::from_str(ip).unwrap()), "{}", ip);
+        }
+    }
+}
@@ -1,7 +1,9 @@
 use super::{Context, DomainSet, Plugin};
+use crate::dns::domain_regex;
 use anyhow::{Context as AnyhowContext, Result};
 use async_trait::async_trait;
 use prost::Message;
+use regex::Regex;
 use serde::Deserialize;
 use std::collections::HashSet;
 use std::fs::read;
@@ -35,8 +37,8 @@ struct GeositeConfig {
 pub struct GeositePlugin {
     exact_matches: HashSet<String>,
     suffix_matches: Vec<String>,
-    #[allow(dead_code)]
-    regex_matches: Vec<String>, // Parsed regexes could be stored here
+    keyword_matches: Vec<String>,
+    regex_matches: Vec<Regex>,
 }
 
 impl GeositePlugin {
@@ -56,8 +58,8 @@ impl GeositePlugin {
 
         let mut exact_matches = HashSet::new();
         let mut suffix_matches = Vec::new();
-        // Regex not fully implemented yet
-        let regex_matches = Vec::new();
+        let mut keyword_matches = Vec::new();
+        let mut regex_matches = Vec::new();
 
         if let Some(site) = site {
             info!(
@@ -67,92 +69,15 @@ impl GeositePlugin {
             );
             for domain in site.domain {
                 match domain.r#type {
-                    0 | 3 => { // Plain or Full
-                         // Protocol says:
-                         // Plain (0): substring key? checking v2fly docs: "Plain: Substring match"
-                         // Wait, v2fly docs say:
-                         //   domain: -> substring/subdomain?
-                         //   full: -> exact
-                         // Let's check common.proto comments I saw earlier.
-                         //   Plain = 0; // Value is used as is. (Usually subdomain)
-                         //   Regex = 1;
-                         //   RootDomain = 2; // ?
-                         //   Full = 3;
-
-                        // In my generator:
-                        //   "domain:" -> Type::Plain
-                        //   "full:" -> Type::Full
-
-                        // In V2Ray routing:
-                        //   Plain -> Keyword match (substring)
-                        //   Domain -> Domain match (suffix) - wait, where is Type 2 (RootDomain)?
-
-                        // Let's look at my generator again.
-                        // I mapped "domain:" to Type::Plain.
-                        // Actually, standard v2ray geosite generator maps "domain:" to Type::Domain(2) usually?
-                        // Re-reading common.proto from my memory/doc:
-                        //   Plain = 0;
-                        //   Regex = 1;
-                        //   RootDomain = 2;
-                        //   Full = 3;
-
-                        // My generator uses:
-                        //   "domain:" -> Type::Plain (0) -- WAIT.
-                        //   In v2fly/domain-list-community README:
-                        //     "domain:" -> sub-domain routing rule.
-                        //     "keyword:" -> plain domain routing rule.
-                        //     "full:" -> full domain routing rule.
-
-                        // V2Ray Core Proto:
-                        //   Type 0 (Plain) -> Keyword
-                        //   Type 1 (Regex) -> Regex
-                        //   Type 2 (RootDomain) -> Domain (suffix)
-                        //   Type 3 (Full) -> Full
-
-                        // So I should map "domain:" to Type::RootDomain (2)!
-
-                        // I need to Fix the generator first? Or handle it here?
-                        // If generated as Plain (0), I should treat as Keyword?
-                        // But I mapped "domain:" to Plain in generator. This is a BUG in my generator if I want domain behavior.
-                        // "domain:google.com" means *.google.com + google.com.
-                        // Plain means "has string google.com".
-
-                        // I will FIX the generator to use Type::RootDomain(2) for "domain:".
-                        // And Type::Plain(0) for "keyword:".
-
-                        // But first, let's write this plugin assuming standard types.
-                        // Type::Full(3) -> Exact
-                        // Type::RootDomain(2) -> Suffix
-                        // Type::Plain(0) -> Keyword? (I'll implement as suffix for now if uncertain, or just keyword)
-                    }
-                    _ => {}
-                }
-
-                // For now, let's implement based on what I WILL fix in generator:
-                // Full -> exact
-                // RootDomain -> suffix
-
-                let type_ = domain.r#type;
-                match type_ {
+                    // Plain: keyword, matches names containing the value.
+                    0 => keyword_matches.push(domain.value),
+                    1 => regex_matches.push(domain_regex(&domain.value)?),
+                    // RootDomain: the name and its subdomains.
+                    2 => suffix_matches.push(domain.value),
+                    // Full: exactly the name.
                     3 => {
-                        // Full
                         exact_matches.insert(domain.value);
                     }
-                    2 => {
-                        // RootDomain (Suffix)
-                        suffix_matches.push(domain.value);
-                    }
-                    0 => {
-                        // Plain (Keyword)
-                        // If I generated "domain:" as Plain, I better treat it as suffix for compatibility with my previous step error?
-                        // No, I will fix generator.
-                        // So Plain is Keyword.
-                        // I won't implement keyword matching for now or treat as suffix?
-                        // "keyword: google" -> matches "google.com", "agoogleb.com"
-                        // I'll ignore Plain for now or treat as Suffix if I can't differentiate?
-                        // Let's treat Plain as Suffix for now as fallback?
-                        suffix_matches.push(domain.value);
-                    }
                     _ => {}
                 }
             }
@@ -163,6 +88,7 @@ impl GeositePlugin {
         Ok(Self {
             exact_matches,
             suffix_matches,
+            keyword_matches,
             regex_matches,
         })
     }
@@ -185,7 +111,10 @@ impl DomainSet for GeositePlugin {
                 }
             }
         }
-        false
+        self.keyword_matches
+            .iter()
+            .any(|k| domain.contains(k.as_str()))
+            || self.regex_matches.iter().any(|re| re.is_match(domain))
     }
 }
 
@@ -203,3 +132,44 @@ impl Plugin for GeositePlugin {
         Some(self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proto::domain::Type;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_keyword_matches_substring() {
+        let domain = |type_: Type, value: &str| proto::Domain {
+            r#type: type_ as i32,
+            value: value.to_string(),
+            attribute: vec![],
+        };
+        let list = proto::GeoSiteList {
+            entry: vec![proto::GeoSite {
+                country_code: "TEST".to_string(),
+                domain: vec![
+                    domain(Type::Plain, "google"),
+                    domain(Type::RootDomain, "example.com"),
+                    domain(Type::Full, "www.example.org"),
+                ],
+            }],
+        };
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(&list.encode_to_vec()).unwrap();
+        let yaml = format!("file: \"{}\"\ncode: test", file.path().display());
+        let plugin = GeositePlugin::new(Some(&serde_yaml::from_str(&yaml).unwrap())).unwrap();
+
+        assert!(plugin.contains("agooglebcom"));
+        assert!(plugin.contains("www.google.co.uk"));
+        // The keyword is not a suffix or exact rule.
+        assert!(plugin.suffix_matches.iter().all(|s| s != "google"));
+        assert!(!plugin.exact_matches.contains("google"));
+        assert!(plugin.contains("mail.example.com"));
+        assert!(!plugin.contains("notexample.com"));
+        assert!(plugin.contains("www.example.org"));
+        assert!(!plugin.contains("example.org"));
+    }
+}
@@ -1,8 +1,11 @@
 use super::{Context, Plugin};
-use anyhow::Result;
+use crate::dns::{self, canonical_name, decode_hex};
+use anyhow::{anyhow, bail, Context as AnyhowContext, Result};
 use async_trait::async_trait;
-use hickory_proto::op::Message;
-use hickory_proto::rr::{RData, Record, RecordType};
+use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::rr::rdata::caa::KeyValue;
+use hickory_proto::rr::rdata::{CAA, MX, PTR, TLSA, TXT};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
 use serde::Deserialize;
 use std::collections::HashMap;
 use std::fs::File;
@@ -17,10 +20,167 @@ struct HostsConfig {
     files: Vec<String>,
     #[serde(default)]
     hosts: HashMap<String, String>,
+    #[serde(default)]
+    records: Vec<RecordConfig>,
+    /// TTL of answers; the server's `default_ttl` if unset.
+    #[serde(default)]
+    ttl: Option<u32>,
+}
+
+/// A typed record beyond plain addresses, answered only for its own query type
+/// (and ANY).
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "UPPERCASE")]
+enum RecordConfig {
+    Caa {
+        name: String,
+        #[serde(default)]
+        ttl: Option<u32>,
+        #[serde(default)]
+        critical: bool,
+        /// `issue`, `issuewild` or `iodef`.
+        tag: String,
+        /// Issuer domain with optional `; key=value` parameters, or the iodef URL.
+        value: String,
+    },
+    Tlsa {
+        name: String,
+        #[serde(default)]
+        ttl: Option<u32>,
+        usage: u8,
+        selector: u8,
+        matching: u8,
+        /// Certificate association data, hex encoded.
+        data: String,
+    },
+    Txt {
+        name: String,
+        #[serde(default)]
+        ttl: Option<u32>,
+        text: String,
+    },
+    Mx {
+        name: String,
+        #[serde(default)]
+        ttl: Option<u32>,
+        preference: u16,
+        exchange: String,
+    },
+    Ptr {
+        /// Reverse name (`4.3.2.1.in-addr.arpa`) or the address itself.
+        name: String,
+        #[serde(default)]
+        ttl: Option<u32>,
+        target: String,
+    },
+}
+
+impl RecordConfig {
+    fn into_record(self) -> Result<(String, Record, Option<u32>)> {
+        let (name, ttl, rdata) = match self {
+            RecordConfig::Caa {
+                name,
+                ttl,
+                critical,
+                tag,
+                value,
+            } => (name, ttl, RData::CAA(caa_rdata(critical, &tag, &value)?)),
+            RecordConfig::Tlsa {
+                name,
+                ttl,
+                usage,
+                selector,
+                matching,
+                data,
+            } => {
+                let data =
+                    decode_hex(&data).with_context(|| format!("Invalid TLSA data for {}", name))?;
+                let tlsa = TLSA::new(usage.into(), selector.into(), matching.into(), data);
+                (name, ttl, RData::TLSA(tlsa))
+            }
+            RecordConfig::Txt { name, ttl, text } => (name, ttl, RData::TXT(TXT::new(vec![text]))),
+            RecordConfig::Mx {
+                name,
+                ttl,
+                preference,
+                exchange,
+            } => {
+                let mut exchange = Name::from_str(&exchange)
+                    .with_context(|| format!("Invalid MX exchange: {}", exchange))?;
+                exchange.set_fqdn(true);
+                (name, ttl, RData::MX(MX::new(preference, exchange)))
+            }
+            RecordConfig::Ptr { name, ttl, target } => {
+                let mut target = Name::from_str(&target)
+                    .with_context(|| format!("Invalid PTR target: {}", target))?;
+                target.set_fqdn(true);
+                let name = match IpAddr::from_str(&name) {
+                    Ok(ip) => Name::from(ip).to_ascii(),
+                    Err(_) => name,
+                };
+                (name, ttl, RData::PTR(PTR(target)))
+            }
+        };
+        let owner = Name::from_str(&name).with_context(|| format!("Invalid name: {}", name))?;
+        Ok((
+            canonical_name(&name),
+            Record::from_rdata(owner, 0, rdata),
+            ttl,
+        ))
+    }
+}
+
+fn caa_rdata(critical: bool, tag: &str, value: &str) -> Result<CAA> {
+    match tag.to_ascii_lowercase().as_str() {
+        "iodef" => {
+            let url = value
+                .parse()
+                .with_context(|| format!("Invalid CAA iodef URL: {}", value))?;
+            Ok(CAA::new_iodef(critical, url))
+        }
+        tag @ ("issue" | "issuewild") => {
+            let mut parts = value.split(';').map(str::trim);
+            let issuer = match parts.next() {
+                Some("") | None => None,
+                Some(domain) => Some(
+                    Name::from_str(domain)
+                        .with_context(|| format!("Invalid CAA issuer: {}", domain))?,
+                ),
+            };
+            let params = parts
+                .filter(|p| !p.is_empty())
+                .map(|p| {
+                    p.split_once('=')
+                        .map(|(k, v)| KeyValue::new(k.trim(), v.trim()))
+                        .ok_or_else(|| anyhow!("Invalid CAA parameter: {}", p))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            Ok(if tag == "issue" {
+                CAA::new_issue(critical, issuer, params)
+            } else {
+                CAA::new_issuewild(critical, issuer, params)
+            })
+        }
+        other => bail!("Unsupported CAA tag: {}", other),
+    }
+}
+
+/// A or AAAA data for an address, by its family.
+fn address_rdata(ip: &IpAddr) -> RData {
+    match ip {
+        IpAddr::V4(ipv4) => RData::A(hickory_proto::rr::rdata::A(*ipv4)),
+        IpAddr::V6(ipv6) => RData::AAAA(hickory_proto::rr::rdata::AAAA(*ipv6)),
+    }
 }
 
 pub struct Hosts {
-    mappings: HashMap<String, IpAddr>,
+    /// Addresses of each name; a name may have both IPv4 and IPv6 ones.
+    mappings: HashMap<String, Vec<IpAddr>>,
+    /// `*.<suffix>` entries by suffix, used when no exact mapping matches.
+    wildcards: HashMap<String, Vec<IpAddr>>,
+    /// Typed records (CAA, TLSA) and their own TTL, by canonical owner name.
+    records: HashMap<String, Vec<(Record, Option<u32>)>>,
+    ttl: Option<u32>,
 }
 
 impl Hosts {
@@ -31,23 +191,34 @@ impl Hosts {
             HostsConfig {
                 files: vec![],
                 hosts: HashMap::new(),
+                records: vec![],
+                ttl: None,
             }
         };
 
-        let mut mappings = HashMap::new();
+        let mut mappings: HashMap<String, Vec<IpAddr>> = HashMap::new();
+        let mut wildcards: HashMap<String, Vec<IpAddr>> = HashMap::new();
+        let mut insert = |domain: &str, ip: IpAddr| {
+            let domain = canonical_name(domain);
+            let ips = match domain.strip_prefix("*.") {
+                Some(suffix) => wildcards.entry(suffix.to_string()).or_default(),
+                None => mappings.entry(domain).or_default(),
+            };
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        };
 
         // Load from files
         for path in config.files {
             if let Ok(file) = File::open(&path) {
                 let reader = BufReader::new(file);
-                for line in reader.lines() {
-                    if let Ok(l) = line {
-                        let parts: Vec<&str> = l.split_whitespace().collect();
-                        if parts.len() >= 2 {
-                            if let Ok(ip) = IpAddr::from_str(parts[0]) {
-                                for domain in &parts[1..] {
-                                    mappings.insert(domain.to_string(), ip);
-                                }
+                for l in reader.lines().map_while(Result::ok) {
+                    let parts: Vec<&str> = l.split_whitespace().collect();
+                    if parts.len() >= 2 {
+                        if let Ok(ip) = IpAddr::from_str(parts[0]) {
+                            for domain in &parts[1..] {
+                                insert(domain, ip);
                             }
                         }
                     }
@@ -57,16 +228,45 @@ impl Hosts {
             }
         }
 
-        // Load from inline config
-        for (domain, ip_str) in config.hosts {
-            if let Ok(ip) = IpAddr::from_str(&ip_str) {
-                mappings.insert(domain, ip);
-            } else {
-                warn!("Invalid IP in hosts config: {}", ip_str);
+        // Load from inline config; a name may list several addresses.
+        for (domain, ip_list) in config.hosts {
+            for ip_str in ip_list.split([',', ' ']).filter(|s| !s.is_empty()) {
+                if let Ok(ip) = IpAddr::from_str(ip_str) {
+                    insert(&domain, ip);
+                } else {
+                    warn!("Invalid IP in hosts config: {}", ip_str);
+                }
             }
         }
 
-        Ok(Self { mappings })
+        let mut records: HashMap<String, Vec<(Record, Option<u32>)>> = HashMap::new();
+        for entry in config.records {
+            let (key, record, ttl) = entry.into_record()?;
+            records.entry(key).or_default().push((record, ttl));
+        }
+
+        Ok(Self {
+            mappings,
+            wildcards,
+            records,
+            ttl: config.ttl,
+        })
+    }
+
+    /// Addresses of `name` (canonical): its exact mapping, else the wildcard of
+    /// its closest parent. A wildcard does not match its suffix itself.
+    fn lookup(&self, name: &str) -> Option<&[IpAddr]> {
+        if let Some(ips) = self.mappings.get(name) {
+            return Some(ips);
+        }
+        let mut rest = name;
+        while let Some((_, parent)) = rest.split_once('.') {
+            if let Some(ips) = self.wildcards.get(parent) {
+                return Some(ips);
+            }
+            rest = parent;
+        }
+        None
     }
 }
 
@@ -83,9 +283,39 @@ impl Plugin for Hosts {
 
         if let Some(query) = ctx.request.query() {
             let name = query.name().to_string();
-            let name_clean = name.trim_end_matches('.');
+            let name_clean = canonical_name(&name);
+            let ttl = self.ttl.unwrap_or(ctx.default_ttl);
+
+            // ANY gets every record of the name, its address included.
+            let any = query.query_type() == RecordType::ANY;
+            let mut typed: Vec<Record> = self
+                .records
+                .get(&name_clean)
+                .into_iter()
+                .flatten()
+                .filter(|(r, _)| any || r.record_type() == query.query_type())
+                .map(|(r, record_ttl)| {
+                    let mut r = r.clone();
+                    r.set_name(query.name().clone())
+                        .set_ttl(record_ttl.unwrap_or(ttl));
+                    r
+                })
+                .collect();
+            if let Some(ips) = self.lookup(&name_clean).filter(|_| any) {
+                let addresses = ips
+                    .iter()
+                    .map(|ip| Record::from_rdata(query.name().clone(), ttl, address_rdata(ip)));
+                typed.splice(0..0, addresses);
+            }
+            if !typed.is_empty() {
+                let mut response = dns::empty_response(&ctx.request, ResponseCode::NoError);
+                response.insert_answers(typed);
+                ctx.response = Some(response);
+                info!("Hosts {} record for {}", query.query_type(), name);
+                return Ok(());
+            }
 
-            if let Some(ip) = self.mappings.get(name_clean) {
+            if let Some(ips) = self.lookup(&name_clean) {
                 let mut response = Message::new();
                 response.set_id(ctx.request.id());
                 response.set_message_type(hickory_proto::op::MessageType::Response);
@@ -95,17 +325,23 @@ impl Plugin for Hosts {
                 response.set_response_code(hickory_proto::op::ResponseCode::NoError);
                 response.add_query(query.clone());
 
-                let rdata = match ip {
-                    IpAddr::V4(ipv4) => RData::A(hickory_proto::rr::rdata::A(*ipv4)),
-                    IpAddr::V6(ipv6) => RData::AAAA(hickory_proto::rr::rdata::AAAA(*ipv6)),
-                };
-
-                let mut record = Record::with(query.name().clone(), RecordType::A, 60);
-                record.set_data(Some(rdata));
-                response.add_answer(record);
+                // Only addresses of the asked family; without any the answer
+                // is NODATA, as the name itself exists.
+                let matching: Vec<&IpAddr> = ips
+                    .iter()
+                    .filter(|ip| match query.query_type() {
+                        RecordType::A => ip.is_ipv4(),
+                        RecordType::AAAA => ip.is_ipv6(),
+                        _ => false,
+                    })
+                    .collect();
+                for ip in &matching {
+                    let record = Record::from_rdata(query.name().clone(), ttl, address_rdata(ip));
+                    response.add_answer(record);
+                }
 
                 ctx.response = Some(response);
-                info!("Hosts match for {}: {}", name, ip);
+                info!("Hosts match for {}: {:?}", name, matching);
             }
         }
         Ok(())
@@ -178,4 +414,219 @@ mod tests {
         hosts.next(&mut ctx).await.unwrap();
         assert!(ctx.response.is_none());
     }
+
+    #[tokio::test]
+    async fn test_hosts_wildcard() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "10.0.0.2 *.staging.dev.local").unwrap();
+        let yaml = format!(
+            "files: [\"{}\"]\nhosts:\n  \"*.dev.local\": \"10.0.0.1\"\n  db.dev.local: \"10.0.0.9\"",
+            file.path().display()
+        );
+        let config: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        let hosts = Hosts::new(Some(&config)).unwrap();
+
+        let resolve = |name: &str| {
+            let mut ctx = make_ctx(name);
+            let hosts = &hosts;
+            async move {
+                hosts.next(&mut ctx).await.unwrap();
+                ctx.response
+                    .map(|r| r.answers()[0].data().unwrap().to_string())
+            }
+        };
+        assert_eq!(resolve("api.dev.local.").await.as_deref(), Some("10.0.0.1"));
+        assert_eq!(resolve("a.b.Dev.Local.").await.as_deref(), Some("10.0.0.1"));
+        // Exact entries and closer wildcards win.
+        assert_eq!(resolve("db.dev.local.").await.as_deref(), Some("10.0.0.9"));
+        assert_eq!(
+            resolve("web.staging.dev.local.").await.as_deref(),
+            Some("10.0.0.2")
+        );
+        assert_eq!(resolve("dev.local.").await, None);
+        assert_eq!(resolve("api.dev.example.").await, None);
+    }
+
+    #[tokio::test]
+    async fn test_hosts_both_families() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "192.168.1.5 nas.lan").unwrap();
+        writeln!(file, "fd00::5 nas.lan").unwrap();
+        let yaml = format!(
+            "files: [\"{}\"]\nhosts:\n  v6only.lan: \"fd00::6\"\n  dual.lan: \"10.0.0.7, fd00::7\"",
+            file.path().display()
+        );
+        let config: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        let hosts = Hosts::new(Some(&config)).unwrap();
+
+        let resolve = |name: &str, qtype: RecordType| {
+            let mut ctx = make_ctx(name);
+            ctx.request.queries_mut()[0].set_query_type(qtype);
+            let hosts = &hosts;
+            async move {
+                hosts.next(&mut ctx).await.unwrap();
+                ctx.response.unwrap().answers().to_vec()
+            }
+        };
+
+        for (name, ip) in [("nas.lan.", "fd00::5"), ("dual.lan.", "fd00::7")] {
+            let answers = resolve(name, RecordType::AAAA).await;
+            assert_eq!(answers.len(), 1, "{}", name);
+            assert_eq!(answers[0].record_type(), RecordType::AAAA);
+            assert_eq!(answers[0].data().unwrap().to_string(), ip);
+        }
+        let answers = resolve("nas.lan.", RecordType::A).await;
+        assert_eq!(answers.len(), 1);
+        assert_eq!(answers[0].record_type(), RecordType::A);
+        assert_eq!(answers[0].data().unwrap().to_string(), "192.168.1.5");
+
+        // An IPv6-only name is NODATA for A, never an A record with AAAA data.
+        assert!(resolve("v6only.lan.", RecordType::A).await.is_empty());
+        assert_eq!(resolve("nas.lan.", RecordType::ANY).await.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_hosts_default_ttl() {
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("hosts:\n  nas.lan: \"192.168.1.5\"").unwrap();
+        let hosts = Hosts::new(Some(&config)).unwrap();
+        let mut ctx = make_ctx("nas.lan.");
+        ctx.default_ttl = 300;
+        hosts.next(&mut ctx).await.unwrap();
+        assert_eq!(ctx.response.unwrap().answers()[0].ttl(), 300);
+
+        // The plugin's own ttl wins over the server default.
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("ttl: 5\nhosts:\n  nas.lan: \"192.168.1.5\"").unwrap();
+        let hosts = Hosts::new(Some(&config)).unwrap();
+        let mut ctx = make_ctx("nas.lan.");
+        ctx.default_ttl = 300;
+        hosts.next(&mut ctx).await.unwrap();
+        assert_eq!(ctx.response.unwrap().answers()[0].ttl(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_hosts_caa_record() {
+        let config: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+            records:
+              - name: corp.lan
+                type: CAA
+                tag: issue
+                value: "ca.corp.lan; account=42"
+              - name: _443._tcp.corp.lan
+                type: TLSA
+                usage: 3
+                selector: 1
+                matching: 1
+                data: "0a1B2c"
+            "#,
+        )
+        .unwrap();
+        let hosts = Hosts::new(Some(&config)).unwrap();
+
+        let mut ctx = make_ctx("Corp.Lan.");
+        ctx.request.queries_mut()[0].set_query_type(RecordType::CAA);
+        hosts.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].name(), response.queries()[0].name());
+        match response.answers()[0].data() {
+            Some(RData::CAA(caa)) => {
+                assert_eq!(caa.tag(), &hickory_proto::rr::rdata::caa::Property::Issue);
+                assert!(!caa.issuer_critical());
+            }
+            other => panic!("Expected CAA record, got {:?}", other),
+        }
+
+        // Other query types for the same name fall through.
+        let mut ctx = make_ctx("corp.lan.");
+        hosts.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.is_none());
+
+        let mut ctx = make_ctx("_443._tcp.corp.lan.");
+        ctx.request.queries_mut()[0].set_query_type(RecordType::TLSA);
+        hosts.next(&mut ctx).await.unwrap();
+        match ctx.response.unwrap().answers()[0].data() {
+            Some(RData::TLSA(tlsa)) => assert_eq!(tlsa.cert_data(), &[0x0a, 0x1b, 0x2c]),
+            other => panic!("Expected TLSA record, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hosts_any_query() {
+        let config: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+            hosts:
+              mail.lan: "192.168.1.25"
+            records:
+              - name: mail.lan
+                type: TXT
+                text: "v=spf1 mx -all"
+              - name: mail.lan
+                type: MX
+                preference: 10
+                exchange: mail.lan.
+            "#,
+        )
+        .unwrap();
+        let hosts = Hosts::new(Some(&config)).unwrap();
+
+        let mut ctx = make_ctx("mail.lan.");
+        ctx.request.queries_mut()[0].set_query_type(RecordType::ANY);
+        hosts.next(&mut ctx).await.unwrap();
+        let types: Vec<RecordType> = ctx
+            .response
+            .unwrap()
+            .answers()
+            .iter()
+            .map(|r| r.record_type())
+            .collect();
+        assert_eq!(types, [RecordType::A, RecordType::TXT, RecordType::MX]);
+
+        // A plain TXT query only gets the TXT record.
+        let mut ctx = make_ctx("mail.lan.");
+        ctx.request.queries_mut()[0].set_query_type(RecordType::TXT);
+        hosts.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.answers().len(), 1);
+        match response.answers()[0].data() {
+            Some(RData::TXT(txt)) => assert_eq!(txt.to_string(), "v=spf1 mx -all"),
+            other => panic!("Expected TXT record, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hosts_ptr_record() {
+        let config: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+            records:
+              - name: 4.3.2.1.in-addr.arpa
+                type: PTR
+                target: host.lan
+              - name: "fd00::5"
+                type: PTR
+                target: nas.lan.
+            "#,
+        )
+        .unwrap();
+        let hosts = Hosts::new(Some(&config)).unwrap();
+
+        for (name, target) in [
+            ("4.3.2.1.in-addr.arpa.", "host.lan."),
+            (
+                "5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.d.f.ip6.arpa.",
+                "nas.lan.",
+            ),
+        ] {
+            let mut ctx = make_ctx(name);
+            ctx.request.queries_mut()[0].set_query_type(RecordType::PTR);
+            hosts.next(&mut ctx).await.unwrap();
+            let response = ctx.response.unwrap();
+            match response.answers()[0].data() {
+                Some(RData::PTR(ptr)) => assert_eq!(ptr.0.to_ascii(), target),
+                other => panic!("Expected PTR record, got {:?}", other),
+            }
+        }
+    }
 }
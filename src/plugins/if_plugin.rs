@@ -1,4 +1,4 @@
-use super::{Context, Plugin, SharedPlugin};
+use super::{exec, Context, Plugin, SharedPlugin};
 use anyhow::Result;
 use async_trait::async_trait;
 use serde::Deserialize;
@@ -90,7 +90,7 @@ impl Plugin for IfPlugin {
         };
 
         for plugin in plugins {
-            plugin.next(ctx).await?;
+            exec(plugin, ctx).await?;
             if ctx.abort {
                 break;
             }
@@ -105,7 +105,7 @@ mod tests {
     use super::super::Condition;
     use super::*; // Condition is in parent module (plugins/mod.rs) re-exported or accessible via super?
                   // In src/plugins/if_plugin.rs, super refers to src/plugins/mod.rs
-                  // Check line 1: use super::{Context, Plugin, SharedPlugin};
+                  // Check line 1: use super::{exec, Context, Plugin, SharedPlugin};
                   // So usually `use super::Condition` works if it's there.
 
     use std::sync::{Arc, Mutex};
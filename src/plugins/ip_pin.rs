@@ -0,0 +1,221 @@
+use super::{exec, Context, Plugin, SharedPlugin};
+use crate::dns::{canonical_name, empty_response};
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::{Record, RecordType};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+fn default_duration() -> u64 {
+    3600
+}
+
+fn default_max_entries() -> usize {
+    10000
+}
+
+#[derive(Deserialize)]
+struct IpPinConfig {
+    /// Seconds a name keeps the addresses it first resolved to.
+    #[serde(default = "default_duration")]
+    duration: u64,
+    /// Upper bound on pinned (name, type) keys.
+    #[serde(default = "default_max_entries")]
+    max_entries: usize,
+    #[serde(default)]
+    exec: Vec<String>,
+}
+
+struct Pin {
+    /// Answer section of the first response, CNAMEs included.
+    answers: Vec<Record>,
+    expires: Instant,
+}
+
+/// Pins the addresses a name first resolves to for `duration`, answering A and
+/// AAAA queries from the pin without running `exec`, so apps that break when a
+/// CDN rotates its addresses keep seeing the same ones.
+pub struct IpPin {
+    duration: Duration,
+    max_entries: usize,
+    pins: Mutex<HashMap<(String, RecordType), Pin>>,
+    plugins: Vec<SharedPlugin>,
+}
+
+impl IpPin {
+    pub fn new(
+        config: Option<&serde_yaml::Value>,
+        registry: &HashMap<String, SharedPlugin>,
+    ) -> Result<Self> {
+        let config: IpPinConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            return Err(anyhow::anyhow!("IpPin requires config"));
+        };
+
+        let mut plugins = Vec::new();
+        for tag in config.exec {
+            let p = registry
+                .get(&tag)
+                .ok_or_else(|| anyhow::anyhow!("Plugin not found: {}", tag))?;
+            plugins.push(p.clone());
+        }
+
+        Ok(Self {
+            duration: Duration::from_secs(config.duration),
+            max_entries: config.max_entries.max(1),
+            pins: Mutex::new(HashMap::new()),
+            plugins,
+        })
+    }
+
+    /// Pinned answers for `key` with TTLs capped to the time the pin has left.
+    fn lookup(&self, key: &(String, RecordType)) -> Option<Vec<Record>> {
+        let now = Instant::now();
+        let pins = self.pins.lock().unwrap();
+        let pin = pins.get(key).filter(|p| p.expires > now)?;
+        let remaining = pin.expires.duration_since(now).as_secs().max(1);
+        let remaining = u32::try_from(remaining).unwrap_or(u32::MAX);
+        Some(
+            pin.answers
+                .iter()
+                .map(|r| {
+                    let mut r = r.clone();
+                    r.set_ttl(r.ttl().min(remaining));
+                    r
+                })
+                .collect(),
+        )
+    }
+
+    fn pin(&self, key: (String, RecordType), answers: Vec<Record>) {
+        let now = Instant::now();
+        let mut pins = self.pins.lock().unwrap();
+        if pins.len() >= self.max_entries {
+            pins.retain(|_, p| p.expires > now);
+        }
+        if pins.len() < self.max_entries {
+            pins.insert(
+                key,
+                Pin {
+                    answers,
+                    expires: now + self.duration,
+                },
+            );
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for IpPin {
+    fn name(&self) -> &str {
+        "ip_pin"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        let key = ctx
+            .request
+            .query()
+            .filter(|q| matches!(q.query_type(), RecordType::A | RecordType::AAAA))
+            .map(|q| (canonical_name(&q.name().to_ascii()), q.query_type()));
+
+        if let Some(key) = &key {
+            if let Some(answers) = self.lookup(key) {
+                debug!("Answering {} {} from its pinned addresses", key.0, key.1);
+                let mut response = empty_response(&ctx.request, ResponseCode::NoError);
+                response.insert_answers(answers);
+                ctx.response = Some(response);
+                return Ok(());
+            }
+        }
+
+        for plugin in &self.plugins {
+            exec(plugin, ctx).await?;
+            if ctx.response.is_some() || ctx.abort {
+                break;
+            }
+        }
+
+        if let (Some(key), Some(response)) = (key, &ctx.response) {
+            let has_address = response.answers().iter().any(|r| r.record_type() == key.1);
+            if response.response_code() == ResponseCode::NoError && has_address {
+                self.pin(key, response.answers().to_vec());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::{Name, RData};
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::atomic::{AtomicU8, Ordering};
+    use std::sync::{Arc, RwLock};
+
+    /// Returns a different address on every call, like a rotating CDN.
+    struct Rotating(AtomicU8);
+
+    #[async_trait]
+    impl Plugin for Rotating {
+        fn name(&self) -> &str {
+            "rotating"
+        }
+
+        async fn next(&self, ctx: &mut Context) -> Result<()> {
+            let last = self.0.fetch_add(1, Ordering::SeqCst);
+            let mut response = empty_response(&ctx.request, ResponseCode::NoError);
+            response.add_answer(Record::from_rdata(
+                ctx.request.query().unwrap().name().clone(),
+                300,
+                RData::A(Ipv4Addr::new(192, 0, 2, last).into()),
+            ));
+            ctx.response = Some(response);
+            Ok(())
+        }
+    }
+
+    fn make_ctx(name: &str, qtype: RecordType) -> Context {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+        Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    async fn resolve(plugin: &IpPin, name: &str) -> RData {
+        let mut ctx = make_ctx(name, RecordType::A);
+        plugin.next(&mut ctx).await.unwrap();
+        ctx.response.unwrap().answers()[0].data().unwrap().clone()
+    }
+
+    #[tokio::test]
+    async fn test_pinned_answer_kept() {
+        let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+        registry.insert("upstream".to_string(), Arc::new(Rotating(AtomicU8::new(1))));
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("duration: 60\nexec: [upstream]").unwrap();
+        let plugin = IpPin::new(Some(&config), &registry).unwrap();
+
+        let first = resolve(&plugin, "cdn.example.").await;
+        assert_eq!(first, RData::A(Ipv4Addr::new(192, 0, 2, 1).into()));
+        for name in ["cdn.example.", "CDN.Example."] {
+            assert_eq!(resolve(&plugin, name).await, first);
+        }
+        // Pins are per name; the TTL never outlives the pin.
+        let other = resolve(&plugin, "other.example.").await;
+        assert_eq!(other, RData::A(Ipv4Addr::new(192, 0, 2, 2).into()));
+        let mut ctx = make_ctx("cdn.example.", RecordType::A);
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.unwrap().answers()[0].ttl() <= 60);
+    }
+}
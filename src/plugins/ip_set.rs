@@ -31,17 +31,15 @@ impl IpSetPlugin {
         for path in config.files {
             if let Ok(file) = File::open(&path) {
                 let reader = BufReader::new(file);
-                for line in reader.lines() {
-                    if let Ok(l) = line {
-                        let l = l.trim();
-                        if !l.is_empty() && !l.starts_with('#') {
-                            if let Ok(net) = IpNet::from_str(l) {
-                                cidrs.push(net);
-                            } else if let Ok(ip) = IpAddr::from_str(l) {
-                                cidrs.push(IpNet::from(ip));
-                            } else {
-                                warn!("Invalid IP/CIDR in {}: {}", path, l);
-                            }
+                for l in reader.lines().map_while(Result::ok) {
+                    let l = l.trim();
+                    if !l.is_empty() && !l.starts_with('#') {
+                        if let Ok(net) = IpNet::from_str(l) {
+                            cidrs.push(net);
+                        } else if let Ok(ip) = IpAddr::from_str(l) {
+                            cidrs.push(IpNet::from(ip));
+                        } else {
+                            warn!("Invalid IP/CIDR in {}: {}", path, l);
                         }
                     }
                 }
@@ -1,9 +1,15 @@
-use super::{Condition, Context, Plugin, SharedPlugin};
+use super::{exec, Condition, Context, Plugin, SharedPlugin};
+use crate::dns::{canonical_name, domain_regex, is_subdomain};
 use anyhow::Result;
 use async_trait::async_trait;
+use hickory_proto::rr::{RData, RecordType};
+use ipnet::IpNet;
+use regex::Regex;
 use serde::Deserialize;
 use std::collections::HashMap;
-use tracing::{info, warn};
+use std::net::IpAddr;
+use std::str::FromStr;
+use tracing::info;
 
 #[derive(Deserialize)]
 struct MatcherConfig {
@@ -11,14 +17,28 @@ struct MatcherConfig {
     domain: Vec<String>,
     #[serde(default)]
     client_ip: Vec<String>,
+    /// Addresses or sets matched against the A/AAAA answers of `ctx.response`;
+    /// only useful after a plugin (e.g. `forward`) has produced a response.
+    #[serde(default)]
+    response_ip: Vec<String>,
+    /// Query types (e.g. `AAAA`, `HTTPS`) required on top of the other
+    /// criteria; any type if empty.
+    #[serde(default)]
+    qtype: Vec<String>,
     #[serde(default)]
     exec: Vec<String>,
 }
 
 pub struct Matcher {
     domains: Vec<String>,
+    /// `regex:` entries of `domain`, matched against the canonical name.
+    domain_regexes: Vec<Regex>,
+    qtypes: Vec<RecordType>,
     domain_providers: Vec<SharedPlugin>,
+    client_nets: Vec<IpNet>,
     ip_providers: Vec<SharedPlugin>,
+    response_nets: Vec<IpNet>,
+    response_ip_providers: Vec<SharedPlugin>,
     plugins: Vec<SharedPlugin>,
 }
 
@@ -33,6 +53,8 @@ impl Matcher {
             MatcherConfig {
                 domain: vec![],
                 client_ip: vec![],
+                response_ip: vec![],
+                qtype: vec![],
                 exec: vec![],
             }
         };
@@ -46,11 +68,11 @@ impl Matcher {
         }
 
         let mut direct_domains = Vec::new();
+        let mut domain_regexes = Vec::new();
         let mut domain_providers = Vec::new();
 
         for d in config.domain {
-            if d.starts_with("provider:") {
-                let tag = &d["provider:".len()..];
+            if let Some(tag) = d.strip_prefix("provider:") {
                 let p = registry
                     .get(tag)
                     .ok_or_else(|| anyhow::anyhow!("Provider plugin not found: {}", tag))?;
@@ -59,55 +81,78 @@ impl Matcher {
                 } else {
                     return Err(anyhow::anyhow!("Plugin {} is not a DomainSet", tag));
                 }
+            } else if let Some(pattern) = d.strip_prefix("regex:") {
+                domain_regexes.push(domain_regex(pattern)?);
             } else {
-                direct_domains.push(d);
+                direct_domains.push(canonical_name(&d));
             }
         }
 
-        let mut ip_providers = Vec::new();
-        for ip_ref in config.client_ip {
-            if ip_ref.starts_with("provider:") {
-                let tag = &ip_ref["provider:".len()..];
-                let p = registry
-                    .get(tag)
-                    .ok_or_else(|| anyhow::anyhow!("Provider plugin not found: {}", tag))?;
-                if p.as_ip_set().is_some() {
-                    ip_providers.push(p.clone());
-                } else {
-                    return Err(anyhow::anyhow!("Plugin {} is not an IpSet", tag));
-                }
-            } else {
-                warn!(
-                    "Direct IP/CIDR matching not yet implemented in Matcher, ignoring: {}",
-                    ip_ref
-                );
-            }
-        }
+        let (client_nets, ip_providers) = parse_ip_refs(config.client_ip, registry)?;
+        let (response_nets, response_ip_providers) = parse_ip_refs(config.response_ip, registry)?;
+
+        let qtypes = config
+            .qtype
+            .iter()
+            .map(|t| {
+                RecordType::from_str(&t.to_ascii_uppercase())
+                    .map_err(|_| anyhow::anyhow!("Unknown query type: {}", t))
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         Ok(Self {
             domains: direct_domains,
+            domain_regexes,
+            qtypes,
             domain_providers,
+            client_nets,
             ip_providers,
+            response_nets,
+            response_ip_providers,
             plugins,
         })
     }
 
     fn matches(&self, ctx: &Context) -> bool {
+        // Query type narrows the other criteria, or matches on its own.
+        if !self.qtypes.is_empty() {
+            let qtype = ctx.request.query().map(|q| q.query_type());
+            if !qtype.is_some_and(|t| self.qtypes.contains(&t)) {
+                return false;
+            }
+            if self.domains.is_empty()
+                && self.domain_regexes.is_empty()
+                && self.domain_providers.is_empty()
+                && self.client_nets.is_empty()
+                && self.ip_providers.is_empty()
+                && self.response_nets.is_empty()
+                && self.response_ip_providers.is_empty()
+            {
+                return true;
+            }
+        }
+
         // Match Domain
-        if !self.domains.is_empty() || !self.domain_providers.is_empty() {
+        if !self.domains.is_empty()
+            || !self.domain_regexes.is_empty()
+            || !self.domain_providers.is_empty()
+        {
             if let Some(query) = ctx.request.query() {
-                let name = query.name().to_string();
-                let name_clean = name.trim_end_matches('.');
+                let name = canonical_name(&query.name().to_string());
 
                 for d in &self.domains {
-                    if name_clean == d || name_clean.ends_with(&format!(".{}", d)) {
+                    if is_subdomain(&name, d) {
                         return true;
                     }
                 }
 
+                if self.domain_regexes.iter().any(|re| re.is_match(&name)) {
+                    return true;
+                }
+
                 for p in &self.domain_providers {
                     if let Some(ds) = p.as_domain_set() {
-                        if ds.contains(name_clean) {
+                        if ds.contains(&name) {
                             return true;
                         }
                     }
@@ -116,8 +161,11 @@ impl Matcher {
         }
 
         // Match Client IP
+        let ip = ctx.client_addr.ip();
+        if self.client_nets.iter().any(|net| net.contains(&ip)) {
+            return true;
+        }
         if !self.ip_providers.is_empty() {
-            let ip = ctx.client_addr.ip();
             for p in &self.ip_providers {
                 if let Some(is) = p.as_ip_set() {
                     if is.contains(ip) {
@@ -127,10 +175,59 @@ impl Matcher {
             }
         }
 
+        // Match answer IPs
+        if !self.response_nets.is_empty() || !self.response_ip_providers.is_empty() {
+            if let Some(response) = &ctx.response {
+                let answer_ips = response.answers().iter().filter_map(|r| match r.data() {
+                    Some(RData::A(a)) => Some(IpAddr::V4(a.0)),
+                    Some(RData::AAAA(aaaa)) => Some(IpAddr::V6(aaaa.0)),
+                    _ => None,
+                });
+                for ip in answer_ips {
+                    if self.response_nets.iter().any(|net| net.contains(&ip))
+                        || self
+                            .response_ip_providers
+                            .iter()
+                            .any(|p| p.as_ip_set().is_some_and(|is| is.contains(ip)))
+                    {
+                        return true;
+                    }
+                }
+            }
+        }
+
         false
     }
 }
 
+/// Split IP references into literal networks and `provider:` IP sets.
+fn parse_ip_refs(
+    refs: Vec<String>,
+    registry: &HashMap<String, SharedPlugin>,
+) -> Result<(Vec<IpNet>, Vec<SharedPlugin>)> {
+    let mut nets = Vec::new();
+    let mut providers = Vec::new();
+    for ip_ref in refs {
+        if let Some(tag) = ip_ref.strip_prefix("provider:") {
+            let p = registry
+                .get(tag)
+                .ok_or_else(|| anyhow::anyhow!("Provider plugin not found: {}", tag))?;
+            if p.as_ip_set().is_some() {
+                providers.push(p.clone());
+            } else {
+                return Err(anyhow::anyhow!("Plugin {} is not an IpSet", tag));
+            }
+        } else if let Ok(net) = IpNet::from_str(&ip_ref) {
+            nets.push(net);
+        } else if let Ok(ip) = IpAddr::from_str(&ip_ref) {
+            nets.push(IpNet::from(ip));
+        } else {
+            return Err(anyhow::anyhow!("Invalid IP or CIDR: {}", ip_ref));
+        }
+    }
+    Ok((nets, providers))
+}
+
 impl Condition for Matcher {
     fn check(&self, ctx: &Context) -> bool {
         self.matches(ctx)
@@ -147,7 +244,7 @@ impl Plugin for Matcher {
         if self.matches(ctx) {
             info!("Matcher matched, executing sub-plugins");
             for plugin in &self.plugins {
-                plugin.next(ctx).await?;
+                exec(plugin, ctx).await?;
                 if ctx.response.is_some() || ctx.abort {
                     break;
                 }
@@ -168,12 +265,15 @@ mod tests {
     use std::sync::{Arc, RwLock};
 
     fn make_ctx(name: &str) -> Context {
+        make_typed_ctx(name, RecordType::A)
+    }
+
+    fn make_typed_ctx(name: &str, qtype: RecordType) -> Context {
         use hickory_proto::op::{Message, Query};
-        use hickory_proto::rr::{Name, RecordType};
-        use std::str::FromStr;
+        use hickory_proto::rr::Name;
 
         let mut msg = Message::new();
-        msg.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+        msg.add_query(Query::query(Name::from_str(name).unwrap(), qtype));
 
         use crate::statistics::Statistics;
         let stats = Arc::new(RwLock::new(Statistics::new()));
@@ -187,9 +287,11 @@ mod tests {
 
     #[test]
     fn test_matcher_domain() {
-        let config = MatcherConfig {
+        let _config = MatcherConfig {
             domain: vec!["example.com".to_string(), "suffix:test.com".to_string()],
             client_ip: vec![],
+            response_ip: vec![],
+            qtype: vec![],
             exec: vec![],
         };
         // We mock registry as empty or None, as we won't use exec/providers here for basic test
@@ -206,8 +308,13 @@ mod tests {
             // So logic supports suffix matching inherently for all domains listed?
             // "name_clean.ends_with(&format!(".{}", d))" -> Yes.
             // So if d="test.com", "sub.test.com" matches.
+            domain_regexes: vec![],
+            qtypes: vec![],
             domain_providers: vec![],
+            client_nets: vec![],
             ip_providers: vec![],
+            response_nets: vec![],
+            response_ip_providers: vec![],
             plugins: vec![],
         };
 
@@ -223,4 +330,102 @@ mod tests {
         let ctx = make_ctx("google.com.");
         assert!(!matcher.matches(&ctx));
     }
+
+    #[test]
+    fn test_matcher_qtype() {
+        let registry = HashMap::new();
+        let config: serde_yaml::Value = serde_yaml::from_str("qtype: [AAAA, https]").unwrap();
+        let matcher = Matcher::new(Some(&config), &registry).unwrap();
+        assert!(matcher.matches(&make_typed_ctx("example.com.", RecordType::AAAA)));
+        assert!(matcher.matches(&make_typed_ctx("example.com.", RecordType::HTTPS)));
+        assert!(!matcher.matches(&make_typed_ctx("example.com.", RecordType::A)));
+
+        // Combined with domains, both have to match.
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("qtype: [AAAA]\ndomain: [example.com]").unwrap();
+        let matcher = Matcher::new(Some(&config), &registry).unwrap();
+        assert!(matcher.matches(&make_typed_ctx("www.example.com.", RecordType::AAAA)));
+        assert!(!matcher.matches(&make_typed_ctx("www.example.org.", RecordType::AAAA)));
+        assert!(!matcher.matches(&make_typed_ctx("www.example.com.", RecordType::A)));
+
+        let config: serde_yaml::Value = serde_yaml::from_str("qtype: [BOGUS]").unwrap();
+        assert!(Matcher::new(Some(&config), &registry).is_err());
+    }
+
+    #[test]
+    fn test_matcher_domain_regex() {
+        let registry = HashMap::new();
+        let config: serde_yaml::Value =
+            serde_yaml::from_str(r#"domain: ['regex:^ad[0-9]+\.example\.com$']"#).unwrap();
+        let matcher = Matcher::new(Some(&config), &registry).unwrap();
+        assert!(matcher.matches(&make_ctx("ad3.example.com.")));
+        assert!(matcher.matches(&make_ctx("AD42.Example.com.")));
+        assert!(!matcher.matches(&make_ctx("ads.example.com.")));
+        assert!(!matcher.matches(&make_ctx("x.ad3.example.com.")));
+
+        let config: serde_yaml::Value = serde_yaml::from_str("domain: ['regex:(ad']").unwrap();
+        let err = Matcher::new(Some(&config), &registry).err().unwrap();
+        assert!(err.to_string().contains("Invalid domain regex"));
+    }
+
+    #[test]
+    fn test_matcher_client_cidr() {
+        let registry = HashMap::new();
+        let config: serde_yaml::Value =
+            serde_yaml::from_str(r#"client_ip: ["192.168.1.0/24", "10.0.0.1", "fd00::/8"]"#)
+                .unwrap();
+        let matcher = Matcher::new(Some(&config), &registry).unwrap();
+
+        let from = |ip: &str| {
+            let mut ctx = make_ctx("example.com.");
+            ctx.client_addr = SocketAddr::new(ip.parse().unwrap(), 1234);
+            matcher.matches(&ctx)
+        };
+        assert!(from("192.168.1.77"));
+        assert!(from("10.0.0.1"));
+        assert!(from("fd12::1"));
+        assert!(!from("192.168.2.1"));
+        assert!(!from("10.0.0.2"));
+
+        let config: serde_yaml::Value = serde_yaml::from_str("client_ip: [not-an-ip]").unwrap();
+        assert!(Matcher::new(Some(&config), &registry).is_err());
+    }
+
+    #[test]
+    fn test_matcher_response_ip() {
+        use crate::dns::empty_response;
+        use crate::plugins::ip_set::IpSetPlugin;
+        use hickory_proto::op::ResponseCode;
+        use hickory_proto::rr::rdata::A;
+        use hickory_proto::rr::Record;
+        use std::io::Write;
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "203.0.113.0/24").unwrap();
+        let set_config: serde_yaml::Value =
+            serde_yaml::from_str(&format!("files: [\"{}\"]", file.path().display())).unwrap();
+        let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+        registry.insert(
+            "cdn".to_string(),
+            Arc::new(IpSetPlugin::new(Some(&set_config)).unwrap()),
+        );
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("response_ip: [\"provider:cdn\", 198.51.100.7]").unwrap();
+        let matcher = Matcher::new(Some(&config), &registry).unwrap();
+
+        let answered = |ip: Ipv4Addr| {
+            let mut ctx = make_ctx("cdn.example.com.");
+            let mut response = empty_response(&ctx.request, ResponseCode::NoError);
+            let name = ctx.request.query().unwrap().name().clone();
+            response.add_answer(Record::from_rdata(name, 60, RData::A(A(ip))));
+            ctx.response = Some(response);
+            matcher.matches(&ctx)
+        };
+        assert!(answered(Ipv4Addr::new(203, 0, 113, 9)));
+        assert!(answered(Ipv4Addr::new(198, 51, 100, 7)));
+        assert!(!answered(Ipv4Addr::new(192, 0, 2, 1)));
+
+        // Before any response exists there is nothing to match.
+        assert!(!matcher.matches(&make_ctx("cdn.example.com.")));
+    }
 }
@@ -0,0 +1,235 @@
+use super::{exec, Context, Plugin, SharedPlugin};
+use crate::dns::{canonical_name, is_subdomain};
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::rr::RData;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use tracing::{debug, warn};
+
+fn default_min_answers() -> usize {
+    2
+}
+
+#[derive(Deserialize)]
+struct MinRecordsConfig {
+    /// Tag of the trusted plugin/chain used to retry suspect answers.
+    trusted: String,
+    /// Answers with fewer records than this are retried.
+    #[serde(default = "default_min_answers")]
+    min_answers: usize,
+    /// Domains (and their subdomains) to check; all names if empty.
+    #[serde(default)]
+    domains: Vec<String>,
+    /// Addresses known to be returned by forged answers.
+    #[serde(default)]
+    suspicious_ips: Vec<IpAddr>,
+}
+
+/// Re-resolves a thin or known-forged answer through a trusted chain.
+///
+/// Place it after the plugin that produced the response, e.g. in a sequence
+/// right after a plain UDP `forward`.
+pub struct MinRecords {
+    trusted: SharedPlugin,
+    min_answers: usize,
+    domains: Vec<String>,
+    suspicious_ips: Vec<IpAddr>,
+}
+
+impl MinRecords {
+    pub fn new(
+        config: Option<&serde_yaml::Value>,
+        registry: &HashMap<String, SharedPlugin>,
+    ) -> Result<Self> {
+        let config: MinRecordsConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            return Err(anyhow::anyhow!("MinRecords requires config"));
+        };
+
+        let trusted = registry
+            .get(&config.trusted)
+            .ok_or_else(|| anyhow::anyhow!("Trusted plugin not found: {}", config.trusted))?
+            .clone();
+
+        Ok(Self {
+            trusted,
+            min_answers: config.min_answers,
+            domains: config.domains.iter().map(|d| canonical_name(d)).collect(),
+            suspicious_ips: config.suspicious_ips,
+        })
+    }
+
+    fn applies_to(&self, request: &Message) -> bool {
+        if self.domains.is_empty() {
+            return true;
+        }
+        let Some(query) = request.query() else {
+            return false;
+        };
+        let name = canonical_name(&query.name().to_string());
+        self.domains.iter().any(|d| is_subdomain(&name, d))
+    }
+
+    fn is_suspect(&self, response: &Message) -> bool {
+        // Only successful answers are judged; NXDOMAIN and errors pass through.
+        if response.response_code() != ResponseCode::NoError {
+            return false;
+        }
+        if response.answers().len() < self.min_answers {
+            return true;
+        }
+        response.answers().iter().any(|record| {
+            let ip = match record.data() {
+                Some(RData::A(a)) => IpAddr::V4(a.0),
+                Some(RData::AAAA(aaaa)) => IpAddr::V6(aaaa.0),
+                _ => return false,
+            };
+            self.suspicious_ips.contains(&ip)
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for MinRecords {
+    fn name(&self) -> &str {
+        "min_records"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        let suspect = match &ctx.response {
+            Some(response) => self.applies_to(&ctx.request) && self.is_suspect(response),
+            None => false,
+        };
+        if !suspect {
+            return Ok(());
+        }
+
+        debug!(
+            "Suspect answer for {:?}, retrying via {}",
+            ctx.request.query().map(|q| q.name().to_string()),
+            self.trusted.name()
+        );
+        let original = ctx.response.take();
+        let answered_by = ctx.answered_by.take();
+        if let Err(e) = exec(&self.trusted, ctx).await {
+            warn!("Trusted retry failed: {}", e);
+        }
+        if ctx.response.is_none() {
+            // Keep the thin answer rather than none at all.
+            ctx.response = original;
+            ctx.answered_by = answered_by;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::Query;
+    use hickory_proto::rr::{Name, Record, RecordType};
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::str::FromStr;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, RwLock};
+
+    struct Trusted {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Plugin for Trusted {
+        fn name(&self) -> &str {
+            "trusted"
+        }
+
+        async fn next(&self, ctx: &mut Context) -> Result<()> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            ctx.response = Some(answer(&ctx.request, &[[1, 1, 1, 1], [1, 0, 0, 1]]));
+            Ok(())
+        }
+    }
+
+    fn answer(request: &Message, ips: &[[u8; 4]]) -> Message {
+        let mut response = crate::dns::empty_response(request, ResponseCode::NoError);
+        let name = request.query().unwrap().name().clone();
+        for ip in ips {
+            response.add_answer(Record::from_rdata(
+                name.clone(),
+                60,
+                RData::A(Ipv4Addr::from(*ip).into()),
+            ));
+        }
+        response
+    }
+
+    fn make_ctx(name: &str, ips: &[[u8; 4]]) -> Context {
+        let mut request = Message::new();
+        request.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+        let mut ctx = Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            request,
+            Arc::new(RwLock::new(Statistics::new())),
+        );
+        ctx.response = Some(answer(&ctx.request, ips));
+        ctx
+    }
+
+    fn make_plugin(trusted: Arc<Trusted>) -> MinRecords {
+        let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+        registry.insert("trusted".to_string(), trusted);
+        let config: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+            trusted: trusted
+            min_answers: 2
+            domains: [google.com]
+            suspicious_ips: [8.7.198.46]
+            "#,
+        )
+        .unwrap();
+        MinRecords::new(Some(&config), &registry).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_thin_answer_retried() {
+        let trusted = Arc::new(Trusted {
+            calls: AtomicUsize::new(0),
+        });
+        let plugin = make_plugin(trusted.clone());
+
+        // A single A record for a watched domain is retried.
+        let mut ctx = make_ctx("www.google.com.", &[[93, 46, 8, 90]]);
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(trusted.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(ctx.response.unwrap().answers().len(), 2);
+        assert_eq!(ctx.answered_by.as_deref(), Some("trusted"));
+
+        // So is a full answer containing a known forged address.
+        let mut ctx = make_ctx("google.com.", &[[8, 7, 198, 46], [1, 2, 3, 4]]);
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(trusted.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_healthy_answer_kept() {
+        let trusted = Arc::new(Trusted {
+            calls: AtomicUsize::new(0),
+        });
+        let plugin = make_plugin(trusted.clone());
+
+        let mut ctx = make_ctx("google.com.", &[[1, 2, 3, 4], [5, 6, 7, 8]]);
+        plugin.next(&mut ctx).await.unwrap();
+
+        // Names outside the configured domains are never checked.
+        let mut other = make_ctx("example.com.", &[[1, 2, 3, 4]]);
+        plugin.next(&mut other).await.unwrap();
+
+        assert_eq!(trusted.calls.load(Ordering::SeqCst), 0);
+        assert_eq!(other.response.unwrap().answers().len(), 1);
+    }
+}
@@ -1,28 +1,65 @@
 use anyhow::Result;
 use async_trait::async_trait;
 use hickory_proto::op::Message;
+use std::collections::BTreeMap;
 use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
 
+pub mod alias;
+pub mod allowlist;
 pub mod cache;
+pub mod chaos;
+pub mod cname_flatten;
+pub mod dedupe;
 pub mod delay_plugin;
+pub mod dns64;
+pub mod dnssec_ok;
 pub mod domain_set;
+pub mod drop;
+pub mod ecs;
+pub mod edns_option;
 pub mod fallback;
 pub mod forward;
+pub mod geo_steer;
+pub mod geoip;
 pub mod geosite;
 pub mod hosts;
 pub mod if_plugin;
+pub mod ip_pin;
 pub mod ip_set;
 pub mod matcher;
+pub mod min_records;
+pub mod nodata_soa;
+pub mod order;
+pub mod override_plugin;
+pub mod rate_limit;
 pub mod reject_plugin;
+pub mod reloadable;
 pub mod return_plugin;
+pub mod root_hints;
 pub mod sequence;
+pub mod sinkhole;
+pub mod special_use;
+pub mod static_response;
 pub mod system;
+pub mod trust_anchor;
 pub mod ttl;
+pub mod txt_store;
+pub mod valid_tld;
+pub mod webhook;
+pub mod weighted_answer;
 
 use crate::statistics::Statistics;
 use std::sync::RwLock;
 
+/// Default limit on how deeply plugins may nest (sequence -> if -> matcher -> ...).
+pub const DEFAULT_MAX_DEPTH: usize = 32;
+
+/// TTL of records synthesized by plugins (hosts, static answers) unless configured.
+pub const DEFAULT_TTL: u32 = 60;
+
 /// Context holds the state of a DNS query handling.
 #[derive(Clone)]
 pub struct Context {
@@ -30,10 +67,59 @@ pub struct Context {
     pub request: Message,
     pub response: Option<Message>,
     pub abort: bool,
+    /// The query is deliberately left unanswered (see `drop`), so the server
+    /// must not fill in a SERVFAIL.
+    pub dropped: bool,
     pub is_remote: bool,
+    /// When set, plugins must not forward the query upstream (cache/local data only).
+    pub local_only: bool,
+    /// Current plugin nesting depth and the limit enforced by `exec`.
+    pub depth: usize,
+    pub max_depth: usize,
+    /// Plugin (by name) that produced the response, if any.
+    pub answered_by: Option<String>,
+    /// The response came from a cache rather than a fresh resolution.
+    pub cache_hit: bool,
+    /// Upstream that answered when the query was forwarded.
+    pub upstream: Option<String>,
+    /// The response was resolved for the client's subnet (ECS added upstream),
+    /// so it must not be cached for other clients.
+    pub ecs_scoped: bool,
+    /// Names of the plugins entered through `exec`, in order.
+    pub trace: Vec<String>,
+    /// Record how long each plugin entered through `exec` takes.
+    pub profile: bool,
+    /// TTL for synthesized records when the plugin has no `ttl` of its own.
+    pub default_ttl: u32,
+    /// When the server received the query, for the per-query log.
+    pub started_at: Instant,
+    /// Upstream attempts the query may still make; unlimited if `None`.
+    pub retry_budget: Option<RetryBudget>,
     pub stats: Arc<RwLock<Statistics>>,
 }
 
+/// Upstream attempts left for one query. Clones share the count, so nested
+/// resolutions on a cloned context draw from the same budget.
+#[derive(Debug, Clone)]
+pub struct RetryBudget(Arc<AtomicUsize>);
+
+impl RetryBudget {
+    pub fn new(attempts: usize) -> Self {
+        Self(Arc::new(AtomicUsize::new(attempts)))
+    }
+
+    /// Spend up to `n` attempts, returning how many were granted.
+    pub fn take(&self, n: usize) -> usize {
+        let left = self
+            .0
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |left| {
+                Some(left.saturating_sub(n))
+            })
+            .unwrap_or(0);
+        left.min(n)
+    }
+}
+
 impl Context {
     pub fn new(client_addr: SocketAddr, request: Message, stats: Arc<RwLock<Statistics>>) -> Self {
         Self {
@@ -41,12 +127,77 @@ impl Context {
             request,
             response: None,
             abort: false,
+            dropped: false,
             is_remote: false,
+            local_only: false,
+            depth: 0,
+            max_depth: DEFAULT_MAX_DEPTH,
+            answered_by: None,
+            cache_hit: false,
+            upstream: None,
+            ecs_scoped: false,
+            trace: Vec::new(),
+            profile: false,
+            default_ttl: DEFAULT_TTL,
+            started_at: Instant::now(),
+            retry_budget: None,
             stats,
         }
     }
 }
 
+/// Error raised when the plugin chain nests deeper than `Context::max_depth`,
+/// usually because of a loop in the configuration.
+#[derive(Debug)]
+pub struct DepthExceeded {
+    pub max_depth: usize,
+    pub plugin: String,
+}
+
+impl std::fmt::Display for DepthExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "maximum chain depth {} exceeded entering plugin {}",
+            self.max_depth, self.plugin
+        )
+    }
+}
+
+impl std::error::Error for DepthExceeded {}
+
+/// Run a nested plugin one level deeper in the chain.
+///
+/// Container plugins (sequence, if, matcher, ...) call their children through this
+/// so that misconfigured loops fail with `DepthExceeded` instead of recursing forever.
+pub async fn exec(plugin: &SharedPlugin, ctx: &mut Context) -> Result<()> {
+    if ctx.depth >= ctx.max_depth {
+        return Err(DepthExceeded {
+            max_depth: ctx.max_depth,
+            plugin: plugin.name().to_string(),
+        }
+        .into());
+    }
+    ctx.depth += 1;
+    ctx.trace.push(plugin.name().to_string());
+    let answered = ctx.response.is_some();
+    let start = ctx.profile.then(Instant::now);
+    let result = plugin.next(ctx).await;
+    if let Some(start) = start {
+        // Inclusive time: a container's figure covers its children.
+        let tag = plugin.tag().unwrap_or(plugin.name());
+        ctx.stats
+            .write()
+            .unwrap()
+            .record_plugin_time(tag, start.elapsed());
+    }
+    ctx.depth -= 1;
+    if !answered && ctx.response.is_some() && ctx.answered_by.is_none() {
+        ctx.answered_by = Some(plugin.name().to_string());
+    }
+    result
+}
+
 pub trait DomainSet: Send + Sync {
     fn contains(&self, domain: &str) -> bool;
 }
@@ -59,12 +210,109 @@ pub trait Condition: Send + Sync {
     fn check(&self, ctx: &Context) -> bool;
 }
 
+/// Access statistics for a single cache key.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct CacheKeyStats {
+    pub key: String,
+    pub hits: u64,
+    pub ttl_remaining: u64,
+}
+
+/// A live cache entry, as carried over from one cache instance to another.
+#[derive(Debug, Clone)]
+pub struct CacheSnapshotEntry {
+    pub key: String,
+    pub response: Message,
+    pub stored_at: Instant,
+    pub valid_until: Instant,
+    pub hits: u64,
+}
+
+pub trait CacheStore: Send + Sync {
+    /// The `n` most frequently hit live keys, most-hit first.
+    fn top_keys(&self, n: usize) -> Vec<CacheKeyStats>;
+
+    /// Live entries, least recently used first.
+    fn export_entries(&self) -> Vec<CacheSnapshotEntry>;
+
+    /// Insert `entries` in order, each becoming the most recently used.
+    fn import_entries(&self, entries: Vec<CacheSnapshotEntry>);
+}
+
+/// Liveness of a single upstream as tracked by a forwarding plugin.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct UpstreamHealth {
+    pub upstream: String,
+    pub state: UpstreamState,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    /// Exponentially weighted moving average of successful exchange latency.
+    pub ewma_latency_ms: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpstreamState {
+    Healthy,
+    /// Temporarily skipped after too many consecutive failures.
+    Ejected,
+}
+
+pub trait UpstreamHealthReporter: Send + Sync {
+    fn upstream_health(&self) -> Vec<UpstreamHealth>;
+}
+
+/// Answer forced for an overridden name.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "action", rename_all = "lowercase")]
+pub enum OverrideAction {
+    /// Answer A/AAAA queries with these addresses.
+    Answer {
+        ips: Vec<IpAddr>,
+    },
+    Nxdomain,
+}
+
+/// Runtime-editable name overrides, keyed by canonical name.
+pub trait OverrideStore: Send + Sync {
+    fn overrides(&self) -> BTreeMap<String, OverrideAction>;
+    fn set_override(&self, name: &str, action: OverrideAction);
+    /// Returns whether the name was present.
+    fn remove_override(&self, name: &str) -> bool;
+}
+
+/// TXT value written to a `TxtStore`.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct TxtValue {
+    pub value: String,
+    /// TTL of the answer; the store's default if unset.
+    #[serde(default)]
+    pub ttl: Option<u32>,
+    /// Seconds until the value is dropped; the store's default if unset.
+    #[serde(default)]
+    pub expire: Option<u64>,
+}
+
+/// Runtime-writable TXT records, e.g. ACME DNS-01 challenges.
+pub trait TxtStore: Send + Sync {
+    /// Whether the store takes records for `name`.
+    fn accepts(&self, name: &str) -> bool;
+    fn add_txt(&self, name: &str, value: TxtValue);
+    /// Drops every value of `name`; returns whether there was any.
+    fn remove_txt(&self, name: &str) -> bool;
+}
+
 /// Plugin is the core interface for processing DNS requests.
 #[async_trait]
 pub trait Plugin: Send + Sync {
     /// Name of the plugin.
     fn name(&self) -> &str;
 
+    /// Tag the plugin was configured under, if it came from the registry.
+    fn tag(&self) -> Option<&str> {
+        None
+    }
+
     /// Execute the plugin logic.
     async fn next(&self, ctx: &mut Context) -> Result<()>;
 
@@ -80,6 +328,26 @@ pub trait Plugin: Send + Sync {
     fn as_condition(&self) -> Option<&dyn Condition> {
         None
     }
+
+    fn as_cache_store(&self) -> Option<&dyn CacheStore> {
+        None
+    }
+
+    fn as_upstream_health(&self) -> Option<&dyn UpstreamHealthReporter> {
+        None
+    }
+
+    fn as_override_store(&self) -> Option<&dyn OverrideStore> {
+        None
+    }
+
+    fn as_txt_store(&self) -> Option<&dyn TxtStore> {
+        None
+    }
+
+    fn as_reloadable(&self) -> Option<&reloadable::Reloadable> {
+        None
+    }
 }
 
 /// Type alias for a shared plugin instance.
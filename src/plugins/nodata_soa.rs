@@ -0,0 +1,212 @@
+use super::{Context, Plugin};
+use crate::dns::{canonical_name, is_subdomain};
+use anyhow::{Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::rdata::SOA;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use serde::Deserialize;
+use std::str::FromStr;
+
+fn default_serial() -> u32 {
+    1
+}
+
+fn default_refresh() -> i32 {
+    3600
+}
+
+fn default_retry() -> i32 {
+    600
+}
+
+fn default_expire() -> i32 {
+    86400
+}
+
+fn default_minimum() -> u32 {
+    300
+}
+
+#[derive(Deserialize)]
+struct ZoneConfig {
+    zone: String,
+    /// Primary name server; `ns.<zone>` if unset.
+    #[serde(default)]
+    mname: Option<String>,
+    /// Responsible mailbox in name form; `hostmaster.<zone>` if unset.
+    #[serde(default)]
+    rname: Option<String>,
+    #[serde(default = "default_serial")]
+    serial: u32,
+    #[serde(default = "default_refresh")]
+    refresh: i32,
+    #[serde(default = "default_retry")]
+    retry: i32,
+    #[serde(default = "default_expire")]
+    expire: i32,
+    /// Negative caching TTL (RFC 2308), also used as the SOA record's TTL.
+    #[serde(default = "default_minimum")]
+    minimum: u32,
+}
+
+#[derive(Deserialize)]
+struct NodataSoaConfig {
+    zones: Vec<ZoneConfig>,
+}
+
+struct Zone {
+    /// Canonical zone name, for matching.
+    key: String,
+    record: Record,
+}
+
+/// Adds the zone's SOA to the authority section of negative answers (NODATA and
+/// NXDOMAIN) for locally served zones, so clients can cache them (RFC 2308).
+///
+/// Place it after the plugin that answered. Responses already carrying an SOA
+/// are left alone.
+pub struct NodataSoa {
+    /// Most specific zones first.
+    zones: Vec<Zone>,
+}
+
+impl NodataSoa {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: NodataSoaConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            return Err(anyhow::anyhow!("NodataSoa requires config"));
+        };
+
+        let mut zones = config
+            .zones
+            .into_iter()
+            .map(|z| {
+                let key = canonical_name(&z.zone);
+                let name = |n: Option<String>, prefix: &str| {
+                    let n = n.unwrap_or_else(|| format!("{}.{}", prefix, key));
+                    Name::from_str(&n).with_context(|| format!("Invalid name: {}", n))
+                };
+                let soa = SOA::new(
+                    name(z.mname, "ns")?,
+                    name(z.rname, "hostmaster")?,
+                    z.serial,
+                    z.refresh,
+                    z.retry,
+                    z.expire,
+                    z.minimum,
+                );
+                let origin = Name::from_str(&format!("{}.", key))
+                    .with_context(|| format!("Invalid zone: {}", z.zone))?;
+                Ok(Zone {
+                    key,
+                    record: Record::from_rdata(origin, z.minimum, RData::SOA(soa)),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        zones.sort_by_key(|z| std::cmp::Reverse(z.key.len()));
+        Ok(Self { zones })
+    }
+
+    fn zone_for(&self, name: &str) -> Option<&Zone> {
+        self.zones.iter().find(|z| is_subdomain(name, &z.key))
+    }
+}
+
+#[async_trait]
+impl Plugin for NodataSoa {
+    fn name(&self) -> &str {
+        "nodata_soa"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        let Some(query) = ctx.request.query() else {
+            return Ok(());
+        };
+        let Some(response) = &mut ctx.response else {
+            return Ok(());
+        };
+        let negative = match response.response_code() {
+            ResponseCode::NXDomain => true,
+            ResponseCode::NoError => response.answers().is_empty(),
+            _ => false,
+        };
+        if !negative
+            || response
+                .name_servers()
+                .iter()
+                .any(|r| r.record_type() == RecordType::SOA)
+        {
+            return Ok(());
+        }
+
+        let name = canonical_name(&query.name().to_ascii());
+        if let Some(zone) = self.zone_for(&name) {
+            response.add_name_server(zone.record.clone());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::empty_response;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Message, Query};
+    use std::net::SocketAddr;
+    use std::sync::{Arc, RwLock};
+
+    fn make_ctx(name: &str, rcode: ResponseCode) -> Context {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(
+            Name::from_str(name).unwrap(),
+            RecordType::AAAA,
+        ));
+        let mut ctx = Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        );
+        ctx.response = Some(empty_response(&ctx.request, rcode));
+        ctx
+    }
+
+    #[tokio::test]
+    async fn test_soa_added_to_nodata() {
+        let config: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+            zones:
+              - zone: lan
+              - zone: office.lan
+                minimum: 60
+            "#,
+        )
+        .unwrap();
+        let plugin = NodataSoa::new(Some(&config)).unwrap();
+
+        let mut ctx = make_ctx("printer.office.lan.", ResponseCode::NoError);
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.name_servers().len(), 1);
+        let record = &response.name_servers()[0];
+        assert_eq!(record.name(), &Name::from_str("office.lan.").unwrap());
+        assert_eq!(record.ttl(), 60);
+        match record.data() {
+            Some(RData::SOA(soa)) => {
+                assert_eq!(soa.mname(), &Name::from_str("ns.office.lan.").unwrap());
+                assert_eq!(soa.minimum(), 60);
+            }
+            other => panic!("unexpected record {:?}", other),
+        }
+
+        // Outside the configured zones, and failures, are left alone.
+        let mut ctx = make_ctx("example.com.", ResponseCode::NoError);
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.unwrap().name_servers().is_empty());
+        let mut ctx = make_ctx("nas.lan.", ResponseCode::ServFail);
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.unwrap().name_servers().is_empty());
+    }
+}
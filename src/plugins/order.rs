@@ -0,0 +1,183 @@
+use super::{Context, Plugin};
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::rr::{Record, RecordType};
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+
+/// How answer addresses are reordered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum OrderMode {
+    /// Random order of A/AAAA records in every response.
+    #[default]
+    Shuffle,
+    /// A records before AAAA records, each family keeping its order.
+    PreferIpv4,
+    /// AAAA records before A records, each family keeping its order.
+    PreferIpv6,
+}
+
+#[derive(Deserialize)]
+struct OrderConfig {
+    #[serde(default)]
+    mode: OrderMode,
+}
+
+/// Reorders the address records of an existing response, e.g. to spread
+/// clients that always take the first address. `shuffle` only swaps addresses
+/// among their own positions, so other records stay where they are; the
+/// `prefer_*` modes move other records in front of every address, keeping
+/// their order, so CNAME chains come first.
+pub struct Order {
+    mode: OrderMode,
+}
+
+impl Order {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: OrderConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            OrderConfig {
+                mode: OrderMode::default(),
+            }
+        };
+        Ok(Self { mode: config.mode })
+    }
+}
+
+fn is_address(record: &Record) -> bool {
+    matches!(record.record_type(), RecordType::A | RecordType::AAAA)
+}
+
+#[async_trait]
+impl Plugin for Order {
+    fn name(&self) -> &str {
+        "order"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        let Some(response) = &mut ctx.response else {
+            return Ok(());
+        };
+        let answers = response.answers_mut();
+        match self.mode {
+            OrderMode::Shuffle => {
+                // Shuffle the addresses among their own positions.
+                let positions: Vec<usize> = (0..answers.len())
+                    .filter(|&i| is_address(&answers[i]))
+                    .collect();
+                let mut addresses: Vec<Record> =
+                    positions.iter().map(|&i| answers[i].clone()).collect();
+                addresses.shuffle(&mut rand::thread_rng());
+                for (i, record) in positions.into_iter().zip(addresses) {
+                    answers[i] = record;
+                }
+            }
+            OrderMode::PreferIpv4 | OrderMode::PreferIpv6 => {
+                let preferred = if self.mode == OrderMode::PreferIpv4 {
+                    RecordType::A
+                } else {
+                    RecordType::AAAA
+                };
+                answers.sort_by_key(|r| match r.record_type() {
+                    _ if !is_address(r) => 0,
+                    t if t == preferred => 1,
+                    _ => 2,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dns::empty_response;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Message, Query, ResponseCode};
+    use hickory_proto::rr::rdata::CNAME;
+    use hickory_proto::rr::{Name, RData};
+    use std::net::{IpAddr, SocketAddr};
+    use std::sync::{Arc, RwLock};
+
+    fn make_ctx(ips: &[&str]) -> Context {
+        let name = Name::from_ascii("www.example.com.").unwrap();
+        let target = Name::from_ascii("cdn.example.net.").unwrap();
+        let mut msg = Message::new();
+        msg.add_query(Query::query(name.clone(), RecordType::A));
+        let mut response = empty_response(&msg, ResponseCode::NoError);
+        response.add_answer(Record::from_rdata(
+            name,
+            60,
+            RData::CNAME(CNAME(target.clone())),
+        ));
+        for ip in ips {
+            let rdata = match ip.parse::<IpAddr>().unwrap() {
+                IpAddr::V4(v4) => RData::A(v4.into()),
+                IpAddr::V6(v6) => RData::AAAA(v6.into()),
+            };
+            response.add_answer(Record::from_rdata(target.clone(), 60, rdata));
+        }
+        let mut ctx = Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        );
+        ctx.response = Some(response);
+        ctx
+    }
+
+    fn answers(ctx: &Context) -> Vec<String> {
+        let response = ctx.response.as_ref().unwrap();
+        response
+            .answers()
+            .iter()
+            .map(|r| r.data().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_prefer_ipv6() {
+        let config: serde_yaml::Value = serde_yaml::from_str("mode: prefer_ipv6").unwrap();
+        let plugin = Order::new(Some(&config)).unwrap();
+        let mut ctx = make_ctx(&["192.0.2.1", "2001:db8::1", "192.0.2.2", "2001:db8::2"]);
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(
+            answers(&ctx),
+            [
+                "cdn.example.net.",
+                "2001:db8::1",
+                "2001:db8::2",
+                "192.0.2.1",
+                "192.0.2.2"
+            ]
+        );
+
+        // No response, nothing to do.
+        let mut ctx = make_ctx(&[]);
+        ctx.response = None;
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_shuffle_keeps_cname_first() {
+        let plugin = Order::new(None).unwrap();
+        let ips: Vec<String> = (1..=8).map(|i| format!("192.0.2.{}", i)).collect();
+        let ips: Vec<&str> = ips.iter().map(String::as_str).collect();
+        let mut orders = std::collections::HashSet::new();
+        for _ in 0..20 {
+            let mut ctx = make_ctx(&ips);
+            plugin.next(&mut ctx).await.unwrap();
+            let answers = answers(&ctx);
+            assert_eq!(answers[0], "cdn.example.net.");
+            let mut sorted = answers[1..].to_vec();
+            sorted.sort();
+            assert_eq!(sorted, ips);
+            orders.insert(answers);
+        }
+        assert!(orders.len() > 1);
+    }
+}
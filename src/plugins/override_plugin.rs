@@ -0,0 +1,204 @@
+use super::{Context, OverrideAction, OverrideStore, Plugin};
+use crate::dns::{self, canonical_name};
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{RData, Record, RecordType};
+use serde::Deserialize;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::RwLock;
+use tracing::info;
+
+#[derive(Deserialize)]
+struct OverrideConfig {
+    /// Overrides present at startup; more can be added through the API.
+    #[serde(default)]
+    entries: HashMap<String, OverrideAction>,
+    /// TTL of answers; the server's `default_ttl` if unset.
+    #[serde(default)]
+    ttl: Option<u32>,
+}
+
+/// Answers listed names (and their subdomains) before anything else in the chain.
+///
+/// Unlike `hosts`, entries can be changed at runtime through
+/// `/plugins/{tag}/overrides`, e.g. to pin or block a compromised domain during an
+/// incident. Runtime changes are lost when the plugin is reloaded.
+pub struct OverridePlugin {
+    entries: RwLock<HashMap<String, OverrideAction>>,
+    ttl: Option<u32>,
+}
+
+impl OverridePlugin {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: OverrideConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            OverrideConfig {
+                entries: HashMap::new(),
+                ttl: None,
+            }
+        };
+        Ok(Self {
+            entries: RwLock::new(
+                config
+                    .entries
+                    .into_iter()
+                    .map(|(name, action)| (canonical_name(&name), action))
+                    .collect(),
+            ),
+            ttl: config.ttl,
+        })
+    }
+
+    /// The override for `name` or its closest listed parent.
+    fn lookup(&self, name: &str) -> Option<OverrideAction> {
+        let entries = self.entries.read().unwrap();
+        let mut name = name;
+        loop {
+            if let Some(action) = entries.get(name) {
+                return Some(action.clone());
+            }
+            name = name.split_once('.')?.1;
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for OverridePlugin {
+    fn name(&self) -> &str {
+        "override"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        if ctx.response.is_some() {
+            return Ok(());
+        }
+        let Some(query) = ctx.request.query() else {
+            return Ok(());
+        };
+        let name = canonical_name(&query.name().to_string());
+        let Some(action) = self.lookup(&name) else {
+            return Ok(());
+        };
+
+        let response = match action {
+            OverrideAction::Nxdomain => dns::empty_response(&ctx.request, ResponseCode::NXDomain),
+            OverrideAction::Answer { ips } => {
+                let ttl = self.ttl.unwrap_or(ctx.default_ttl);
+                let mut response = dns::empty_response(&ctx.request, ResponseCode::NoError);
+                for ip in ips {
+                    let rdata = match (ip, query.query_type()) {
+                        (IpAddr::V4(v4), RecordType::A) => RData::A(A(v4)),
+                        (IpAddr::V6(v6), RecordType::AAAA) => RData::AAAA(AAAA(v6)),
+                        _ => continue,
+                    };
+                    response.add_answer(Record::from_rdata(query.name().clone(), ttl, rdata));
+                }
+                response
+            }
+        };
+        info!("Override for {}: {:?}", name, response.response_code());
+        ctx.response = Some(response);
+        Ok(())
+    }
+
+    fn as_override_store(&self) -> Option<&dyn OverrideStore> {
+        Some(self)
+    }
+}
+
+impl OverrideStore for OverridePlugin {
+    fn overrides(&self) -> BTreeMap<String, OverrideAction> {
+        let entries = self.entries.read().unwrap();
+        entries
+            .iter()
+            .map(|(name, action)| (name.clone(), action.clone()))
+            .collect()
+    }
+
+    fn set_override(&self, name: &str, action: OverrideAction) {
+        let name = canonical_name(name);
+        info!("Setting override for {}: {:?}", name, action);
+        self.entries.write().unwrap().insert(name, action);
+    }
+
+    fn remove_override(&self, name: &str) -> bool {
+        let name = canonical_name(name);
+        self.entries.write().unwrap().remove(&name).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::Name;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::str::FromStr;
+    use std::sync::{Arc, RwLock};
+
+    fn make_ctx(name: &str, qtype: RecordType) -> Context {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(Name::from_str(name).unwrap(), qtype));
+        Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_override_entries() {
+        let config: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+            entries:
+              Bad.Example.: { action: nxdomain }
+              pinned.example: { action: answer, ips: ["10.0.0.1", "::1"] }
+            "#,
+        )
+        .unwrap();
+        let plugin = OverridePlugin::new(Some(&config)).unwrap();
+
+        // Subdomains of a listed name are covered too.
+        let mut ctx = make_ctx("www.bad.example.", RecordType::A);
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(
+            ctx.response.unwrap().response_code(),
+            ResponseCode::NXDomain
+        );
+
+        // Only addresses of the queried family are returned.
+        let mut ctx = make_ctx("pinned.example.", RecordType::A);
+        plugin.next(&mut ctx).await.unwrap();
+        let answers = ctx.response.unwrap().answers().to_vec();
+        assert_eq!(answers.len(), 1);
+        assert_eq!(
+            answers[0].data(),
+            Some(&RData::A(Ipv4Addr::new(10, 0, 0, 1).into()))
+        );
+
+        assert!(plugin.remove_override("bad.example"));
+        let mut ctx = make_ctx("www.bad.example.", RecordType::A);
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_override_ttl() {
+        for (extra, expected) in [("", 300), ("ttl: 5", 5)] {
+            let yaml = format!(
+                "entries:\n  pinned.example: {{ action: answer, ips: [\"10.0.0.1\"] }}\n{}",
+                extra
+            );
+            let plugin = OverridePlugin::new(Some(&serde_yaml::from_str(&yaml).unwrap())).unwrap();
+            let mut ctx = make_ctx("pinned.example.", RecordType::A);
+            ctx.default_ttl = 300;
+            plugin.next(&mut ctx).await.unwrap();
+            assert_eq!(ctx.response.unwrap().answers()[0].ttl(), expected);
+        }
+    }
+}
@@ -0,0 +1,221 @@
+use super::{Context, Plugin};
+use crate::dns::{empty_response, truncated};
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::op::ResponseCode;
+use lru::LruCache;
+use serde::Deserialize;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::debug;
+
+/// Clients tracked before the least recently seen ones are forgotten.
+const MAX_CLIENTS: usize = 65536;
+
+/// What a client over its limit gets.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LimitAction {
+    /// No response at all.
+    Drop,
+    /// An empty REFUSED response.
+    #[default]
+    Refused,
+    /// An empty response with TC set, so the client has to retry over TCP.
+    /// Clients already on TCP can't retry and just get the empty answer.
+    Truncate,
+}
+
+#[derive(Deserialize)]
+struct RateLimitConfig {
+    /// Queries per second allowed per client address.
+    qps: f64,
+    /// Queries a client may send at once after being idle; `qps` if unset.
+    #[serde(default)]
+    burst: Option<f64>,
+    #[serde(default)]
+    action: LimitAction,
+}
+
+/// Token bucket of one client.
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Limits how fast each client address may query. Queries within the limit
+/// pass on; the others are answered per `action` and end the chain.
+pub struct RateLimit {
+    qps: f64,
+    burst: f64,
+    action: LimitAction,
+    buckets: Mutex<LruCache<IpAddr, Bucket>>,
+}
+
+impl RateLimit {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: RateLimitConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            return Err(anyhow::anyhow!("RateLimit requires config"));
+        };
+        if config.qps <= 0.0 {
+            return Err(anyhow::anyhow!("qps must be positive, got {}", config.qps));
+        }
+        let burst = config.burst.unwrap_or(config.qps).max(1.0);
+        Ok(Self {
+            qps: config.qps,
+            burst,
+            action: config.action,
+            buckets: Mutex::new(LruCache::new(NonZeroUsize::new(MAX_CLIENTS).unwrap())),
+        })
+    }
+
+    /// Take a token for `ip`, returning false if it has none left.
+    fn allow(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        // A new client pushes out the one seen longest ago once the cache is full.
+        let bucket = buckets.get_or_insert_mut(ip, || Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.qps).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for RateLimit {
+    fn name(&self) -> &str {
+        "rate_limit"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        let ip = ctx.client_addr.ip();
+        if self.allow(ip, Instant::now()) {
+            return Ok(());
+        }
+
+        debug!("{} is over its rate limit, action {:?}", ip, self.action);
+        ctx.response = match self.action {
+            LimitAction::Drop => {
+                ctx.dropped = true;
+                None
+            }
+            LimitAction::Refused => Some(empty_response(&ctx.request, ResponseCode::Refused)),
+            LimitAction::Truncate => Some(truncated(&empty_response(
+                &ctx.request,
+                ResponseCode::NoError,
+            ))),
+        };
+        ctx.abort = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::{Name, RecordType};
+    use std::net::SocketAddr;
+    use std::sync::{Arc, RwLock};
+    use std::time::Duration;
+
+    fn make_ctx(client: &str) -> Context {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(
+            Name::from_ascii("example.com.").unwrap(),
+            RecordType::A,
+        ));
+        Context::new(
+            SocketAddr::new(client.parse().unwrap(), 1234),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    /// Run a query from `client`, returning the context afterwards.
+    async fn query(plugin: &RateLimit, client: &str) -> Context {
+        let mut ctx = make_ctx(client);
+        plugin.next(&mut ctx).await.unwrap();
+        ctx
+    }
+
+    #[tokio::test]
+    async fn test_actions_over_limit() {
+        for action in ["drop", "refused", "truncate"] {
+            let yaml = format!("qps: 1\nburst: 2\naction: {}", action);
+            let plugin = RateLimit::new(Some(&serde_yaml::from_str(&yaml).unwrap())).unwrap();
+            for _ in 0..2 {
+                let ctx = query(&plugin, "192.0.2.1").await;
+                assert!(ctx.response.is_none() && !ctx.abort, "{}", action);
+            }
+
+            let ctx = query(&plugin, "192.0.2.1").await;
+            assert!(ctx.abort, "{}", action);
+            match action {
+                "drop" => {
+                    assert!(ctx.dropped);
+                    assert!(ctx.response.is_none());
+                }
+                "refused" => {
+                    let response = ctx.response.unwrap();
+                    assert_eq!(response.response_code(), ResponseCode::Refused);
+                }
+                _ => {
+                    let response = ctx.response.unwrap();
+                    assert_eq!(response.response_code(), ResponseCode::NoError);
+                    assert!(response.truncated());
+                    assert!(response.answers().is_empty());
+                }
+            }
+
+            // Other clients have their own budget.
+            let ctx = query(&plugin, "192.0.2.2").await;
+            assert!(ctx.response.is_none() && !ctx.abort, "{}", action);
+        }
+    }
+
+    #[test]
+    fn test_tokens_refill() {
+        let config: serde_yaml::Value = serde_yaml::from_str("qps: 10").unwrap();
+        let plugin = RateLimit::new(Some(&config)).unwrap();
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let start = Instant::now();
+        assert_eq!((0..20).filter(|_| plugin.allow(ip, start)).count(), 10);
+        // A tenth of a second buys one more query.
+        assert!(plugin.allow(ip, start + Duration::from_millis(100)));
+        assert!(!plugin.allow(ip, start + Duration::from_millis(100)));
+
+        let config: serde_yaml::Value = serde_yaml::from_str("qps: 0").unwrap();
+        assert!(RateLimit::new(Some(&config)).is_err());
+    }
+
+    #[test]
+    fn test_clients_bounded() {
+        let config: serde_yaml::Value = serde_yaml::from_str("qps: 1").unwrap();
+        let plugin = RateLimit::new(Some(&config)).unwrap();
+        let now = Instant::now();
+        let first: IpAddr = "192.0.2.1".parse().unwrap();
+        assert!(plugin.allow(first, now));
+        assert!(!plugin.allow(first, now));
+
+        for i in 0..MAX_CLIENTS as u32 {
+            assert!(plugin.allow(IpAddr::from((0x0a00_0000 + i).to_be_bytes()), now));
+        }
+        assert_eq!(plugin.buckets.lock().unwrap().len(), MAX_CLIENTS);
+        // The least recently seen client was forgotten, so it starts afresh.
+        assert!(plugin.allow(first, now));
+    }
+}
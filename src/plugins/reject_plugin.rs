@@ -1,13 +1,23 @@
 use super::{Context, Plugin};
-use anyhow::Result;
+use anyhow::{Context as AnyhowContext, Result};
 use async_trait::async_trait;
 use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::rr::rdata::{HTTPS, SVCB};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
 use serde::Deserialize;
+use std::str::FromStr;
 
 #[derive(Deserialize)]
 struct RejectConfig {
     #[serde(default = "default_rcode")]
     rcode: u8, // 5 = REFUSED, 3 = NXDOMAIN
+    /// Host serving a block explanation page; HTTPS/SVCB queries are answered
+    /// with a record pointing browsers at it instead of failing.
+    #[serde(default)]
+    block_page: Option<String>,
+    /// TTL of block page records; the server's `default_ttl` if unset.
+    #[serde(default)]
+    ttl: Option<u32>,
 }
 
 fn default_rcode() -> u8 {
@@ -16,6 +26,8 @@ fn default_rcode() -> u8 {
 
 pub struct RejectPlugin {
     rcode: ResponseCode,
+    block_page: Option<Name>,
+    ttl: Option<u32>,
 }
 
 impl RejectPlugin {
@@ -23,13 +35,28 @@ impl RejectPlugin {
         let config: RejectConfig = if let Some(c) = config {
             serde_yaml::from_value(c.clone())?
         } else {
-            RejectConfig { rcode: 5 }
+            RejectConfig {
+                rcode: 5,
+                block_page: None,
+                ttl: None,
+            }
         };
 
         // Convert u8 to ResponseCode safely (assuming low bits only for now)
         let rcode = ResponseCode::from(0, config.rcode);
 
-        Ok(Self { rcode })
+        let block_page = config
+            .block_page
+            .map(|host| {
+                Name::from_str(&host).with_context(|| format!("Invalid block_page host: {}", host))
+            })
+            .transpose()?;
+
+        Ok(Self {
+            rcode,
+            block_page,
+            ttl: config.ttl,
+        })
     }
 }
 
@@ -41,7 +68,7 @@ impl Plugin for RejectPlugin {
 
     async fn next(&self, ctx: &mut Context) -> Result<()> {
         let mut response = Message::new();
-        response.set_header(ctx.request.header().clone());
+        response.set_header(*ctx.request.header());
         response.set_response_code(self.rcode);
         // Ensure it's a response
         response.set_message_type(hickory_proto::op::MessageType::Response);
@@ -49,6 +76,24 @@ impl Plugin for RejectPlugin {
         // Copy id
         response.set_id(ctx.request.id());
 
+        if let (Some(target), Some(query)) = (&self.block_page, ctx.request.query()) {
+            // ServiceMode record with no parameters: "connect to `target` instead".
+            let svcb = SVCB::new(1, target.clone(), vec![]);
+            let rdata = match query.query_type() {
+                RecordType::HTTPS => Some(RData::HTTPS(HTTPS(svcb))),
+                RecordType::SVCB => Some(RData::SVCB(svcb)),
+                _ => None,
+            };
+            if let Some(rdata) = rdata {
+                response.set_response_code(ResponseCode::NoError);
+                response.add_answer(Record::from_rdata(
+                    query.name().clone(),
+                    self.ttl.unwrap_or(ctx.default_ttl),
+                    rdata,
+                ));
+            }
+        }
+
         ctx.response = Some(response);
         ctx.abort = true;
         Ok(())
@@ -90,4 +135,59 @@ mod tests {
         );
         assert!(ctx.abort);
     }
+
+    #[tokio::test]
+    async fn test_block_page_https() {
+        use hickory_proto::op::Query;
+
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("rcode: 3\nblock_page: blocked.lan").unwrap();
+        let plugin = RejectPlugin::new(Some(&config)).unwrap();
+
+        let mut ctx = make_ctx();
+        ctx.request.add_query(Query::query(
+            Name::from_str("ads.example.").unwrap(),
+            RecordType::HTTPS,
+        ));
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        match response.answers()[0].data() {
+            Some(RData::HTTPS(HTTPS(svcb))) => {
+                assert_eq!(svcb.svc_priority(), 1);
+                assert_eq!(svcb.target_name(), &Name::from_str("blocked.lan").unwrap());
+            }
+            other => panic!("Expected HTTPS record, got {:?}", other),
+        }
+
+        // Other types are rejected as configured.
+        let mut ctx = make_ctx();
+        ctx.request.add_query(Query::query(
+            Name::from_str("ads.example.").unwrap(),
+            RecordType::A,
+        ));
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NXDomain);
+        assert!(response.answers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_block_page_ttl() {
+        use hickory_proto::op::Query;
+
+        for (extra, expected) in [("", 300), ("\nttl: 5", 5)] {
+            let yaml = format!("block_page: blocked.lan{}", extra);
+            let plugin = RejectPlugin::new(Some(&serde_yaml::from_str(&yaml).unwrap())).unwrap();
+            let mut ctx = make_ctx();
+            ctx.default_ttl = 300;
+            ctx.request.add_query(Query::query(
+                Name::from_str("ads.example.").unwrap(),
+                RecordType::HTTPS,
+            ));
+            plugin.next(&mut ctx).await.unwrap();
+            assert_eq!(ctx.response.unwrap().answers()[0].ttl(), expected);
+        }
+    }
 }
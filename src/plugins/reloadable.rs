@@ -0,0 +1,191 @@
+use super::{
+    CacheKeyStats, CacheSnapshotEntry, CacheStore, Condition, Context, DomainSet, IpSet,
+    OverrideAction, OverrideStore, Plugin, SharedPlugin, TxtStore, TxtValue, UpstreamHealth,
+    UpstreamHealthReporter,
+};
+use crate::config::PluginConfig;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::RwLock;
+use tracing::info;
+
+/// Registry slot that delegates to a plugin which can be rebuilt from its config
+/// and swapped in at runtime.
+///
+/// Every plugin in the registry is wrapped in one, so containers that captured a
+/// reference at startup see the new instance after a reload.
+pub struct Reloadable {
+    name: String,
+    config: PluginConfig,
+    inner: RwLock<SharedPlugin>,
+}
+
+impl Reloadable {
+    pub fn new(config: PluginConfig, plugin: SharedPlugin) -> Self {
+        Self {
+            name: plugin.name().to_string(),
+            config,
+            inner: RwLock::new(plugin),
+        }
+    }
+
+    /// Rebuild the plugin from its stored config and swap it in.
+    ///
+    /// The current instance stays in place if the rebuild fails.
+    pub fn reload(&self, registry: &HashMap<String, SharedPlugin>) -> Result<()> {
+        let plugin = crate::build_plugin(&self.config, registry)?
+            .ok_or_else(|| anyhow::anyhow!("Unknown plugin type: {}", self.config.type_))?;
+        *self.inner.write().unwrap() = plugin;
+        info!("Reloaded plugin {}", self.config.tag);
+        Ok(())
+    }
+
+    fn current(&self) -> SharedPlugin {
+        self.inner.read().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl Plugin for Reloadable {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn tag(&self) -> Option<&str> {
+        Some(&self.config.tag)
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        // Clone out of the lock so a reload never waits on an in-flight query.
+        self.current().next(ctx).await
+    }
+
+    fn as_domain_set(&self) -> Option<&dyn DomainSet> {
+        self.current().as_domain_set().is_some().then_some(self)
+    }
+
+    fn as_ip_set(&self) -> Option<&dyn IpSet> {
+        self.current().as_ip_set().is_some().then_some(self)
+    }
+
+    fn as_condition(&self) -> Option<&dyn Condition> {
+        self.current().as_condition().is_some().then_some(self)
+    }
+
+    fn as_cache_store(&self) -> Option<&dyn CacheStore> {
+        self.current().as_cache_store().is_some().then_some(self)
+    }
+
+    fn as_upstream_health(&self) -> Option<&dyn UpstreamHealthReporter> {
+        self.current()
+            .as_upstream_health()
+            .is_some()
+            .then_some(self)
+    }
+
+    fn as_override_store(&self) -> Option<&dyn OverrideStore> {
+        self.current().as_override_store().is_some().then_some(self)
+    }
+
+    fn as_txt_store(&self) -> Option<&dyn TxtStore> {
+        self.current().as_txt_store().is_some().then_some(self)
+    }
+
+    fn as_reloadable(&self) -> Option<&Reloadable> {
+        Some(self)
+    }
+}
+
+impl DomainSet for Reloadable {
+    fn contains(&self, domain: &str) -> bool {
+        self.current()
+            .as_domain_set()
+            .is_some_and(|ds| ds.contains(domain))
+    }
+}
+
+impl IpSet for Reloadable {
+    fn contains(&self, ip: IpAddr) -> bool {
+        self.current().as_ip_set().is_some_and(|is| is.contains(ip))
+    }
+}
+
+impl Condition for Reloadable {
+    fn check(&self, ctx: &Context) -> bool {
+        self.current().as_condition().is_some_and(|c| c.check(ctx))
+    }
+}
+
+impl CacheStore for Reloadable {
+    fn top_keys(&self, n: usize) -> Vec<CacheKeyStats> {
+        self.current()
+            .as_cache_store()
+            .map(|c| c.top_keys(n))
+            .unwrap_or_default()
+    }
+
+    fn export_entries(&self) -> Vec<CacheSnapshotEntry> {
+        self.current()
+            .as_cache_store()
+            .map(|c| c.export_entries())
+            .unwrap_or_default()
+    }
+
+    fn import_entries(&self, entries: Vec<CacheSnapshotEntry>) {
+        if let Some(c) = self.current().as_cache_store() {
+            c.import_entries(entries);
+        }
+    }
+}
+
+impl OverrideStore for Reloadable {
+    fn overrides(&self) -> BTreeMap<String, OverrideAction> {
+        self.current()
+            .as_override_store()
+            .map(|o| o.overrides())
+            .unwrap_or_default()
+    }
+
+    fn set_override(&self, name: &str, action: OverrideAction) {
+        if let Some(o) = self.current().as_override_store() {
+            o.set_override(name, action);
+        }
+    }
+
+    fn remove_override(&self, name: &str) -> bool {
+        self.current()
+            .as_override_store()
+            .is_some_and(|o| o.remove_override(name))
+    }
+}
+
+impl TxtStore for Reloadable {
+    fn accepts(&self, name: &str) -> bool {
+        self.current()
+            .as_txt_store()
+            .is_some_and(|t| t.accepts(name))
+    }
+
+    fn add_txt(&self, name: &str, value: TxtValue) {
+        if let Some(t) = self.current().as_txt_store() {
+            t.add_txt(name, value);
+        }
+    }
+
+    fn remove_txt(&self, name: &str) -> bool {
+        self.current()
+            .as_txt_store()
+            .is_some_and(|t| t.remove_txt(name))
+    }
+}
+
+impl UpstreamHealthReporter for Reloadable {
+    fn upstream_health(&self) -> Vec<UpstreamHealth> {
+        self.current()
+            .as_upstream_health()
+            .map(|h| h.upstream_health())
+            .unwrap_or_default()
+    }
+}
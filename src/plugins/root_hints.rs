@@ -0,0 +1,245 @@
+use super::{Context, Plugin};
+use anyhow::{Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use hickory_proto::op::{Message, MessageType, OpCode, ResponseCode};
+use hickory_proto::rr::rdata::{A, AAAA, NS};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use serde::Deserialize;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use tracing::debug;
+
+/// IANA root hints (named.root), used when no `file` is configured.
+const BUNDLED_ROOT_HINTS: &str = "\
+.                        3600000      NS    A.ROOT-SERVERS.NET.
+A.ROOT-SERVERS.NET.      3600000      A     198.41.0.4
+A.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:ba3e::2:30
+.                        3600000      NS    B.ROOT-SERVERS.NET.
+B.ROOT-SERVERS.NET.      3600000      A     170.247.170.2
+B.ROOT-SERVERS.NET.      3600000      AAAA  2801:1b8:10::b
+.                        3600000      NS    C.ROOT-SERVERS.NET.
+C.ROOT-SERVERS.NET.      3600000      A     192.33.4.12
+C.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:2::c
+.                        3600000      NS    D.ROOT-SERVERS.NET.
+D.ROOT-SERVERS.NET.      3600000      A     199.7.91.13
+D.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:2d::d
+.                        3600000      NS    E.ROOT-SERVERS.NET.
+E.ROOT-SERVERS.NET.      3600000      A     192.203.230.10
+E.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:a8::e
+.                        3600000      NS    F.ROOT-SERVERS.NET.
+F.ROOT-SERVERS.NET.      3600000      A     192.5.5.241
+F.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:2f::f
+.                        3600000      NS    G.ROOT-SERVERS.NET.
+G.ROOT-SERVERS.NET.      3600000      A     192.112.36.4
+G.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:12::d0d
+.                        3600000      NS    H.ROOT-SERVERS.NET.
+H.ROOT-SERVERS.NET.      3600000      A     198.97.190.53
+H.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:1::53
+.                        3600000      NS    I.ROOT-SERVERS.NET.
+I.ROOT-SERVERS.NET.      3600000      A     192.36.148.17
+I.ROOT-SERVERS.NET.      3600000      AAAA  2001:7fe::53
+.                        3600000      NS    J.ROOT-SERVERS.NET.
+J.ROOT-SERVERS.NET.      3600000      A     192.58.128.30
+J.ROOT-SERVERS.NET.      3600000      AAAA  2001:503:c27::2:30
+.                        3600000      NS    K.ROOT-SERVERS.NET.
+K.ROOT-SERVERS.NET.      3600000      A     193.0.14.129
+K.ROOT-SERVERS.NET.      3600000      AAAA  2001:7fd::1
+.                        3600000      NS    L.ROOT-SERVERS.NET.
+L.ROOT-SERVERS.NET.      3600000      A     199.7.83.42
+L.ROOT-SERVERS.NET.      3600000      AAAA  2001:500:9f::42
+.                        3600000      NS    M.ROOT-SERVERS.NET.
+M.ROOT-SERVERS.NET.      3600000      A     202.12.27.33
+M.ROOT-SERVERS.NET.      3600000      AAAA  2001:dc3::35
+";
+
+#[derive(Deserialize)]
+struct RootHintsConfig {
+    #[serde(default)]
+    file: Option<String>,
+}
+
+/// Answers root priming queries (`. NS`) locally from root hints.
+pub struct RootHints {
+    ns: Vec<Record>,
+    glue: Vec<Record>,
+}
+
+impl RootHints {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: RootHintsConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            RootHintsConfig { file: None }
+        };
+
+        let hints = match &config.file {
+            Some(path) => std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read root hints file {}", path))?,
+            None => BUNDLED_ROOT_HINTS.to_string(),
+        };
+
+        let (ns, glue) = Self::parse_hints(&hints)?;
+        if ns.is_empty() {
+            return Err(anyhow::anyhow!("Root hints contain no NS records"));
+        }
+        Ok(Self { ns, glue })
+    }
+
+    /// Parse named.root style lines: `<name> [ttl] [class] <type> <data>`.
+    fn parse_hints(text: &str) -> Result<(Vec<Record>, Vec<Record>)> {
+        let mut ns = Vec::new();
+        let mut glue = Vec::new();
+
+        for line in text.lines() {
+            let line = line.split(';').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            // Skip the optional TTL and IN class columns.
+            let mut rest = &fields[1..];
+            let mut ttl = 3600000;
+            if let Some(t) = rest.first().and_then(|t| t.parse().ok()) {
+                ttl = t;
+                rest = &rest[1..];
+            }
+            if rest.first().is_some_and(|c| c.eq_ignore_ascii_case("IN")) {
+                rest = &rest[1..];
+            }
+            let (rtype, data) = match rest {
+                [rtype, data] => (rtype.to_ascii_uppercase(), *data),
+                _ => return Err(anyhow::anyhow!("Invalid root hints line: {}", line)),
+            };
+
+            let name = Name::from_str(fields[0])
+                .with_context(|| format!("Invalid name in root hints: {}", fields[0]))?;
+            let rdata =
+                match rtype.as_str() {
+                    "NS" => RData::NS(NS(Name::from_str(data)
+                        .with_context(|| format!("Invalid NS target in root hints: {}", data))?)),
+                    "A" => RData::A(A(Ipv4Addr::from_str(data).with_context(|| {
+                        format!("Invalid A address in root hints: {}", data)
+                    })?)),
+                    "AAAA" => RData::AAAA(AAAA(Ipv6Addr::from_str(data).with_context(|| {
+                        format!("Invalid AAAA address in root hints: {}", data)
+                    })?)),
+                    other => {
+                        return Err(anyhow::anyhow!(
+                            "Unsupported record type in root hints: {}",
+                            other
+                        ))
+                    }
+                };
+
+            let record = Record::from_rdata(name, ttl, rdata);
+            if record.record_type() == RecordType::NS {
+                ns.push(record);
+            } else {
+                glue.push(record);
+            }
+        }
+        Ok((ns, glue))
+    }
+}
+
+#[async_trait]
+impl Plugin for RootHints {
+    fn name(&self) -> &str {
+        "root_hints"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        if ctx.response.is_some() {
+            return Ok(());
+        }
+
+        if let Some(query) = ctx.request.query() {
+            if !query.name().is_root() || query.query_type() != RecordType::NS {
+                return Ok(());
+            }
+
+            let mut response = Message::new();
+            response.set_id(ctx.request.id());
+            response.set_message_type(MessageType::Response);
+            response.set_op_code(OpCode::Query);
+            response.set_recursion_desired(ctx.request.recursion_desired());
+            response.set_recursion_available(true);
+            response.set_response_code(ResponseCode::NoError);
+            response.add_query(query.clone());
+            response.add_answers(self.ns.iter().cloned());
+            response.add_additionals(self.glue.iter().cloned());
+
+            ctx.response = Some(response);
+            debug!("Answered root priming query from local hints");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::Query;
+    use std::sync::{Arc, RwLock};
+
+    fn make_ctx(name: &str, qtype: RecordType) -> Context {
+        use crate::statistics::Statistics;
+        use std::net::{IpAddr, SocketAddr};
+
+        let mut msg = Message::new();
+        msg.set_id(42);
+        msg.add_query(Query::query(Name::from_str(name).unwrap(), qtype));
+
+        Context::new(
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 1234),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_root_priming() {
+        let plugin = RootHints::new(None).unwrap();
+
+        let mut ctx = make_ctx(".", RecordType::NS);
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.id(), 42);
+        assert_eq!(response.answers().len(), 13);
+        assert!(response.answers().iter().any(|r| matches!(
+            r.data(),
+            Some(RData::NS(ns)) if ns.0 == Name::from_str("a.root-servers.net.").unwrap()
+        )));
+        assert_eq!(response.additionals().len(), 26);
+
+        // Non-root queries pass through.
+        let mut ctx = make_ctx("example.com.", RecordType::NS);
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.is_none());
+        let mut ctx = make_ctx(".", RecordType::SOA);
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_root_hints_file() {
+        use std::io::Write;
+        use tempfile::NamedTempFile;
+
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "; local root").unwrap();
+        writeln!(file, ".  3600  IN  NS  root.lan.").unwrap();
+        writeln!(file, "root.lan.  3600  IN  A  10.0.0.53").unwrap();
+
+        let yaml = format!("file: \"{}\"", file.path().to_str().unwrap());
+        let config: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        let plugin = RootHints::new(Some(&config)).unwrap();
+
+        let mut ctx = make_ctx(".", RecordType::NS);
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].ttl(), 3600);
+        assert_eq!(response.additionals().len(), 1);
+    }
+}
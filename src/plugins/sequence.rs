@@ -1,4 +1,4 @@
-use super::{Context, Plugin, SharedPlugin};
+use super::{exec, Context, Plugin, SharedPlugin};
 use anyhow::{Context as AnyhowContext, Result};
 use async_trait::async_trait;
 use serde::Deserialize;
@@ -7,11 +7,26 @@ use tracing::debug;
 
 #[derive(Deserialize)]
 struct SequenceConfig {
-    exec: Vec<String>,
+    exec: Vec<ExecEntry>,
+}
+
+/// An `exec` entry: a plugin tag, or `{goto: tag}`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ExecEntry {
+    Tag(String),
+    Goto { goto: String },
+}
+
+enum Step {
+    Run(SharedPlugin),
+    /// Run the plugin, then end the chain (mosdns' `goto`): control does not
+    /// come back to this sequence or the ones that called it.
+    Goto(SharedPlugin),
 }
 
 pub struct Sequence {
-    plugins: Vec<SharedPlugin>,
+    steps: Vec<Step>,
 }
 
 impl Sequence {
@@ -25,15 +40,21 @@ impl Sequence {
             SequenceConfig { exec: vec![] }
         };
 
-        let mut plugins = Vec::new();
-        for tag in config.exec {
-            let p = registry
-                .get(&tag)
-                .ok_or_else(|| anyhow::anyhow!("Plugin not found: {}", tag))?;
-            plugins.push(p.clone());
+        let lookup = |tag: &str| {
+            registry
+                .get(tag)
+                .cloned()
+                .ok_or_else(|| anyhow::anyhow!("Plugin not found: {}", tag))
+        };
+        let mut steps = Vec::new();
+        for entry in config.exec {
+            steps.push(match entry {
+                ExecEntry::Tag(tag) => Step::Run(lookup(&tag)?),
+                ExecEntry::Goto { goto } => Step::Goto(lookup(&goto)?),
+            });
         }
 
-        Ok(Self { plugins })
+        Ok(Self { steps })
     }
 }
 
@@ -44,15 +65,20 @@ impl Plugin for Sequence {
     }
 
     async fn next(&self, ctx: &mut Context) -> Result<()> {
-        for plugin in &self.plugins {
+        for step in &self.steps {
             if ctx.abort {
                 debug!("Sequence aborted");
                 break;
             }
-            plugin
-                .next(ctx)
+            let (Step::Run(plugin) | Step::Goto(plugin)) = step;
+            exec(plugin, ctx)
                 .await
                 .with_context(|| format!("Plugin {} failed", plugin.name()))?;
+            if let Step::Goto(_) = step {
+                debug!("Sequence jumped to {}", plugin.name());
+                ctx.abort = true;
+                break;
+            }
         }
         Ok(())
     }
@@ -127,4 +153,79 @@ mod tests {
         assert_eq!(*c1.lock().unwrap(), 1);
         assert_eq!(*c2.lock().unwrap(), 1);
     }
+
+    #[tokio::test]
+    async fn test_goto_does_not_return() {
+        use crate::plugins::static_response::StaticResponse;
+        use hickory_proto::op::{Query, ResponseCode};
+        use hickory_proto::rr::{Name, RecordType};
+
+        let (after, after_count) = make_mock("after");
+        let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+        registry.insert("after".to_string(), after);
+        registry.insert(
+            "answer".to_string(),
+            Arc::new(StaticResponse::new(None).unwrap()),
+        );
+        let config: serde_yaml::Value = serde_yaml::from_str("exec: [answer]").unwrap();
+        registry.insert(
+            "local".to_string(),
+            Arc::new(Sequence::new(Some(&config), &registry).unwrap()),
+        );
+
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("exec: [{goto: local}, after]").unwrap();
+        let sequence = Sequence::new(Some(&config), &registry).unwrap();
+        let mut ctx = make_ctx();
+        ctx.request.add_query(Query::query(
+            Name::from_ascii("example.com.").unwrap(),
+            RecordType::A,
+        ));
+        sequence.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        assert!(ctx.abort);
+        assert_eq!(*after_count.lock().unwrap(), 0);
+
+        let config: serde_yaml::Value = serde_yaml::from_str("exec: [{goto: missing}]").unwrap();
+        assert!(Sequence::new(Some(&config), &registry).is_err());
+    }
+
+    // Plugin that jumps back into a sequence, forming a loop.
+    struct LoopBack {
+        target: std::sync::OnceLock<SharedPlugin>,
+    }
+
+    #[async_trait]
+    impl Plugin for LoopBack {
+        fn name(&self) -> &str {
+            "loop_back"
+        }
+        async fn next(&self, ctx: &mut Context) -> Result<()> {
+            exec(self.target.get().unwrap(), ctx).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_self_referential_sequence_trips_depth_guard() {
+        use crate::plugins::DepthExceeded;
+
+        let loop_back = Arc::new(LoopBack {
+            target: std::sync::OnceLock::new(),
+        });
+        let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+        registry.insert("loop_back".to_string(), loop_back.clone());
+
+        let config: serde_yaml::Value = serde_yaml::from_str("exec: [loop_back]").unwrap();
+        let sequence: SharedPlugin = Arc::new(Sequence::new(Some(&config), &registry).unwrap());
+        let _ = loop_back.target.set(sequence.clone());
+
+        let mut ctx = make_ctx();
+        ctx.max_depth = 8;
+        let err = sequence.next(&mut ctx).await.unwrap_err();
+        let depth = err.downcast_ref::<DepthExceeded>().unwrap();
+        assert_eq!(depth.max_depth, 8);
+        assert_eq!(ctx.depth, 0);
+    }
 }
@@ -0,0 +1,148 @@
+use super::{Context, Plugin};
+use crate::dns;
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{RData, Record, RecordType};
+use serde::Deserialize;
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+fn default_ipv4() -> Ipv4Addr {
+    Ipv4Addr::UNSPECIFIED
+}
+
+fn default_ipv6() -> Ipv6Addr {
+    Ipv6Addr::UNSPECIFIED
+}
+
+#[derive(Deserialize)]
+struct SinkholeConfig {
+    /// Address A queries are answered with.
+    #[serde(default = "default_ipv4")]
+    ipv4: Ipv4Addr,
+    /// Address AAAA queries are answered with.
+    #[serde(default = "default_ipv6")]
+    ipv6: Ipv6Addr,
+    /// TTL of the answers; the server's `default_ttl` if unset.
+    #[serde(default)]
+    ttl: Option<u32>,
+}
+
+/// Answers A/AAAA queries with a fixed address (e.g. `0.0.0.0` or a local web
+/// server) and ends the chain, so blocked names fail fast in browsers instead
+/// of looking nonexistent. Other query types get NODATA.
+pub struct Sinkhole {
+    ipv4: Ipv4Addr,
+    ipv6: Ipv6Addr,
+    ttl: Option<u32>,
+}
+
+impl Sinkhole {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: SinkholeConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            SinkholeConfig {
+                ipv4: default_ipv4(),
+                ipv6: default_ipv6(),
+                ttl: None,
+            }
+        };
+        Ok(Self {
+            ipv4: config.ipv4,
+            ipv6: config.ipv6,
+            ttl: config.ttl,
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for Sinkhole {
+    fn name(&self) -> &str {
+        "sinkhole"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        let Some(query) = ctx.request.query() else {
+            return Ok(());
+        };
+
+        let ttl = self.ttl.unwrap_or(ctx.default_ttl);
+        let mut response = dns::empty_response(&ctx.request, ResponseCode::NoError);
+        let rdata = match query.query_type() {
+            RecordType::A => Some(RData::A(A(self.ipv4))),
+            RecordType::AAAA => Some(RData::AAAA(AAAA(self.ipv6))),
+            _ => None,
+        };
+        if let Some(rdata) = rdata {
+            response.add_answer(Record::from_rdata(query.name().clone(), ttl, rdata));
+        }
+        ctx.response = Some(response);
+        ctx.abort = true;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::Name;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, RwLock};
+
+    fn make_ctx(qtype: RecordType) -> Context {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(
+            Name::from_ascii("ads.example.").unwrap(),
+            qtype,
+        ));
+        Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_sinkhole_a() {
+        let plugin = Sinkhole::new(None).unwrap();
+        let mut ctx = make_ctx(RecordType::A);
+        ctx.default_ttl = 120;
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.abort);
+        let response = ctx.response.unwrap();
+        assert_eq!(response.answers().len(), 1);
+        // Without its own ttl the plugin uses the server's default.
+        assert_eq!(response.answers()[0].ttl(), 120);
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&RData::A(A(Ipv4Addr::UNSPECIFIED)))
+        );
+
+        // Other types are NODATA.
+        let mut ctx = make_ctx(RecordType::MX);
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sinkhole_aaaa() {
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("ipv4: 192.168.1.10\nipv6: \"fd00::10\"\nttl: 30").unwrap();
+        let plugin = Sinkhole::new(Some(&config)).unwrap();
+        let mut ctx = make_ctx(RecordType::AAAA);
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].ttl(), 30);
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&RData::AAAA(AAAA("fd00::10".parse().unwrap())))
+        );
+    }
+}
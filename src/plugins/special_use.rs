@@ -0,0 +1,223 @@
+use super::{Context, Plugin};
+use crate::dns;
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use serde::Deserialize;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use tracing::debug;
+
+/// Reverse zones of private and link-local ranges (RFC 1918, RFC 3927, RFC 4193, RFC 4291).
+const PRIVATE_REVERSE_ZONES: &[&str] = &[
+    "10.in-addr.arpa.",
+    "16.172.in-addr.arpa.",
+    "17.172.in-addr.arpa.",
+    "18.172.in-addr.arpa.",
+    "19.172.in-addr.arpa.",
+    "20.172.in-addr.arpa.",
+    "21.172.in-addr.arpa.",
+    "22.172.in-addr.arpa.",
+    "23.172.in-addr.arpa.",
+    "24.172.in-addr.arpa.",
+    "25.172.in-addr.arpa.",
+    "26.172.in-addr.arpa.",
+    "27.172.in-addr.arpa.",
+    "28.172.in-addr.arpa.",
+    "29.172.in-addr.arpa.",
+    "30.172.in-addr.arpa.",
+    "31.172.in-addr.arpa.",
+    "168.192.in-addr.arpa.",
+    "254.169.in-addr.arpa.",
+    "c.f.ip6.arpa.",
+    "d.f.ip6.arpa.",
+    "8.e.f.ip6.arpa.",
+    "9.e.f.ip6.arpa.",
+    "a.e.f.ip6.arpa.",
+    "b.e.f.ip6.arpa.",
+];
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Deserialize)]
+struct SpecialUseConfig {
+    /// Answer `localhost.` and its subdomains with loopback addresses.
+    #[serde(default = "default_true")]
+    localhost: bool,
+    /// Answer `invalid.` names with NXDOMAIN.
+    #[serde(default = "default_true")]
+    invalid: bool,
+    /// Answer `test.` names with NXDOMAIN.
+    #[serde(default = "default_true")]
+    test: bool,
+    /// Answer reverse lookups for private ranges with NXDOMAIN.
+    #[serde(default = "default_true")]
+    private_reverse: bool,
+}
+
+/// Answers RFC 6761 special-use names locally so they never leak upstream.
+///
+/// Runs only when no earlier plugin produced a response, so a `hosts` plugin with
+/// PTR data placed before it still answers private reverse lookups.
+pub struct SpecialUse {
+    localhost: Option<Name>,
+    nxdomain_zones: Vec<Name>,
+}
+
+impl SpecialUse {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: SpecialUseConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            SpecialUseConfig {
+                localhost: true,
+                invalid: true,
+                test: true,
+                private_reverse: true,
+            }
+        };
+
+        let mut zones = Vec::new();
+        if config.invalid {
+            zones.push("invalid.");
+        }
+        if config.test {
+            zones.push("test.");
+        }
+        if config.private_reverse {
+            zones.extend_from_slice(PRIVATE_REVERSE_ZONES);
+        }
+
+        Ok(Self {
+            localhost: config
+                .localhost
+                .then(|| Name::from_ascii("localhost."))
+                .transpose()?,
+            nxdomain_zones: zones
+                .into_iter()
+                .map(Name::from_ascii)
+                .collect::<Result<_, _>>()?,
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for SpecialUse {
+    fn name(&self) -> &str {
+        "special_use"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        if ctx.response.is_some() {
+            return Ok(());
+        }
+
+        let query = match ctx.request.query() {
+            Some(q) => q.clone(),
+            None => return Ok(()),
+        };
+        let name = query.name();
+
+        if self.localhost.as_ref().is_some_and(|l| l.zone_of(name)) {
+            let mut response = dns::empty_response(&ctx.request, ResponseCode::NoError);
+            response.set_authoritative(true);
+            let rdata = match query.query_type() {
+                RecordType::A => Some(RData::A(A(Ipv4Addr::LOCALHOST))),
+                RecordType::AAAA => Some(RData::AAAA(AAAA(Ipv6Addr::LOCALHOST))),
+                _ => None,
+            };
+            if let Some(rdata) = rdata {
+                response.add_answer(Record::from_rdata(name.clone(), 0, rdata));
+            }
+            debug!("Answered special-use name {} locally", name);
+            ctx.response = Some(response);
+        } else if self.nxdomain_zones.iter().any(|z| z.zone_of(name)) {
+            let mut response = dns::empty_response(&ctx.request, ResponseCode::NXDomain);
+            response.set_authoritative(true);
+            debug!("Answered special-use name {} with NXDOMAIN", name);
+            ctx.response = Some(response);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::{Message, Query};
+    use std::str::FromStr;
+    use std::sync::{Arc, RwLock};
+
+    fn make_ctx(name: &str, qtype: RecordType) -> Context {
+        use crate::statistics::Statistics;
+        use std::net::{IpAddr, SocketAddr};
+
+        let mut msg = Message::new();
+        msg.set_id(7);
+        msg.add_query(Query::query(Name::from_str(name).unwrap(), qtype));
+
+        Context::new(
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 1234),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_localhost() {
+        let plugin = SpecialUse::new(None).unwrap();
+
+        let mut ctx = make_ctx("localhost.", RecordType::A);
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.id(), 7);
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&RData::A(A(Ipv4Addr::LOCALHOST)))
+        );
+
+        let mut ctx = make_ctx("app.localhost.", RecordType::AAAA);
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(
+            ctx.response.unwrap().answers()[0].data(),
+            Some(&RData::AAAA(AAAA(Ipv6Addr::LOCALHOST)))
+        );
+
+        let config: serde_yaml::Value = serde_yaml::from_str("localhost: false").unwrap();
+        let plugin = SpecialUse::new(Some(&config)).unwrap();
+        let mut ctx = make_ctx("localhost.", RecordType::A);
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_and_private_reverse() {
+        let plugin = SpecialUse::new(None).unwrap();
+
+        for name in ["invalid.", "foo.invalid.", "1.1.168.192.in-addr.arpa."] {
+            let mut ctx = make_ctx(name, RecordType::A);
+            plugin.next(&mut ctx).await.unwrap();
+            assert_eq!(
+                ctx.response.unwrap().response_code(),
+                ResponseCode::NXDomain,
+                "{}",
+                name
+            );
+        }
+
+        // Public names and public reverse zones pass through.
+        for name in [
+            "example.com.",
+            "invalid.example.com.",
+            "8.8.8.8.in-addr.arpa.",
+        ] {
+            let mut ctx = make_ctx(name, RecordType::A);
+            plugin.next(&mut ctx).await.unwrap();
+            assert!(ctx.response.is_none(), "{}", name);
+        }
+    }
+}
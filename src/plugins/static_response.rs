@@ -0,0 +1,138 @@
+use super::{Context, Plugin};
+use crate::dns;
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{RData, Record, RecordType};
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr};
+
+fn default_ips() -> Vec<IpAddr> {
+    vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]
+}
+
+#[derive(Deserialize)]
+struct StaticResponseConfig {
+    /// Addresses answered; A queries get the IPv4 ones, AAAA the IPv6 ones.
+    #[serde(default = "default_ips")]
+    ips: Vec<IpAddr>,
+    /// The server's `default_ttl` if unset.
+    #[serde(default)]
+    ttl: Option<u32>,
+}
+
+/// Answers every query with the same addresses, without any lookup.
+///
+/// Meant for benchmarking the server's packet handling in isolation from
+/// upstream latency; other query types get an empty NOERROR answer.
+pub struct StaticResponse {
+    ips: Vec<IpAddr>,
+    ttl: Option<u32>,
+}
+
+impl StaticResponse {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: StaticResponseConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            StaticResponseConfig {
+                ips: default_ips(),
+                ttl: None,
+            }
+        };
+        Ok(Self {
+            ips: config.ips,
+            ttl: config.ttl,
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for StaticResponse {
+    fn name(&self) -> &str {
+        "static_response"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        if ctx.response.is_some() {
+            return Ok(());
+        }
+        let Some(query) = ctx.request.query() else {
+            return Ok(());
+        };
+
+        let ttl = self.ttl.unwrap_or(ctx.default_ttl);
+        let mut response = dns::empty_response(&ctx.request, ResponseCode::NoError);
+        for ip in &self.ips {
+            let rdata = match (ip, query.query_type()) {
+                (IpAddr::V4(v4), RecordType::A) => RData::A(A(*v4)),
+                (IpAddr::V6(v6), RecordType::AAAA) => RData::AAAA(AAAA(*v6)),
+                _ => continue,
+            };
+            response.add_answer(Record::from_rdata(query.name().clone(), ttl, rdata));
+        }
+        ctx.response = Some(response);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::Name;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, RwLock};
+
+    fn make_ctx(name: &str, qtype: RecordType) -> Context {
+        let mut msg = Message::new();
+        msg.set_id(4321);
+        msg.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+        Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_static_answer() {
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("ips: [10.1.2.3, \"2001:db8::1\"]\nttl: 5").unwrap();
+        let plugin = StaticResponse::new(Some(&config)).unwrap();
+
+        for name in ["Bench.Example.", "anything.test."] {
+            let mut ctx = make_ctx(name, RecordType::A);
+            plugin.next(&mut ctx).await.unwrap();
+            let response = ctx.response.unwrap();
+            assert_eq!(response.id(), 4321);
+            assert_eq!(response.queries(), ctx.request.queries());
+            assert_eq!(response.answers().len(), 1);
+            let answer = &response.answers()[0];
+            assert_eq!(answer.name(), &Name::from_ascii(name).unwrap());
+            assert_eq!(answer.ttl(), 5);
+            assert_eq!(
+                answer.data(),
+                Some(&RData::A(Ipv4Addr::new(10, 1, 2, 3).into()))
+            );
+        }
+
+        // Types without a configured address get NODATA.
+        let mut ctx = make_ctx("bench.example.", RecordType::MX);
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert!(response.answers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_default_ttl() {
+        let plugin = StaticResponse::new(None).unwrap();
+        let mut ctx = make_ctx("sink.example.", RecordType::A);
+        ctx.default_ttl = 900;
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(ctx.response.unwrap().answers()[0].ttl(), 900);
+    }
+}
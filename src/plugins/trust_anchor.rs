@@ -0,0 +1,255 @@
+use super::{Context, Plugin};
+use crate::dns::{self, canonical_name, decode_hex};
+use anyhow::{anyhow, bail, Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::rdata::NULL;
+use hickory_proto::rr::{Name, RData, Record, RecordType};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use tracing::debug;
+
+#[derive(Deserialize)]
+struct TrustAnchorConfig {
+    /// Trust anchor files with one DS or DNSKEY record per line, in zone file
+    /// presentation format.
+    #[serde(default)]
+    files: Vec<String>,
+    /// Records in the same format, given inline.
+    #[serde(default)]
+    anchors: Vec<String>,
+    /// TTL of records that don't set their own; the server's `default_ttl` if unset.
+    #[serde(default)]
+    ttl: Option<u32>,
+}
+
+/// Parse `<zone> [ttl] [IN] DS|DNSKEY <rdata>` into the zone's canonical name,
+/// the record (its rdata in wire format) and the line's own TTL.
+fn parse_anchor(line: &str) -> Result<(String, Record, Option<u32>)> {
+    let mut tokens = line.split_whitespace();
+    let zone = tokens.next().ok_or_else(|| anyhow!("empty record"))?;
+    let mut name = Name::from_ascii(zone).with_context(|| format!("Invalid zone: {}", zone))?;
+    name.set_fqdn(true);
+
+    let mut ttl = None;
+    let rtype = loop {
+        match tokens.next() {
+            Some(t) if t.eq_ignore_ascii_case("IN") => {}
+            Some(t) if ttl.is_none() && t.parse::<u32>().is_ok() => ttl = t.parse().ok(),
+            Some(t) => break t.to_ascii_uppercase(),
+            None => bail!("missing record type"),
+        }
+    };
+
+    let mut field = |what: &str| {
+        tokens
+            .next()
+            .ok_or_else(|| anyhow!("missing {}", what))
+            .map(str::to_string)
+    };
+    let (code, mut rdata) = match rtype.as_str() {
+        "DS" => {
+            let key_tag: u16 = field("key tag")?.parse()?;
+            let mut rdata = key_tag.to_be_bytes().to_vec();
+            rdata.push(field("algorithm")?.parse()?);
+            rdata.push(field("digest type")?.parse()?);
+            (RecordType::DS, rdata)
+        }
+        "DNSKEY" => {
+            let flags: u16 = field("flags")?.parse()?;
+            let mut rdata = flags.to_be_bytes().to_vec();
+            rdata.push(field("protocol")?.parse()?);
+            rdata.push(field("algorithm")?.parse()?);
+            (RecordType::DNSKEY, rdata)
+        }
+        other => bail!("Unsupported trust anchor type: {}", other),
+    };
+    // The digest or key may be split over several fields.
+    let rest: String = tokens.collect();
+    let tail = if code == RecordType::DS {
+        decode_hex(&rest).context("Invalid DS digest")?
+    } else {
+        STANDARD
+            .decode(&rest)
+            .context("Invalid DNSKEY public key")?
+    };
+    if tail.is_empty() {
+        bail!("missing digest or public key");
+    }
+    rdata.extend(tail);
+
+    let record = Record::from_rdata(
+        name,
+        0,
+        RData::Unknown {
+            code,
+            rdata: NULL::with(rdata),
+        },
+    );
+    Ok((canonical_name(zone), record, ttl))
+}
+
+/// Answers DS and DNSKEY queries for configured zones from a local trust
+/// store, so trust anchors never depend on an upstream being reachable.
+/// Other queries, and zones without a record of the asked type, pass on.
+pub struct TrustAnchor {
+    /// Records and their own TTL, by canonical zone name.
+    anchors: HashMap<String, Vec<(Record, Option<u32>)>>,
+    ttl: Option<u32>,
+}
+
+impl TrustAnchor {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: TrustAnchorConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            return Err(anyhow!("TrustAnchor requires config"));
+        };
+
+        let mut lines = config.anchors;
+        for path in &config.files {
+            let content = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read trust anchor file: {}", path))?;
+            lines.extend(content.lines().map(str::to_string));
+        }
+
+        let mut anchors: HashMap<String, Vec<(Record, Option<u32>)>> = HashMap::new();
+        for line in &lines {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+            let (zone, record, ttl) =
+                parse_anchor(line).with_context(|| format!("Invalid trust anchor: {}", line))?;
+            anchors.entry(zone).or_default().push((record, ttl));
+        }
+
+        Ok(Self {
+            anchors,
+            ttl: config.ttl,
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for TrustAnchor {
+    fn name(&self) -> &str {
+        "trust_anchor"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        if ctx.response.is_some() {
+            return Ok(());
+        }
+        let Some(query) = ctx.request.query() else {
+            return Ok(());
+        };
+        if !matches!(query.query_type(), RecordType::DS | RecordType::DNSKEY) {
+            return Ok(());
+        }
+
+        let zone = canonical_name(&query.name().to_ascii());
+        let ttl = self.ttl.unwrap_or(ctx.default_ttl);
+        let answers: Vec<Record> = self
+            .anchors
+            .get(&zone)
+            .into_iter()
+            .flatten()
+            .filter(|(r, _)| r.record_type() == query.query_type())
+            .map(|(r, record_ttl)| {
+                let mut r = r.clone();
+                r.set_name(query.name().clone())
+                    .set_ttl(record_ttl.unwrap_or(ttl));
+                r
+            })
+            .collect();
+        if answers.is_empty() {
+            return Ok(());
+        }
+
+        debug!(
+            "Answering {} {} from the local trust store",
+            zone,
+            query.query_type()
+        );
+        let mut response = dns::empty_response(&ctx.request, ResponseCode::NoError);
+        response.insert_answers(answers);
+        ctx.response = Some(response);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Message, Query};
+    use std::io::Write;
+    use std::net::SocketAddr;
+    use std::sync::{Arc, RwLock};
+    use tempfile::NamedTempFile;
+
+    fn make_ctx(name: &str, qtype: RecordType) -> Context {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+        Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_dnskey_from_trust_store() {
+        let mut file = NamedTempFile::new().unwrap();
+        writeln!(file, "; lab zone keys").unwrap();
+        writeln!(file, "lab.internal. 3600 IN DNSKEY 257 3 13 AQID BAU=").unwrap();
+        let yaml = format!(
+            "files: [\"{}\"]\nanchors:\n  - \". IN DS 20326 8 2 E06D44B8 0B8F1D39\"",
+            file.path().display()
+        );
+        let config: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        let plugin = TrustAnchor::new(Some(&config)).unwrap();
+
+        let mut ctx = make_ctx("Lab.Internal.", RecordType::DNSKEY);
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.answers().len(), 1);
+        let record = &response.answers()[0];
+        assert_eq!(record.record_type(), RecordType::DNSKEY);
+        assert_eq!(record.ttl(), 3600);
+        let Some(RData::Unknown { rdata, .. }) = record.data() else {
+            panic!("Expected raw DNSKEY rdata");
+        };
+        assert_eq!(rdata.anything(), [1, 1, 3, 13, 1, 2, 3, 4, 5]);
+        // Survives a round trip through the wire format.
+        let wire = Message::from_vec(&response.to_vec().unwrap()).unwrap();
+        assert_eq!(wire.answers()[0].record_type(), RecordType::DNSKEY);
+
+        let mut ctx = make_ctx(".", RecordType::DS);
+        ctx.default_ttl = 120;
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.answers()[0].ttl(), 120);
+        let Some(RData::Unknown { rdata, .. }) = response.answers()[0].data() else {
+            panic!("Expected raw DS rdata");
+        };
+        assert_eq!(
+            rdata.anything(),
+            [0x4f, 0x66, 8, 2, 0xe0, 0x6d, 0x44, 0xb8, 0x0b, 0x8f, 0x1d, 0x39]
+        );
+
+        // No DS for the lab zone, and other types pass on.
+        for (name, qtype) in [
+            ("lab.internal.", RecordType::DS),
+            ("lab.internal.", RecordType::A),
+        ] {
+            let mut ctx = make_ctx(name, qtype);
+            plugin.next(&mut ctx).await.unwrap();
+            assert!(ctx.response.is_none());
+        }
+    }
+}
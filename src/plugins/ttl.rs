@@ -1,4 +1,5 @@
 use super::{Context, Plugin};
+use crate::dns::{canonical_name, is_subdomain};
 use anyhow::Result;
 use async_trait::async_trait;
 use serde::Deserialize;
@@ -7,11 +8,34 @@ use serde::Deserialize;
 struct TtlConfig {
     min: Option<u32>,
     max: Option<u32>,
+    /// Domains (and their subdomains) whose answers get TTL 0 so clients always re-query.
+    #[serde(default)]
+    zero: Vec<String>,
+    /// Give every record of a response the same TTL, before `min`/`max` apply,
+    /// so downstream caches expire them together.
+    #[serde(default)]
+    uniform: Option<Uniform>,
+}
+
+/// Common TTL for all records of a response: `lowest` present, or a fixed value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+enum Uniform {
+    Fixed(u32),
+    Keyword(UniformKeyword),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum UniformKeyword {
+    Lowest,
 }
 
 pub struct TtlPlugin {
     min: u32,
     max: u32,
+    zero: Vec<String>,
+    uniform: Option<Uniform>,
 }
 
 impl TtlPlugin {
@@ -22,13 +46,25 @@ impl TtlPlugin {
             TtlConfig {
                 min: None,
                 max: None,
+                zero: vec![],
+                uniform: None,
             }
         };
         Ok(Self {
             min: config.min.unwrap_or(0),
             max: config.max.unwrap_or(u32::MAX),
+            zero: config.zero.iter().map(|d| canonical_name(d)).collect(),
+            uniform: config.uniform,
         })
     }
+
+    fn is_zero_ttl(&self, ctx: &Context) -> bool {
+        let Some(query) = ctx.request.query() else {
+            return false;
+        };
+        let name = canonical_name(&query.name().to_string());
+        self.zero.iter().any(|d| is_subdomain(&name, d))
+    }
 }
 
 #[async_trait]
@@ -38,14 +74,30 @@ impl Plugin for TtlPlugin {
     }
 
     async fn next(&self, ctx: &mut Context) -> Result<()> {
+        let zero = self.is_zero_ttl(ctx);
         if let Some(response) = &mut ctx.response {
+            let common = match self.uniform {
+                Some(Uniform::Fixed(ttl)) => Some(ttl),
+                Some(Uniform::Keyword(UniformKeyword::Lowest)) => response
+                    .answers()
+                    .iter()
+                    .chain(response.name_servers())
+                    .chain(response.additionals())
+                    .map(|r| r.ttl())
+                    .min(),
+                None => None,
+            };
             let modify = |records: &mut Vec<hickory_proto::rr::Record>| {
                 for record in records {
-                    let ttl = record.ttl();
-                    if ttl < self.min {
+                    let ttl = common.unwrap_or(record.ttl());
+                    if zero {
+                        record.set_ttl(0);
+                    } else if ttl < self.min {
                         record.set_ttl(self.min);
                     } else if ttl > self.max {
                         record.set_ttl(self.max);
+                    } else {
+                        record.set_ttl(ttl);
                     }
                 }
             };
@@ -66,13 +118,22 @@ mod tests {
     use std::sync::{Arc, RwLock};
 
     fn make_ctx() -> Context {
+        make_query_ctx(None)
+    }
+
+    fn make_query_ctx(name: Option<&str>) -> Context {
         use crate::statistics::Statistics;
-        use hickory_proto::op::Message;
+        use hickory_proto::op::{Message, Query};
+        use hickory_proto::rr::RecordType;
         use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 
+        let mut request = Message::new();
+        if let Some(name) = name {
+            request.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+        }
         Context::new(
             SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 1234),
-            Message::new(),
+            request,
             Arc::new(RwLock::new(Statistics::new())),
         )
     }
@@ -117,4 +178,79 @@ mod tests {
         assert_eq!(answers[1].ttl(), 100);
         assert_eq!(answers[2].ttl(), 50);
     }
+
+    #[tokio::test]
+    async fn test_zero_ttl_for_matched_names() {
+        let yaml = r#"
+            min: 10
+            max: 100
+            zero: ["dyn.example.com"]
+        "#;
+        let config: serde_yaml::Value = serde_yaml::from_str(yaml).unwrap();
+        let plugin = TtlPlugin::new(Some(&config)).unwrap();
+
+        for (name, expected) in [
+            ("dyn.example.com.", 0),
+            ("lb.dyn.example.com.", 0),
+            ("static.example.com.", 100),
+        ] {
+            let mut ctx = make_query_ctx(Some(name));
+            let mut response = hickory_proto::op::Message::new();
+            let mut record = Record::new();
+            record.set_name(Name::from_str(name).unwrap());
+            record.set_ttl(300);
+            response.add_answer(record);
+            ctx.response = Some(response);
+
+            plugin.next(&mut ctx).await.unwrap();
+            assert_eq!(
+                ctx.response.unwrap().answers()[0].ttl(),
+                expected,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_uniform_ttl() {
+        fn response(ttls: &[u32]) -> hickory_proto::op::Message {
+            let mut response = hickory_proto::op::Message::new();
+            for &ttl in ttls {
+                let mut record = Record::new();
+                record.set_name(Name::from_str("mixed.example.").unwrap());
+                record.set_ttl(ttl);
+                response.add_answer(record);
+            }
+            let mut glue = Record::new();
+            glue.set_name(Name::from_str("ns.example.").unwrap());
+            glue.set_ttl(86400);
+            response.add_additional(glue);
+            response
+        }
+        let ttls = |ctx: Context| -> Vec<u32> {
+            let response = ctx.response.unwrap();
+            response
+                .answers()
+                .iter()
+                .chain(response.additionals())
+                .map(|r| r.ttl())
+                .collect()
+        };
+
+        let config: serde_yaml::Value = serde_yaml::from_str("uniform: lowest").unwrap();
+        let plugin = TtlPlugin::new(Some(&config)).unwrap();
+        let mut ctx = make_ctx();
+        ctx.response = Some(response(&[300, 20, 3600]));
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(ttls(ctx), vec![20, 20, 20, 20]);
+
+        // A fixed value, still bounded by `min`.
+        let config: serde_yaml::Value = serde_yaml::from_str("uniform: 5\nmin: 10").unwrap();
+        let plugin = TtlPlugin::new(Some(&config)).unwrap();
+        let mut ctx = make_ctx();
+        ctx.response = Some(response(&[300, 20]));
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(ttls(ctx), vec![10, 10, 10]);
+    }
 }
@@ -0,0 +1,214 @@
+use super::{Context, Plugin, TxtStore, TxtValue};
+use crate::dns::{self, canonical_name};
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::rdata::TXT;
+use hickory_proto::rr::{RData, Record, RecordType};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use tracing::{debug, info};
+
+fn default_labels() -> Vec<String> {
+    vec!["_acme-challenge".to_string()]
+}
+
+fn default_expire() -> u64 {
+    3600
+}
+
+#[derive(Deserialize)]
+struct TxtStoreConfig {
+    /// First labels of the names the store takes records for.
+    #[serde(default = "default_labels")]
+    labels: Vec<String>,
+    /// TTL of answers whose value has none; the server's `default_ttl` if unset.
+    #[serde(default)]
+    ttl: Option<u32>,
+    /// Seconds a value without its own `expire` is kept.
+    #[serde(default = "default_expire")]
+    expire: u64,
+}
+
+struct StoredTxt {
+    value: String,
+    ttl: Option<u32>,
+    expires_at: Instant,
+}
+
+/// Answers TXT queries from records written at runtime through `/txt/{name}`,
+/// e.g. to back ACME DNS-01 challenges. Values expire after a while; queries
+/// for names without a live value pass on.
+pub struct TxtStorePlugin {
+    labels: HashSet<String>,
+    ttl: Option<u32>,
+    expire: Duration,
+    records: RwLock<HashMap<String, Vec<StoredTxt>>>,
+}
+
+impl TxtStorePlugin {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: TxtStoreConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            TxtStoreConfig {
+                labels: default_labels(),
+                ttl: None,
+                expire: default_expire(),
+            }
+        };
+        Ok(Self {
+            labels: config.labels.iter().map(|l| canonical_name(l)).collect(),
+            ttl: config.ttl,
+            expire: Duration::from_secs(config.expire),
+            records: RwLock::default(),
+        })
+    }
+}
+
+#[async_trait]
+impl Plugin for TxtStorePlugin {
+    fn name(&self) -> &str {
+        "txt_store"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        if ctx.response.is_some() {
+            return Ok(());
+        }
+        let Some(query) = ctx.request.query() else {
+            return Ok(());
+        };
+        if query.query_type() != RecordType::TXT {
+            return Ok(());
+        }
+
+        let name = canonical_name(&query.name().to_ascii());
+        let now = Instant::now();
+        let ttl = self.ttl.unwrap_or(ctx.default_ttl);
+        let answers: Vec<Record> = self
+            .records
+            .read()
+            .unwrap()
+            .get(&name)
+            .into_iter()
+            .flatten()
+            .filter(|txt| txt.expires_at > now)
+            .map(|txt| {
+                // Character strings are at most 255 bytes each.
+                let rdata = TXT::from_bytes(txt.value.as_bytes().chunks(255).collect());
+                Record::from_rdata(
+                    query.name().clone(),
+                    txt.ttl.unwrap_or(ttl),
+                    RData::TXT(rdata),
+                )
+            })
+            .collect();
+        if answers.is_empty() {
+            return Ok(());
+        }
+
+        debug!("Answering TXT {} from the store", name);
+        let mut response = dns::empty_response(&ctx.request, ResponseCode::NoError);
+        response.insert_answers(answers);
+        ctx.response = Some(response);
+        Ok(())
+    }
+
+    fn as_txt_store(&self) -> Option<&dyn TxtStore> {
+        Some(self)
+    }
+}
+
+impl TxtStore for TxtStorePlugin {
+    fn accepts(&self, name: &str) -> bool {
+        let name = canonical_name(name);
+        let first = name.split('.').next().unwrap_or_default();
+        self.labels.contains(first)
+    }
+
+    fn add_txt(&self, name: &str, value: TxtValue) {
+        let name = canonical_name(name);
+        let now = Instant::now();
+        let expire = value.expire.map_or(self.expire, Duration::from_secs);
+        info!("Adding TXT record for {}, expiring in {:?}", name, expire);
+        let mut records = self.records.write().unwrap();
+        // Drop expired values while we hold the lock anyway.
+        records.retain(|_, values| {
+            values.retain(|txt| txt.expires_at > now);
+            !values.is_empty()
+        });
+        records.entry(name).or_default().push(StoredTxt {
+            value: value.value,
+            ttl: value.ttl,
+            expires_at: now + expire,
+        });
+    }
+
+    fn remove_txt(&self, name: &str) -> bool {
+        let name = canonical_name(name);
+        self.records.write().unwrap().remove(&name).is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::Name;
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+
+    fn make_ctx(name: &str, qtype: RecordType) -> Context {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(Name::from_ascii(name).unwrap(), qtype));
+        Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_txt_values_and_labels() {
+        let plugin = TxtStorePlugin::new(None).unwrap();
+        assert!(plugin.accepts("_acme-challenge.www.example.com."));
+        assert!(!plugin.accepts("www.example.com"));
+
+        let long = "x".repeat(300);
+        for value in ["token-1", long.as_str()] {
+            plugin.add_txt(
+                "_ACME-Challenge.example.com",
+                TxtValue {
+                    value: value.to_string(),
+                    ttl: Some(10),
+                    expire: None,
+                },
+            );
+        }
+        let mut ctx = make_ctx("_acme-challenge.example.com.", RecordType::TXT);
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.answers().len(), 2);
+        assert_eq!(response.answers()[0].ttl(), 10);
+        let Some(RData::TXT(txt)) = response.answers()[1].data() else {
+            panic!("Expected TXT");
+        };
+        let lengths: Vec<usize> = txt.txt_data().iter().map(|s| s.len()).collect();
+        assert_eq!(lengths, [255, 45]);
+
+        // Other types pass on.
+        let mut ctx = make_ctx("_acme-challenge.example.com.", RecordType::A);
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.is_none());
+
+        assert!(plugin.remove_txt("_acme-challenge.example.com"));
+        assert!(!plugin.remove_txt("_acme-challenge.example.com"));
+        let mut ctx = make_ctx("_acme-challenge.example.com.", RecordType::TXT);
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.is_none());
+    }
+}
@@ -0,0 +1,230 @@
+use super::{Condition, Context, Plugin};
+use crate::dns::canonical_name;
+use anyhow::{Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// Bundled TLD list, one per line (IANA `tlds-alpha-by-domain.txt` format).
+const BUNDLED_TLDS: &str = include_str!("../../data/tlds-alpha-by-domain.txt");
+
+fn default_refresh_interval() -> u64 {
+    86400
+}
+
+#[derive(Deserialize)]
+struct ValidTldConfig {
+    /// Local TLD list replacing the bundled one.
+    #[serde(default)]
+    file: Option<String>,
+    /// URL the list is periodically re-downloaded from, e.g.
+    /// `https://data.iana.org/TLD/tlds-alpha-by-domain.txt`.
+    #[serde(default)]
+    url: Option<String>,
+    /// Seconds between downloads from `url`.
+    #[serde(default = "default_refresh_interval")]
+    refresh_interval: u64,
+    /// Extra TLDs treated as valid (e.g. `lan`, `internal`).
+    #[serde(default)]
+    allow: Vec<String>,
+}
+
+/// Condition that holds when the query's TLD is *not* delegated in the root
+/// zone, so `if` can answer typo and malware lookups without forwarding them.
+pub struct ValidTld {
+    tlds: Arc<RwLock<HashSet<String>>>,
+    allow: HashSet<String>,
+}
+
+impl ValidTld {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: ValidTldConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            ValidTldConfig {
+                file: None,
+                url: None,
+                refresh_interval: default_refresh_interval(),
+                allow: vec![],
+            }
+        };
+
+        let tlds = match &config.file {
+            Some(path) => parse_tld_list(
+                &std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read TLD list {}", path))?,
+            ),
+            None => parse_tld_list(BUNDLED_TLDS),
+        };
+        if tlds.is_empty() {
+            return Err(anyhow::anyhow!("TLD list is empty"));
+        }
+        let tlds = Arc::new(RwLock::new(tlds));
+
+        if let Some(url) = config.url {
+            match tokio::runtime::Handle::try_current() {
+                Ok(handle) => {
+                    handle.spawn(refresh_loop(
+                        url,
+                        Duration::from_secs(config.refresh_interval.max(60)),
+                        Arc::downgrade(&tlds),
+                    ));
+                }
+                Err(_) => warn!("No async runtime, TLD list will not be refreshed"),
+            }
+        }
+
+        Ok(Self {
+            tlds,
+            allow: config.allow.iter().map(|t| canonical_name(t)).collect(),
+        })
+    }
+
+    fn is_invalid(&self, name: &str) -> bool {
+        let name = canonical_name(name);
+        let tld = name.rsplit('.').next().unwrap_or_default();
+        if tld.is_empty() || self.allow.contains(tld) {
+            return false;
+        }
+        !self.tlds.read().unwrap().contains(tld)
+    }
+}
+
+fn parse_tld_list(text: &str) -> HashSet<String> {
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(|l| l.to_ascii_lowercase())
+        .collect()
+}
+
+/// Download the list from `url` and swap it in if it is usable.
+async fn refresh(
+    client: &reqwest::Client,
+    url: &str,
+    tlds: &RwLock<HashSet<String>>,
+) -> Result<usize> {
+    let text = client
+        .get(url)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let list = parse_tld_list(&text);
+    // Never replace a good list with an empty one (e.g. an error page).
+    if list.is_empty() {
+        return Err(anyhow::anyhow!("Downloaded TLD list is empty"));
+    }
+    let count = list.len();
+    *tlds.write().unwrap() = list;
+    Ok(count)
+}
+
+/// Refresh the list every `interval` until the plugin holding it is dropped,
+/// e.g. replaced by a config reload.
+async fn refresh_loop(url: String, interval: Duration, tlds: Weak<RwLock<HashSet<String>>>) {
+    let client = reqwest::Client::builder()
+        .timeout(Duration::from_secs(30))
+        .build()
+        .unwrap_or_default();
+    while let Some(list) = tlds.upgrade() {
+        match refresh(&client, &url, &list).await {
+            Ok(count) => info!("Refreshed TLD list from {} ({} TLDs)", url, count),
+            Err(e) => warn!("Failed to refresh TLD list from {}: {:#}", url, e),
+        }
+        // Don't keep the list alive while sleeping.
+        drop(list);
+        tokio::time::sleep(interval).await;
+    }
+}
+
+impl Condition for ValidTld {
+    fn check(&self, ctx: &Context) -> bool {
+        ctx.request
+            .query()
+            // Display renders IDN labels as Unicode; the list is in punycode.
+            .is_some_and(|q| self.is_invalid(&q.name().to_ascii()))
+    }
+}
+
+#[async_trait]
+impl Plugin for ValidTld {
+    fn name(&self) -> &str {
+        "valid_tld"
+    }
+
+    async fn next(&self, _ctx: &mut Context) -> Result<()> {
+        Ok(())
+    }
+
+    fn as_condition(&self) -> Option<&dyn Condition> {
+        Some(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::{Name, RecordType};
+    use std::net::SocketAddr;
+    use std::str::FromStr;
+
+    fn make_ctx(name: &str) -> Context {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(Name::from_str(name).unwrap(), RecordType::A));
+        Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[test]
+    fn test_invalid_tld() {
+        let config: serde_yaml::Value = serde_yaml::from_str("allow: [lan]").unwrap();
+        let plugin = ValidTld::new(Some(&config)).unwrap();
+
+        assert!(plugin.check(&make_ctx("example.invalidtld.")));
+        assert!(!plugin.check(&make_ctx("www.example.com.")));
+        assert!(!plugin.check(&make_ctx("example.xn--p1ai.")));
+        assert!(!plugin.check(&make_ctx("printer.lan.")));
+        assert!(!plugin.check(&make_ctx(".")));
+    }
+
+    #[tokio::test]
+    async fn test_refresh_from_url() {
+        use axum::routing::get;
+        use tokio::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/tlds.txt", listener.local_addr().unwrap());
+        let app = axum::Router::new().route(
+            "/tlds.txt",
+            get(|| async { "# Version 2026101600\nCOM\nINVALIDTLD\n" }),
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let plugin = ValidTld::new(None).unwrap();
+        assert!(plugin.check(&make_ctx("example.invalidtld.")));
+
+        let client = reqwest::Client::new();
+        assert_eq!(refresh(&client, &url, &plugin.tlds).await.unwrap(), 2);
+        assert!(!plugin.check(&make_ctx("example.invalidtld.")));
+        assert!(plugin.check(&make_ctx("example.org.")));
+
+        // The loop ends once the plugin's list is gone.
+        let tlds = Arc::downgrade(&plugin.tlds);
+        drop(plugin);
+        let ended = tokio::time::timeout(
+            Duration::from_secs(1),
+            refresh_loop(url, Duration::from_secs(3600), tlds),
+        );
+        assert!(ended.await.is_ok());
+    }
+}
@@ -0,0 +1,242 @@
+use super::{Context, Plugin};
+use crate::dns;
+use anyhow::{Context as AnyhowContext, Result};
+use async_trait::async_trait;
+use hickory_proto::op::ResponseCode;
+use hickory_proto::rr::rdata::{A, AAAA};
+use hickory_proto::rr::{RData, Record, RecordType};
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use std::net::IpAddr;
+use std::time::Duration;
+use tracing::{debug, warn};
+
+fn default_timeout_ms() -> u64 {
+    2000
+}
+
+#[derive(Deserialize)]
+struct WebhookConfig {
+    /// Endpoint queries are POSTed to.
+    url: String,
+    #[serde(default = "default_timeout_ms")]
+    timeout_ms: u64,
+    /// The server's `default_ttl` if neither this nor the reply sets one.
+    #[serde(default)]
+    ttl: Option<u32>,
+}
+
+/// Body POSTed to the endpoint.
+#[derive(Serialize)]
+struct WebhookRequest<'a> {
+    name: &'a str,
+    #[serde(rename = "type")]
+    qtype: String,
+    client: IpAddr,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+enum WebhookRcode {
+    NoError,
+    NxDomain,
+    ServFail,
+    Refused,
+}
+
+impl From<WebhookRcode> for ResponseCode {
+    fn from(rcode: WebhookRcode) -> Self {
+        match rcode {
+            WebhookRcode::NoError => ResponseCode::NoError,
+            WebhookRcode::NxDomain => ResponseCode::NXDomain,
+            WebhookRcode::ServFail => ResponseCode::ServFail,
+            WebhookRcode::Refused => ResponseCode::Refused,
+        }
+    }
+}
+
+/// Endpoint reply; an empty object means "no opinion".
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct WebhookResponse {
+    ips: Vec<IpAddr>,
+    ttl: Option<u32>,
+    rcode: Option<WebhookRcode>,
+}
+
+/// Resolves queries through an HTTP service (e.g. service discovery): the name,
+/// type and client address are POSTed as JSON and the reply's `ips` become the
+/// answer. Failures, timeouts and empty replies fall through to later plugins.
+pub struct Webhook {
+    url: Url,
+    ttl: Option<u32>,
+    client: Client,
+}
+
+impl Webhook {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: WebhookConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            return Err(anyhow::anyhow!("Webhook requires config"));
+        };
+
+        let url = Url::parse(&config.url)
+            .with_context(|| format!("Invalid webhook URL: {}", config.url))?;
+        let client = Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+            .context("Failed to build HTTP client")?;
+        Ok(Self {
+            url,
+            ttl: config.ttl,
+            client,
+        })
+    }
+
+    async fn call(&self, request: &WebhookRequest<'_>) -> Result<WebhookResponse> {
+        let body = self
+            .client
+            .post(self.url.clone())
+            .header("content-type", "application/json")
+            .body(serde_json::to_vec(request)?)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        serde_json::from_slice(&body).context("Invalid webhook reply")
+    }
+}
+
+#[async_trait]
+impl Plugin for Webhook {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        if ctx.response.is_some() || ctx.local_only {
+            return Ok(());
+        }
+        let Some(query) = ctx.request.query() else {
+            return Ok(());
+        };
+
+        let name = query.name().to_ascii();
+        let request = WebhookRequest {
+            name: &name,
+            qtype: query.query_type().to_string(),
+            client: ctx.client_addr.ip(),
+        };
+        let reply = match self.call(&request).await {
+            Ok(reply) => reply,
+            Err(e) => {
+                warn!("Webhook {} failed for {}: {:#}", self.url, name, e);
+                return Ok(());
+            }
+        };
+        if reply.ips.is_empty() && reply.rcode.is_none() {
+            debug!("Webhook has no answer for {}", name);
+            return Ok(());
+        }
+
+        let ttl = reply.ttl.or(self.ttl).unwrap_or(ctx.default_ttl);
+        let rcode = reply.rcode.map_or(ResponseCode::NoError, Into::into);
+        let mut response = dns::empty_response(&ctx.request, rcode);
+        for ip in reply.ips {
+            let rdata = match (ip, query.query_type()) {
+                (IpAddr::V4(v4), RecordType::A) => RData::A(A(v4)),
+                (IpAddr::V6(v6), RecordType::AAAA) => RData::AAAA(AAAA(v6)),
+                _ => continue,
+            };
+            response.add_answer(Record::from_rdata(query.name().clone(), ttl, rdata));
+        }
+        ctx.response = Some(response);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use axum::routing::post;
+    use axum::{Json, Router};
+    use hickory_proto::op::{Message, Query};
+    use hickory_proto::rr::Name;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::sync::{Arc, RwLock};
+
+    async fn lookup(Json(body): Json<serde_json::Value>) -> Json<serde_json::Value> {
+        Json(match body["name"].as_str() {
+            Some("billing.svc.internal.") => serde_json::json!({
+                "ips": ["10.4.0.7", "fd00::7"],
+                "ttl": 15,
+            }),
+            Some("gone.svc.internal.") => serde_json::json!({ "rcode": "NXDOMAIN" }),
+            _ => serde_json::json!({}),
+        })
+    }
+
+    fn make_ctx(name: &str) -> Context {
+        let mut msg = Message::new();
+        msg.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            msg,
+            Arc::new(RwLock::new(Statistics::new())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_webhook_answer() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            axum::serve(listener, Router::new().route("/resolve", post(lookup)))
+                .await
+                .unwrap();
+        });
+
+        let yaml = format!("url: \"http://{}/resolve\"", addr);
+        let config: serde_yaml::Value = serde_yaml::from_str(&yaml).unwrap();
+        let plugin = Webhook::new(Some(&config)).unwrap();
+
+        let mut ctx = make_ctx("billing.svc.internal.");
+        plugin.next(&mut ctx).await.unwrap();
+        let response = ctx.response.unwrap();
+        assert_eq!(response.response_code(), ResponseCode::NoError);
+        assert_eq!(response.answers().len(), 1);
+        assert_eq!(response.answers()[0].ttl(), 15);
+        assert_eq!(
+            response.answers()[0].data(),
+            Some(&RData::A(Ipv4Addr::new(10, 4, 0, 7).into()))
+        );
+
+        let mut ctx = make_ctx("gone.svc.internal.");
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(
+            ctx.response.unwrap().response_code(),
+            ResponseCode::NXDomain
+        );
+
+        // Queries that may only be answered locally never reach the service.
+        let mut ctx = make_ctx("billing.svc.internal.");
+        ctx.local_only = true;
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.is_none());
+
+        // No opinion, or no service at all: fall through.
+        let mut ctx = make_ctx("example.com.");
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.is_none());
+
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("url: \"http://127.0.0.1:1/resolve\"").unwrap();
+        let plugin = Webhook::new(Some(&config)).unwrap();
+        let mut ctx = make_ctx("billing.svc.internal.");
+        plugin.next(&mut ctx).await.unwrap();
+        assert!(ctx.response.is_none());
+    }
+}
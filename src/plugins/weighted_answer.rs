@@ -0,0 +1,194 @@
+use super::{Context, Plugin};
+use anyhow::Result;
+use async_trait::async_trait;
+use hickory_proto::rr::{RData, Record};
+use rand::seq::SliceRandom;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+fn default_count() -> usize {
+    1
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+#[derive(Deserialize)]
+struct WeightedAnswerConfig {
+    /// Number of address records kept per response.
+    #[serde(default = "default_count")]
+    count: usize,
+    /// Relative weight per address. Weight 0 addresses are never picked when an
+    /// answer is trimmed, but answers that need no trimming keep them.
+    #[serde(default)]
+    weights: HashMap<IpAddr, u32>,
+    /// Weight of addresses missing from `weights`.
+    #[serde(default = "default_weight")]
+    default_weight: u32,
+}
+
+/// Trims multi-address answers to a weighted-random subset, spreading clients
+/// across endpoints. Non-address records (e.g. CNAMEs) are kept.
+pub struct WeightedAnswer {
+    count: usize,
+    weights: HashMap<IpAddr, u32>,
+    default_weight: u32,
+}
+
+impl WeightedAnswer {
+    pub fn new(config: Option<&serde_yaml::Value>) -> Result<Self> {
+        let config: WeightedAnswerConfig = if let Some(c) = config {
+            serde_yaml::from_value(c.clone())?
+        } else {
+            WeightedAnswerConfig {
+                count: default_count(),
+                weights: HashMap::new(),
+                default_weight: default_weight(),
+            }
+        };
+        Ok(Self {
+            count: config.count.max(1),
+            weights: config.weights,
+            default_weight: config.default_weight,
+        })
+    }
+
+    fn weight(&self, record: &Record) -> Option<u32> {
+        let ip = match record.data()? {
+            RData::A(a) => IpAddr::V4(a.0),
+            RData::AAAA(aaaa) => IpAddr::V6(aaaa.0),
+            _ => return None,
+        };
+        Some(
+            self.weights
+                .get(&ip)
+                .copied()
+                .unwrap_or(self.default_weight),
+        )
+    }
+}
+
+#[async_trait]
+impl Plugin for WeightedAnswer {
+    fn name(&self) -> &str {
+        "weighted_answer"
+    }
+
+    async fn next(&self, ctx: &mut Context) -> Result<()> {
+        let Some(response) = &mut ctx.response else {
+            return Ok(());
+        };
+
+        let mut others = Vec::new();
+        let mut addresses = Vec::new();
+        for record in response.take_answers() {
+            match self.weight(&record) {
+                Some(weight) => addresses.push((record, weight)),
+                None => others.push(record),
+            }
+        }
+
+        let total: u64 = addresses.iter().map(|(_, w)| u64::from(*w)).sum();
+        if addresses.len() > self.count && total > 0 {
+            let eligible = addresses.iter().filter(|(_, w)| *w > 0).count();
+            let picked = addresses
+                .choose_multiple_weighted(
+                    &mut rand::thread_rng(),
+                    self.count.min(eligible),
+                    |(_, w)| *w as f64,
+                )?
+                .map(|(record, _)| record.clone())
+                .collect::<Vec<_>>();
+            others.extend(picked);
+        } else {
+            others.extend(addresses.into_iter().map(|(record, _)| record));
+        }
+        response.insert_answers(others);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statistics::Statistics;
+    use hickory_proto::op::Message;
+    use hickory_proto::rr::rdata::CNAME;
+    use hickory_proto::rr::Name;
+    use std::net::{Ipv4Addr, SocketAddr};
+    use std::str::FromStr;
+    use std::sync::{Arc, RwLock};
+
+    fn make_ctx(ips: &[[u8; 4]]) -> Context {
+        let mut response = Message::new();
+        response.add_answer(Record::from_rdata(
+            Name::from_str("www.example.com.").unwrap(),
+            60,
+            RData::CNAME(CNAME(Name::from_str("lb.example.com.").unwrap())),
+        ));
+        for ip in ips {
+            response.add_answer(Record::from_rdata(
+                Name::from_str("lb.example.com.").unwrap(),
+                60,
+                RData::A(Ipv4Addr::from(*ip).into()),
+            ));
+        }
+        let mut ctx = Context::new(
+            SocketAddr::from(([127, 0, 0, 1], 1234)),
+            Message::new(),
+            Arc::new(RwLock::new(Statistics::new())),
+        );
+        ctx.response = Some(response);
+        ctx
+    }
+
+    #[tokio::test]
+    async fn test_weighted_distribution() {
+        let config: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+            weights:
+              10.0.0.1: 1
+              10.0.0.2: 3
+              10.0.0.3: 0
+            "#,
+        )
+        .unwrap();
+        let plugin = WeightedAnswer::new(Some(&config)).unwrap();
+
+        const ROUNDS: usize = 10_000;
+        let mut picks: HashMap<IpAddr, usize> = HashMap::new();
+        for _ in 0..ROUNDS {
+            let mut ctx = make_ctx(&[[10, 0, 0, 1], [10, 0, 0, 2], [10, 0, 0, 3]]);
+            plugin.next(&mut ctx).await.unwrap();
+            let answers = ctx.response.unwrap().take_answers();
+            assert_eq!(answers.len(), 2, "CNAME plus one address");
+            match answers[1].data() {
+                Some(RData::A(a)) => *picks.entry(IpAddr::V4(a.0)).or_default() += 1,
+                other => panic!("unexpected record {:?}", other),
+            }
+        }
+
+        let share = |ip: [u8; 4]| {
+            picks.get(&IpAddr::from(ip)).copied().unwrap_or_default() as f64 / ROUNDS as f64
+        };
+        assert!((share([10, 0, 0, 1]) - 0.25).abs() < 0.03);
+        assert!((share([10, 0, 0, 2]) - 0.75).abs() < 0.03);
+        assert_eq!(share([10, 0, 0, 3]), 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_short_answers_untouched() {
+        let config: serde_yaml::Value = serde_yaml::from_str("count: 2").unwrap();
+        let plugin = WeightedAnswer::new(Some(&config)).unwrap();
+
+        let mut ctx = make_ctx(&[[10, 0, 0, 1], [10, 0, 0, 2]]);
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(ctx.response.unwrap().answers().len(), 3);
+
+        let mut ctx = make_ctx(&[[10, 0, 0, 1], [10, 0, 0, 2], [10, 0, 0, 3]]);
+        plugin.next(&mut ctx).await.unwrap();
+        assert_eq!(ctx.response.unwrap().answers().len(), 3);
+    }
+}
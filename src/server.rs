@@ -1,18 +1,236 @@
-use crate::plugins::{Context, SharedPlugin};
+use crate::config::{Config, Protocol, RdPolicy};
+use crate::dns;
+use crate::plugins::{
+    exec, Context, DepthExceeded, RetryBudget, SharedPlugin, DEFAULT_MAX_DEPTH, DEFAULT_TTL,
+};
+use crate::transport::{IncomingQuery, TcpTransport, Transport, UdpTransport};
 use anyhow::Result;
-use hickory_proto::op::Message;
+use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::rr::rdata::opt::EdnsCode;
+use hickory_proto::rr::DNSClass;
+use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::sync::Arc;
-use tokio::net::UdpSocket;
-use tracing::{error, info};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{broadcast, watch};
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
 
 use crate::statistics::Statistics;
 use std::sync::RwLock;
 
+/// Server-level behaviour that applies before/after the plugin chain runs.
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    pub require_rd: RdPolicy,
+    pub max_depth: usize,
+    /// Let identical concurrent queries share a single run of the plugin chain.
+    pub coalesce: bool,
+    /// Answer SERVFAIL when the chain leaves the query unanswered (but not dropped).
+    pub servfail_on_empty: bool,
+    /// Transports `run` listens on.
+    pub protocols: Vec<Protocol>,
+    /// How long an idle TCP connection is kept open.
+    pub tcp_idle_timeout: Duration,
+    /// How long queries still running at shutdown are waited for.
+    pub shutdown_timeout: Duration,
+    /// Time every plugin run and record it per tag.
+    pub profile: bool,
+    /// Log one in this many malformed packets; all are counted.
+    pub malformed_log_every: u64,
+    /// Query classes that are answered; others are REFUSED. All if `None`.
+    pub allowed_classes: Option<Vec<DNSClass>>,
+    /// TTL of records synthesized by plugins without their own `ttl`.
+    pub default_ttl: u32,
+    /// Queries handled at once; packets beyond it are dropped. Unlimited if `None`.
+    pub max_in_flight: Option<usize>,
+    /// Caps on the authority and additional sections of responses not
+    /// forwarded or cached.
+    pub max_authority_records: Option<usize>,
+    pub max_additional_records: Option<usize>,
+    /// Upstream attempts allowed per query; unlimited if `None`.
+    pub retry_budget: Option<usize>,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            require_rd: RdPolicy::default(),
+            max_depth: DEFAULT_MAX_DEPTH,
+            coalesce: false,
+            servfail_on_empty: true,
+            protocols: vec![Protocol::Udp, Protocol::Tcp],
+            tcp_idle_timeout: Duration::from_secs(10),
+            shutdown_timeout: Duration::from_secs(5),
+            profile: false,
+            malformed_log_every: 100,
+            allowed_classes: None,
+            default_ttl: DEFAULT_TTL,
+            max_in_flight: None,
+            max_authority_records: None,
+            max_additional_records: None,
+            retry_budget: None,
+        }
+    }
+}
+
+impl ServerOptions {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            require_rd: config.require_rd,
+            max_depth: config.max_depth,
+            coalesce: config.coalesce,
+            servfail_on_empty: config.servfail_on_empty,
+            protocols: config.protocols.clone(),
+            tcp_idle_timeout: Duration::from_secs(config.tcp_idle_timeout),
+            shutdown_timeout: Duration::from_secs(config.shutdown_timeout),
+            profile: config.profile,
+            malformed_log_every: config.malformed_log_every,
+            allowed_classes: config.allowed_classes.clone(),
+            default_ttl: config.default_ttl,
+            max_in_flight: config.max_in_flight,
+            max_authority_records: config.max_authority_records,
+            max_additional_records: config.max_additional_records,
+            retry_budget: config.retry_budget,
+        }
+    }
+}
+
+/// Upper bound on distinct in-flight questions tracked for coalescing; further
+/// queries simply run the chain on their own.
+const MAX_IN_FLIGHT: usize = 4096;
+
+/// Question identity used for coalescing: canonical name, type, class and the
+/// RD, CD and DO bits, which all change what upstreams answer.
+type InFlightKey = (String, u16, u16, bool, bool, bool);
+
+/// Result shared with waiters: the response and whether it came via a proxy.
+type Shared = Option<(Message, bool)>;
+
+#[derive(Default)]
+struct InFlight {
+    queries: Mutex<HashMap<InFlightKey, broadcast::Sender<Shared>>>,
+}
+
+enum Flight {
+    /// First asker: resolve, then publish through the guard.
+    Leader(FlightGuard),
+    /// An identical query is already running; wait for its result.
+    Waiter(broadcast::Receiver<Shared>),
+    /// Not coalesced (no question, a client subnet, or too many in flight).
+    Alone,
+}
+
+/// Removes the in-flight entry when dropped, so waiters are released even if the
+/// leader fails.
+struct FlightGuard {
+    in_flight: Arc<InFlight>,
+    key: InFlightKey,
+    tx: broadcast::Sender<Shared>,
+}
+
+impl FlightGuard {
+    fn finish(self, result: Shared) {
+        self.remove();
+        let _ = self.tx.send(result);
+    }
+
+    /// Remove our entry, leaving one a later leader put under the same key.
+    fn remove(&self) {
+        let mut queries = self.in_flight.queries.lock().unwrap();
+        if queries
+            .get(&self.key)
+            .is_some_and(|tx| tx.same_channel(&self.tx))
+        {
+            queries.remove(&self.key);
+        }
+    }
+}
+
+impl Drop for FlightGuard {
+    fn drop(&mut self) {
+        self.remove();
+    }
+}
+
+impl InFlight {
+    fn join(self: &Arc<Self>, request: &Message) -> Flight {
+        let Some(query) = request.query() else {
+            return Flight::Alone;
+        };
+        // Answers scoped to a client subnet can't be shared with other clients.
+        let edns = request.extensions().as_ref();
+        if edns.is_some_and(|e| e.option(EdnsCode::Subnet).is_some()) {
+            return Flight::Alone;
+        }
+        let key = (
+            dns::canonical_name(&query.name().to_string()),
+            u16::from(query.query_type()),
+            u16::from(query.query_class()),
+            request.recursion_desired(),
+            request.checking_disabled(),
+            edns.is_some_and(|e| e.dnssec_ok()),
+        );
+        let mut queries = self.queries.lock().unwrap();
+        if let Some(tx) = queries.get(&key) {
+            return Flight::Waiter(tx.subscribe());
+        }
+        if queries.len() >= MAX_IN_FLIGHT {
+            return Flight::Alone;
+        }
+        let (tx, _) = broadcast::channel(1);
+        queries.insert(key.clone(), tx.clone());
+        Flight::Leader(FlightGuard {
+            in_flight: self.clone(),
+            key,
+            tx,
+        })
+    }
+}
+
+/// Result of running one query through the server.
+#[derive(Debug, Clone, Default)]
+pub struct QueryOutcome {
+    /// Response to send back; `None` when the client gets no answer.
+    pub response: Option<Message>,
+    /// Name of the plugin that produced the response. `None` when nothing did, or
+    /// when the server answered itself (e.g. REFUSED or SERVFAIL).
+    pub answered_by: Option<String>,
+    pub cache_hit: bool,
+    /// Upstream that answered a forwarded query.
+    pub upstream: Option<String>,
+    /// Plugins entered while handling the query, in order.
+    pub trace: Vec<String>,
+    /// The response was shared from an identical in-flight query.
+    pub coalesced: bool,
+}
+
+impl QueryOutcome {
+    fn from_context(ctx: Context) -> Self {
+        Self {
+            response: ctx.response,
+            answered_by: ctx.answered_by,
+            cache_hit: ctx.cache_hit,
+            upstream: ctx.upstream,
+            trace: ctx.trace,
+            coalesced: false,
+        }
+    }
+}
+
+/// Runs queries from any `Transport` through the entry plugin.
+///
+/// Cloning is cheap; every clone shares the same plugin chain and statistics.
+#[derive(Clone)]
 pub struct Server {
     addr: SocketAddr,
-    entry_plugin: SharedPlugin,
+    /// Swapped as a whole on config reload; queries already running keep the old chain.
+    entry_plugin: Arc<RwLock<SharedPlugin>>,
     statistics: Arc<RwLock<Statistics>>,
+    options: Arc<ServerOptions>,
+    in_flight: Arc<InFlight>,
+    /// Set once by `shutdown`; serving loops stop accepting when it flips.
+    shutdown: Arc<watch::Sender<bool>>,
 }
 
 impl Server {
@@ -23,44 +241,219 @@ impl Server {
     ) -> Self {
         Self {
             addr,
-            entry_plugin,
+            entry_plugin: Arc::new(RwLock::new(entry_plugin)),
             statistics,
+            options: Arc::new(ServerOptions::default()),
+            in_flight: Arc::default(),
+            shutdown: Arc::new(watch::channel(false).0),
         }
     }
 
+    pub fn with_options(mut self, options: ServerOptions) -> Self {
+        self.options = Arc::new(options);
+        self
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn protocols(&self) -> &[Protocol] {
+        &self.options.protocols
+    }
+
+    /// Route new queries through `plugin`, e.g. after the config was reloaded.
+    /// Affects every clone of this server.
+    pub fn set_entry_plugin(&self, plugin: SharedPlugin) {
+        *self.entry_plugin.write().unwrap() = plugin;
+    }
+
+    /// Stop accepting queries and let `run` return once the queries being
+    /// handled are done. Affects every clone of this server.
+    pub fn shutdown(&self) {
+        self.shutdown.send_replace(true);
+    }
+
+    /// Listen on `addr` with every configured protocol until one of them fails
+    /// or the server is shut down.
     pub async fn run(self) -> Result<()> {
-        let socket = Arc::new(UdpSocket::bind(self.addr).await?);
-        info!("Listening on {}", self.addr);
+        if self.options.protocols.is_empty() {
+            return Err(anyhow::anyhow!("No listening protocols configured"));
+        }
+        let mut addr = self.addr;
+        let mut udp = None;
+        if self.options.protocols.contains(&Protocol::Udp) {
+            let transport = UdpTransport::bind(addr).await?;
+            // With port 0, TCP shares the port UDP was given.
+            addr = transport.local_addr()?;
+            info!("Listening on {} (UDP)", addr);
+            udp = Some(transport);
+        }
+        let mut tcp = None;
+        if self.options.protocols.contains(&Protocol::Tcp) {
+            let transport = TcpTransport::bind(addr, self.options.tcp_idle_timeout).await?;
+            info!("Listening on {} (TCP)", transport.local_addr()?);
+            tcp = Some(transport);
+        }
 
+        let serve_udp = async {
+            match udp {
+                Some(transport) => self.serve(transport).await,
+                None => Ok(()),
+            }
+        };
+        let serve_tcp = async {
+            match tcp {
+                Some(transport) => self.serve(transport).await,
+                None => Ok(()),
+            }
+        };
+        tokio::try_join!(serve_udp, serve_tcp)?;
+        info!("Stopped listening on {}", addr);
+        Ok(())
+    }
+
+    /// Accept queries from `transport` until it closes or the server is shut
+    /// down, handling each in its own task, counted in the `in_flight` gauge
+    /// while it runs. On shutdown, running tasks get `shutdown_timeout` to finish.
+    pub async fn serve<T: Transport>(&self, transport: T) -> Result<()> {
+        let gauge = self.statistics.read().unwrap().in_flight.clone();
+        let mut stopped = self.shutdown.subscribe();
+        let mut tasks = JoinSet::new();
         loop {
-            let mut buf = [0u8; 512];
-            match socket.recv_from(&mut buf).await {
-                Ok((size, src)) => {
-                    let socket_clone = socket.clone();
-                    let plugin = self.entry_plugin.clone();
-                    let stats = self.statistics.clone();
-                    tokio::spawn(async move {
-                        if let Err(e) =
-                            Self::handle_request(socket_clone, &buf[..size], src, plugin, stats)
-                                .await
-                        {
+            // Reap finished tasks so the set only holds running ones.
+            while tasks.try_join_next().is_some() {}
+            let accepted = tokio::select! {
+                biased;
+                _ = stopped.wait_for(|stopped| *stopped) => break,
+                accepted = transport.accept() => accepted,
+            };
+            match accepted {
+                Ok(Some(query)) => {
+                    let Some(guard) = gauge.enter(self.options.max_in_flight) else {
+                        debug!(
+                            "{} queries in flight, dropping query from {}",
+                            gauge.get(),
+                            query.src
+                        );
+                        continue;
+                    };
+                    let server = self.clone();
+                    tasks.spawn(async move {
+                        let _guard = guard;
+                        if let Err(e) = server.handle(query).await {
                             error!("Failed to handle request: {}", e);
                         }
                     });
                 }
-                Err(e) => error!("Failed to receive UDP packet: {}", e),
+                Ok(None) => break,
+                Err(e) => error!("Failed to receive query: {}", e),
             }
         }
+
+        if !tasks.is_empty() {
+            debug!("Waiting for {} queries to finish", tasks.len());
+        }
+        let drain = async { while tasks.join_next().await.is_some() {} };
+        if tokio::time::timeout(self.options.shutdown_timeout, drain)
+            .await
+            .is_err()
+        {
+            // Dropping the set aborts the stragglers.
+            warn!(
+                "Abandoning {} queries still running after {:?}",
+                tasks.len(),
+                self.options.shutdown_timeout
+            );
+        }
+        Ok(())
     }
 
-    async fn handle_request(
-        socket: Arc<UdpSocket>,
-        buf: &[u8],
-        src: SocketAddr,
-        plugin: SharedPlugin,
-        stats: Arc<RwLock<Statistics>>,
-    ) -> Result<()> {
-        let request = Message::from_vec(buf)?;
+    async fn handle(&self, query: IncomingQuery) -> Result<()> {
+        let Some(request) = self.decode(&query.bytes, query.src) else {
+            return Ok(());
+        };
+        // Only answer with the option when the client asked for it (RFC 7828).
+        let keepalive = query
+            .tcp_keepalive
+            .filter(|_| dns::has_tcp_keepalive(&request));
+        let limit = dns::udp_payload_limit(&request);
+        let outcome = self.process_request(request, query.src).await?;
+        if let Some(mut response) = outcome.response {
+            if let Some(timeout) = keepalive {
+                dns::set_tcp_keepalive(&mut response, timeout);
+            }
+            let mut bytes = response.to_vec()?;
+            if query.datagram && bytes.len() > limit {
+                debug!(
+                    "Truncating {} byte response to {} (limit {})",
+                    bytes.len(),
+                    query.src,
+                    limit
+                );
+                bytes = dns::truncated(&response).to_vec()?;
+            }
+            query.responder.respond(bytes).await?;
+        }
+        Ok(())
+    }
+
+    /// Count a packet that is not a usable query, logging only a sample so a
+    /// flood of garbage cannot flood the log too.
+    fn record_malformed(&self, src: SocketAddr, reason: &dyn std::fmt::Display) {
+        let count = {
+            let mut stats = self.statistics.write().unwrap();
+            stats.malformed_packets += 1;
+            stats.malformed_packets
+        };
+        let every = self.options.malformed_log_every.max(1);
+        if (count - 1) % every == 0 {
+            warn!(
+                "Dropping malformed packet from {}: {} ({} so far, logging 1 in {})",
+                src, reason, count, every
+            );
+        }
+    }
+
+    /// Decode a query, run it through the entry plugin and record statistics.
+    ///
+    /// Malformed packets are counted and dropped without a response.
+    pub async fn process_query(&self, buf: &[u8], src: SocketAddr) -> Result<QueryOutcome> {
+        match self.decode(buf, src) {
+            Some(request) => self.process_request(request, src).await,
+            None => Ok(QueryOutcome::default()),
+        }
+    }
+
+    /// Decode a packet, counting it as malformed if it is not a usable query.
+    fn decode(&self, buf: &[u8], src: SocketAddr) -> Option<Message> {
+        if !dns::looks_like_query(buf) {
+            self.record_malformed(src, &"invalid header");
+            return None;
+        }
+        match Message::from_vec(buf) {
+            Ok(request) => Some(request),
+            Err(e) => {
+                self.record_malformed(src, &e);
+                None
+            }
+        }
+    }
+
+    async fn process_request(&self, request: Message, src: SocketAddr) -> Result<QueryOutcome> {
+        let outcome = self.answer(request, src).await?;
+        if let Some(response) = &outcome.response {
+            self.statistics
+                .write()
+                .unwrap()
+                .record_response(response.response_code());
+        }
+        Ok(outcome)
+    }
+
+    async fn answer(&self, request: Message, src: SocketAddr) -> Result<QueryOutcome> {
+        let started_at = Instant::now();
+        let stats = &self.statistics;
 
         // Record request and keep domain for later
         let domain = if let Some(query) = request.query() {
@@ -68,17 +461,72 @@ impl Server {
             {
                 let mut s = stats.write().unwrap();
                 s.record_request(d.clone());
+                s.record_qtype(&d, &query.query_type().to_string());
             }
             Some(d)
         } else {
             None
         };
 
+        let non_recursive = !request.recursion_desired();
+        if non_recursive && self.options.require_rd == RdPolicy::Refuse {
+            debug!("Refusing non-recursive query from {}", src);
+            return Ok(QueryOutcome {
+                response: Some(dns::empty_response(&request, ResponseCode::Refused)),
+                ..Default::default()
+            });
+        }
+
+        if let (Some(allowed), Some(query)) = (&self.options.allowed_classes, request.query()) {
+            if !allowed.contains(&query.query_class()) {
+                debug!("Refusing {} class query from {}", query.query_class(), src);
+                return Ok(QueryOutcome {
+                    response: Some(dns::empty_response(&request, ResponseCode::Refused)),
+                    ..Default::default()
+                });
+            }
+        }
+
         let mut ctx = Context::new(src, request, stats.clone());
+        ctx.local_only = non_recursive && self.options.require_rd == RdPolicy::Local;
+        ctx.max_depth = self.options.max_depth;
+        ctx.profile = self.options.profile;
+        ctx.default_ttl = self.options.default_ttl;
+        ctx.started_at = started_at;
+        ctx.retry_budget = self.options.retry_budget.map(RetryBudget::new);
+
+        let mut coalesced = false;
+        let flight = if self.options.coalesce {
+            self.in_flight.join(&ctx.request)
+        } else {
+            Flight::Alone
+        };
+        match flight {
+            Flight::Leader(guard) => {
+                let result = self.resolve(&mut ctx).await;
+                guard.finish(ctx.response.clone().map(|r| (r, ctx.is_remote)));
+                result?;
+            }
+            Flight::Waiter(mut rx) => match rx.recv().await {
+                Ok(Some((mut response, is_remote))) => {
+                    debug!("Coalesced query from {} onto an in-flight one", src);
+                    response.set_id(ctx.request.id());
+                    ctx.response = Some(response);
+                    ctx.is_remote = is_remote;
+                    coalesced = true;
+                }
+                // The leader produced nothing or was dropped; resolve on our own.
+                _ => {
+                    debug!("In-flight query gave {} no response, resolving alone", src);
+                    self.resolve(&mut ctx).await?;
+                }
+            },
+            Flight::Alone => self.resolve(&mut ctx).await?,
+        }
 
-        plugin.next(&mut ctx).await?;
+        if let Some(response) = ctx.response.as_mut() {
+            dns::echo_question(&ctx.request, response);
 
-        if let Some(response) = ctx.response {
             // Record resolved IPs
             if let Some(d) = &domain {
                 for answer in response.answers() {
@@ -105,11 +553,935 @@ impl Server {
                     }
                 }
             }
+        }
+
+        log_query(&ctx);
+        let mut outcome = QueryOutcome::from_context(ctx);
+        outcome.coalesced = coalesced;
+        Ok(outcome)
+    }
 
-            let bytes = response.to_vec()?;
-            socket.send_to(&bytes, src).await?;
+    /// Run the entry plugin, turning chain-level failures into responses.
+    async fn resolve(&self, ctx: &mut Context) -> Result<()> {
+        let entry_plugin = self.entry_plugin.read().unwrap().clone();
+        if let Err(e) = exec(&entry_plugin, ctx).await {
+            if !e.is::<DepthExceeded>() {
+                return Err(e);
+            }
+            warn!("Query from {} aborted: {:#}", ctx.client_addr, e);
+            ctx.response = Some(dns::empty_response(&ctx.request, ResponseCode::ServFail));
+            ctx.answered_by = None;
         }
 
+        if ctx.response.is_none() && ctx.local_only {
+            // Nothing local could answer and we are not allowed to recurse.
+            ctx.response = Some(dns::empty_response(&ctx.request, ResponseCode::Refused));
+        }
+
+        if ctx.response.is_none() && !ctx.dropped && self.options.servfail_on_empty {
+            debug!(
+                "No response for query from {}, answering SERVFAIL",
+                ctx.client_addr
+            );
+            ctx.response = Some(dns::empty_response(&ctx.request, ResponseCode::ServFail));
+        }
+
+        // Responses built locally are kept small; upstream ones pass as they came.
+        if ctx.upstream.is_none() && !ctx.cache_hit {
+            if let Some(response) = ctx.response.as_mut() {
+                dns::cap_sections(
+                    response,
+                    self.options.max_authority_records,
+                    self.options.max_additional_records,
+                );
+            }
+        }
         Ok(())
     }
 }
+
+/// One structured event per resolved query, e.g. for `RUST_LOG=clean_dns=info`.
+fn log_query(ctx: &Context) {
+    let Some(query) = ctx.request.query() else {
+        return;
+    };
+    let (rcode, answers) = match &ctx.response {
+        Some(response) => (
+            dns::rcode_name(response.response_code()),
+            response.answers().len(),
+        ),
+        None => ("NONE".to_string(), 0),
+    };
+    info!(
+        client = %ctx.client_addr.ip(),
+        name = %query.name(),
+        qtype = %query.query_type(),
+        rcode = %rcode,
+        answers,
+        cache_hit = ctx.cache_hit,
+        upstream = ctx.upstream.as_deref().unwrap_or("-"),
+        duration_us = ctx.started_at.elapsed().as_micros() as u64,
+        "query"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plugins::reject_plugin::RejectPlugin;
+    use crate::transport::Responder;
+    use async_trait::async_trait;
+    use hickory_proto::op::Query;
+    use hickory_proto::rr::{Name, RecordType};
+    use tokio::sync::{mpsc, Mutex};
+
+    // Mock transport fed through a channel; closing the sender closes the transport.
+    struct MockTransport {
+        rx: Mutex<mpsc::Receiver<IncomingQuery>>,
+    }
+
+    #[async_trait]
+    impl Transport for MockTransport {
+        async fn accept(&self) -> Result<Option<IncomingQuery>> {
+            Ok(self.rx.lock().await.recv().await)
+        }
+    }
+
+    struct MockResponder {
+        tx: mpsc::Sender<(&'static str, Vec<u8>)>,
+        transport: &'static str,
+    }
+
+    #[async_trait]
+    impl Responder for MockResponder {
+        async fn respond(&self, response: Vec<u8>) -> Result<()> {
+            self.tx.send((self.transport, response)).await?;
+            Ok(())
+        }
+    }
+
+    fn make_query(id: u16) -> Vec<u8> {
+        make_named_query(id, "example.com.")
+    }
+
+    fn make_named_query(id: u16, name: &str) -> Vec<u8> {
+        let mut msg = Message::new();
+        msg.set_id(id);
+        msg.set_recursion_desired(true);
+        // `from_ascii` keeps the caller's case; `from_str` would IDNA-lowercase it.
+        msg.add_query(Query::query(Name::from_ascii(name).unwrap(), RecordType::A));
+        msg.to_vec().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_transports_share_processing() {
+        let config: serde_yaml::Value = serde_yaml::from_str("rcode: 3").unwrap();
+        let plugin: SharedPlugin = Arc::new(RejectPlugin::new(Some(&config)).unwrap());
+        let stats = Arc::new(RwLock::new(Statistics::new()));
+        let server = Server::new("127.0.0.1:0".parse().unwrap(), plugin, stats.clone());
+
+        let (responses_tx, mut responses_rx) = mpsc::channel(4);
+        let mut handles = Vec::new();
+        for (name, id) in [("first", 1u16), ("second", 2u16)] {
+            let (tx, rx) = mpsc::channel(1);
+            let transport = MockTransport { rx: Mutex::new(rx) };
+            let srv = server.clone();
+            handles.push(tokio::spawn(async move { srv.serve(transport).await }));
+
+            tx.send(IncomingQuery {
+                bytes: make_query(id),
+                src: "127.0.0.1:5300".parse().unwrap(),
+                responder: Box::new(MockResponder {
+                    tx: responses_tx.clone(),
+                    transport: name,
+                }),
+                tcp_keepalive: None,
+                datagram: false,
+            })
+            .await
+            .unwrap();
+        }
+
+        let mut seen = Vec::new();
+        for _ in 0..2 {
+            let (transport, bytes) = responses_rx.recv().await.unwrap();
+            let response = Message::from_vec(&bytes).unwrap();
+            assert_eq!(response.response_code(), ResponseCode::NXDomain);
+            seen.push((transport, response.id()));
+        }
+        seen.sort();
+        assert_eq!(seen, vec![("first", 1), ("second", 2)]);
+
+        // Both transports recorded into the same statistics.
+        assert_eq!(stats.read().unwrap().domains["example.com"].count, 2);
+
+        // Dropping the senders closes the transports and ends `serve`.
+        for handle in handles {
+            handle.await.unwrap().unwrap();
+        }
+    }
+
+    /// Collects formatted log output for assertions.
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_log_event() {
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer(move || writer.clone())
+            .with_ansi(false)
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let config: serde_yaml::Value = serde_yaml::from_str("rcode: 3").unwrap();
+        let plugin: SharedPlugin = Arc::new(RejectPlugin::new(Some(&config)).unwrap());
+        let server = Server::new(
+            "127.0.0.1:0".parse().unwrap(),
+            plugin,
+            Arc::new(RwLock::new(Statistics::new())),
+        );
+        let src = "192.0.2.7:5300".parse().unwrap();
+        server.process_query(&make_query(1), src).await.unwrap();
+
+        let output = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let line = output.lines().find(|l| l.contains(" query ")).unwrap();
+        for field in [
+            "client=192.0.2.7",
+            "name=example.com.",
+            "qtype=A",
+            "rcode=NXDOMAIN",
+            "answers=0",
+            "cache_hit=false",
+            "duration_us=",
+        ] {
+            assert!(line.contains(field), "{} missing from {}", field, line);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_swap_entry_plugin() {
+        let reject = |rcode: &str| -> SharedPlugin {
+            let config: serde_yaml::Value = serde_yaml::from_str(rcode).unwrap();
+            Arc::new(RejectPlugin::new(Some(&config)).unwrap())
+        };
+        let stats = Arc::new(RwLock::new(Statistics::new()));
+        let server = Server::new("127.0.0.1:0".parse().unwrap(), reject("rcode: 3"), stats);
+        let listener = server.clone();
+
+        let src = "127.0.0.1:5300".parse().unwrap();
+        let outcome = listener.process_query(&make_query(1), src).await.unwrap();
+        assert_eq!(
+            outcome.response.unwrap().response_code(),
+            ResponseCode::NXDomain
+        );
+
+        // Swapping through one handle reroutes queries on every clone.
+        server.set_entry_plugin(reject("rcode: 5"));
+        let outcome = listener.process_query(&make_query(2), src).await.unwrap();
+        assert_eq!(
+            outcome.response.unwrap().response_code(),
+            ResponseCode::Refused
+        );
+    }
+
+    #[tokio::test]
+    async fn test_servfail_on_empty() {
+        use crate::plugins::drop::DropPlugin;
+        use crate::plugins::sequence::Sequence;
+        use std::collections::HashMap;
+
+        let empty: SharedPlugin = Arc::new(Sequence::new(None, &HashMap::new()).unwrap());
+        let stats = Arc::new(RwLock::new(Statistics::new()));
+        let src = "127.0.0.1:5300".parse().unwrap();
+
+        let server = Server::new("127.0.0.1:0".parse().unwrap(), empty.clone(), stats.clone());
+        let response = server
+            .process_query(&make_query(5), src)
+            .await
+            .unwrap()
+            .response
+            .unwrap();
+        assert_eq!(response.response_code(), ResponseCode::ServFail);
+        assert_eq!(response.id(), 5);
+        assert_eq!(response.queries()[0].name().to_ascii(), "example.com.");
+
+        // Deliberate drops stay silent.
+        let server = Server::new(
+            "127.0.0.1:0".parse().unwrap(),
+            Arc::new(DropPlugin),
+            stats.clone(),
+        );
+        let outcome = server.process_query(&make_query(6), src).await.unwrap();
+        assert!(outcome.response.is_none());
+
+        let server =
+            Server::new("127.0.0.1:0".parse().unwrap(), empty, stats).with_options(ServerOptions {
+                servfail_on_empty: false,
+                ..Default::default()
+            });
+        let outcome = server.process_query(&make_query(7), src).await.unwrap();
+        assert!(outcome.response.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_depth_exceeded_servfail() {
+        use crate::plugins::sequence::Sequence;
+        use std::collections::HashMap;
+
+        // A sequence nested deeper than the configured limit.
+        let config: serde_yaml::Value = serde_yaml::from_str("rcode: 3").unwrap();
+        let mut plugin: SharedPlugin = Arc::new(RejectPlugin::new(Some(&config)).unwrap());
+        for _ in 0..4 {
+            let mut registry = HashMap::new();
+            registry.insert("inner".to_string(), plugin);
+            let config: serde_yaml::Value = serde_yaml::from_str("exec: [inner]").unwrap();
+            plugin = Arc::new(Sequence::new(Some(&config), &registry).unwrap());
+        }
+
+        let stats = Arc::new(RwLock::new(Statistics::new()));
+        let server = Server::new("127.0.0.1:0".parse().unwrap(), plugin, stats);
+
+        let src = "127.0.0.1:5300".parse().unwrap();
+        let outcome = server.process_query(&make_query(7), src).await.unwrap();
+        assert_eq!(
+            outcome.response.unwrap().response_code(),
+            ResponseCode::NXDomain
+        );
+
+        let server = server.with_options(ServerOptions {
+            max_depth: 2,
+            ..Default::default()
+        });
+        let outcome = server.process_query(&make_query(8), src).await.unwrap();
+        let response = outcome.response.unwrap();
+        assert_eq!(response.id(), 8);
+        assert_eq!(response.response_code(), ResponseCode::ServFail);
+    }
+
+    #[tokio::test]
+    async fn test_question_case_preserved() {
+        use crate::plugins::cache::Cache;
+        use crate::plugins::hosts::Hosts;
+        use std::collections::HashMap;
+
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("hosts:\n  example.com: 10.0.0.1").unwrap();
+        let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+        registry.insert(
+            "hosts".to_string(),
+            Arc::new(Hosts::new(Some(&config)).unwrap()),
+        );
+        let config: serde_yaml::Value = serde_yaml::from_str("size: 16\nexec: [hosts]").unwrap();
+        let plugin: SharedPlugin = Arc::new(Cache::new(Some(&config), &registry).unwrap());
+
+        let stats = Arc::new(RwLock::new(Statistics::new()));
+        let server = Server::new("127.0.0.1:0".parse().unwrap(), plugin, stats);
+        let src = "127.0.0.1:5300".parse().unwrap();
+
+        // The first query populates the cache in lower case; the second is a cache hit.
+        for (id, name) in [(1, "example.com."), (2, "ExAmPlE.CoM.")] {
+            let bytes = make_named_query(id, name);
+            let outcome = server.process_query(&bytes, src).await.unwrap();
+            let response = outcome.response.unwrap();
+            let response = Message::from_vec(&response.to_vec().unwrap()).unwrap();
+            assert_eq!(response.queries()[0].name().to_string(), name);
+            assert_eq!(response.answers()[0].name().to_string(), name);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_query_outcome() {
+        use crate::plugins::cache::Cache;
+        use crate::plugins::hosts::Hosts;
+        use std::collections::HashMap;
+
+        let config: serde_yaml::Value =
+            serde_yaml::from_str("hosts:\n  example.com: 10.0.0.1").unwrap();
+        let mut registry: HashMap<String, SharedPlugin> = HashMap::new();
+        registry.insert(
+            "hosts".to_string(),
+            Arc::new(Hosts::new(Some(&config)).unwrap()),
+        );
+        let config: serde_yaml::Value = serde_yaml::from_str("size: 16\nexec: [hosts]").unwrap();
+        let plugin: SharedPlugin = Arc::new(Cache::new(Some(&config), &registry).unwrap());
+
+        let stats = Arc::new(RwLock::new(Statistics::new()));
+        let server = Server::new("127.0.0.1:0".parse().unwrap(), plugin, stats);
+        let src = "127.0.0.1:5300".parse().unwrap();
+
+        let outcome = server.process_query(&make_query(1), src).await.unwrap();
+        assert!(outcome.response.is_some());
+        assert_eq!(outcome.answered_by.as_deref(), Some("hosts"));
+        assert!(!outcome.cache_hit);
+        assert_eq!(outcome.upstream, None);
+        assert_eq!(outcome.trace, vec!["cache", "hosts"]);
+
+        let outcome = server.process_query(&make_query(2), src).await.unwrap();
+        assert_eq!(outcome.answered_by.as_deref(), Some("cache"));
+        assert!(outcome.cache_hit);
+        assert_eq!(outcome.trace, vec!["cache"]);
+    }
+
+    #[tokio::test]
+    async fn test_tcp_keepalive() {
+        use crate::transport::TcpTransport;
+        use hickory_proto::op::Edns;
+        use hickory_proto::rr::rdata::opt::EdnsOption;
+        use std::time::Duration;
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        use tokio::net::TcpStream;
+
+        let config: serde_yaml::Value = serde_yaml::from_str("rcode: 3").unwrap();
+        let plugin: SharedPlugin = Arc::new(RejectPlugin::new(Some(&config)).unwrap());
+        let stats = Arc::new(RwLock::new(Statistics::new()));
+        let server = Server::new("127.0.0.1:0".parse().unwrap(), plugin, stats);
+
+        let idle = Duration::from_millis(800);
+        let transport = TcpTransport::bind("127.0.0.1:0".parse().unwrap(), idle)
+            .await
+            .unwrap();
+        let addr = transport.local_addr().unwrap();
+        tokio::spawn(async move { server.serve(transport).await });
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        async fn exchange(stream: &mut TcpStream, id: u16, keepalive: bool) -> Message {
+            let mut msg = Message::from_vec(&make_query(id)).unwrap();
+            if keepalive {
+                let mut edns = Edns::new();
+                edns.options_mut()
+                    .insert(EdnsOption::Unknown(u16::from(EdnsCode::Keepalive), vec![]));
+                msg.set_edns(edns);
+            }
+            let bytes = msg.to_vec().unwrap();
+            stream
+                .write_all(&(bytes.len() as u16).to_be_bytes())
+                .await
+                .unwrap();
+            stream.write_all(&bytes).await.unwrap();
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).await.unwrap();
+            let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut buf).await.unwrap();
+            Message::from_vec(&buf).unwrap()
+        }
+        fn keepalive(msg: &Message) -> Option<Vec<u8>> {
+            match msg.extensions().as_ref()?.option(EdnsCode::Keepalive)? {
+                EdnsOption::Unknown(_, data) => Some(data.clone()),
+                _ => None,
+            }
+        }
+
+        let response = exchange(&mut stream, 1, true).await;
+        assert_eq!(response.id(), 1);
+        assert_eq!(keepalive(&response), Some(vec![0, 8])); // 800ms in 100ms units
+
+        // Still open within the advertised window; no option unless asked for.
+        tokio::time::sleep(idle / 2).await;
+        let response = exchange(&mut stream, 2, false).await;
+        assert_eq!(response.id(), 2);
+        assert_eq!(keepalive(&response), None);
+
+        // Closed by the server once idle for longer than the window.
+        tokio::time::sleep(idle * 2).await;
+        let mut buf = [0u8; 1];
+        assert_eq!(stream.read(&mut buf).await.unwrap(), 0);
+
+        // Also when a frame is started but never finished.
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(&[0, 40, 0, 1]).await.unwrap();
+        let read = tokio::time::timeout(idle * 3, stream.read(&mut buf)).await;
+        assert_eq!(read.expect("Connection kept open").unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_udp_response_truncated() {
+        use crate::plugins::Plugin;
+        use crate::transport::UdpTransport;
+        use hickory_proto::op::Edns;
+        use hickory_proto::rr::rdata::TXT;
+        use hickory_proto::rr::{RData, Record};
+        use tokio::net::UdpSocket;
+
+        /// Answers with about 2 KB of TXT data.
+        struct BigAnswer;
+
+        #[async_trait]
+        impl Plugin for BigAnswer {
+            fn name(&self) -> &str {
+                "big"
+            }
+
+            async fn next(&self, ctx: &mut Context) -> Result<()> {
+                let mut response = dns::empty_response(&ctx.request, ResponseCode::NoError);
+                let name = ctx.request.query().unwrap().name().clone();
+                response.add_answer(Record::from_rdata(
+                    name,
+                    60,
+                    RData::TXT(TXT::new(vec!["x".repeat(200); 10])),
+                ));
+                ctx.response = Some(response);
+                Ok(())
+            }
+        }
+
+        let stats = Arc::new(RwLock::new(Statistics::new()));
+        let server = Server::new("127.0.0.1:0".parse().unwrap(), Arc::new(BigAnswer), stats);
+        let transport = UdpTransport::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let addr = transport.local_addr().unwrap();
+        tokio::spawn(async move { server.serve(transport).await });
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let exchange = |payload: Option<u16>| {
+            let client = &client;
+            async move {
+                let mut msg = Message::from_vec(&make_query(7)).unwrap();
+                if let Some(payload) = payload {
+                    let mut edns = Edns::new();
+                    edns.set_max_payload(payload);
+                    msg.set_edns(edns);
+                }
+                client.send_to(&msg.to_vec().unwrap(), addr).await.unwrap();
+                let mut buf = [0u8; 65535];
+                let (len, _) = client.recv_from(&mut buf).await.unwrap();
+                (len, Message::from_vec(&buf[..len]).unwrap())
+            }
+        };
+
+        // Too big for the advertised size (or the 512 bytes without EDNS): TC.
+        for payload in [Some(1232), None] {
+            let (len, response) = exchange(payload).await;
+            assert!(response.truncated(), "{:?}", payload);
+            assert!(response.answers().is_empty());
+            assert!(len <= 512);
+        }
+
+        let (len, response) = exchange(Some(4096)).await;
+        assert!(!response.truncated());
+        assert_eq!(response.answers().len(), 1);
+        assert!(len > 1232);
+    }
+
+    #[tokio::test]
+    async fn test_synthesized_section_caps() {
+        use hickory_proto::rr::{Name, RData, Record};
+        use std::net::Ipv4Addr;
+
+        /// Answers with five authority and five additional records, as if
+        /// forwarded when `upstream` is set.
+        struct Bloated {
+            upstream: bool,
+        }
+
+        #[async_trait::async_trait]
+        impl crate::plugins::Plugin for Bloated {
+            fn name(&self) -> &str {
+                "bloated"
+            }
+
+            async fn next(&self, ctx: &mut Context) -> Result<()> {
+                let mut response = dns::empty_response(&ctx.request, ResponseCode::NoError);
+                for i in 0..5 {
+                    let name = Name::from_ascii(format!("ns{}.example.com.", i)).unwrap();
+                    let rdata = RData::A(Ipv4Addr::new(192, 0, 2, i).into());
+                    response.add_name_server(Record::from_rdata(name.clone(), 60, rdata.clone()));
+                    response.add_additional(Record::from_rdata(name, 60, rdata));
+                }
+                ctx.response = Some(response);
+                if self.upstream {
+                    ctx.upstream = Some("192.0.2.53:53".to_string());
+                }
+                Ok(())
+            }
+        }
+
+        let options = ServerOptions {
+            max_authority_records: Some(1),
+            max_additional_records: Some(2),
+            ..Default::default()
+        };
+        let src = "127.0.0.1:5300".parse().unwrap();
+        for (upstream, expected) in [(false, (1, 2)), (true, (5, 5))] {
+            let stats = Arc::new(RwLock::new(Statistics::new()));
+            let plugin = Arc::new(Bloated { upstream });
+            let server = Server::new("127.0.0.1:0".parse().unwrap(), plugin, stats)
+                .with_options(options.clone());
+            let outcome = server.process_query(&make_query(1), src).await.unwrap();
+            let response = outcome.response.unwrap();
+            assert_eq!(
+                (response.name_servers().len(), response.additionals().len()),
+                expected,
+                "upstream: {}",
+                upstream
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_flight_gauge() {
+        use tokio::net::UdpSocket;
+
+        struct Slow;
+
+        #[async_trait::async_trait]
+        impl crate::plugins::Plugin for Slow {
+            fn name(&self) -> &str {
+                "slow"
+            }
+
+            async fn next(&self, ctx: &mut Context) -> Result<()> {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                ctx.response = Some(dns::empty_response(&ctx.request, ResponseCode::NoError));
+                Ok(())
+            }
+        }
+
+        let stats = Arc::new(RwLock::new(Statistics::new()));
+        let server = Server::new(
+            "127.0.0.1:0".parse().unwrap(),
+            Arc::new(Slow),
+            stats.clone(),
+        )
+        .with_options(ServerOptions {
+            max_in_flight: Some(4),
+            ..Default::default()
+        });
+        let transport = UdpTransport::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let addr = transport.local_addr().unwrap();
+        tokio::spawn(async move { server.serve(transport).await });
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for id in 0..6 {
+            client.send_to(&make_query(id), addr).await.unwrap();
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let gauge = stats.read().unwrap().in_flight.clone();
+        assert_eq!(gauge.get(), 4);
+        assert_eq!(
+            serde_json::to_value(&*stats.read().unwrap()).unwrap()["in_flight"],
+            4
+        );
+
+        let mut buf = [0u8; 512];
+        for _ in 0..4 {
+            client.recv_from(&mut buf).await.unwrap();
+        }
+        // The two queries over the limit were dropped, not queued.
+        let extra = tokio::time::timeout(Duration::from_millis(200), client.recv_from(&mut buf));
+        assert!(extra.await.is_err());
+        assert_eq!(gauge.get(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_drains_queries() {
+        struct Slow;
+
+        #[async_trait::async_trait]
+        impl crate::plugins::Plugin for Slow {
+            fn name(&self) -> &str {
+                "slow"
+            }
+
+            async fn next(&self, ctx: &mut Context) -> Result<()> {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                ctx.response = Some(dns::empty_response(&ctx.request, ResponseCode::NoError));
+                Ok(())
+            }
+        }
+
+        let stats = Arc::new(RwLock::new(Statistics::new()));
+        let server = Server::new("127.0.0.1:0".parse().unwrap(), Arc::new(Slow), stats);
+        let running = tokio::spawn(server.clone().run());
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        server.shutdown();
+        let result = tokio::time::timeout(Duration::from_secs(1), running).await;
+        assert!(result.unwrap().unwrap().is_ok());
+
+        // A query already being handled is still answered.
+        let server = Server::new(
+            "127.0.0.1:0".parse().unwrap(),
+            Arc::new(Slow),
+            Arc::new(RwLock::new(Statistics::new())),
+        );
+        let (tx, rx) = mpsc::channel(1);
+        let transport = MockTransport { rx: Mutex::new(rx) };
+        let srv = server.clone();
+        let serving = tokio::spawn(async move { srv.serve(transport).await });
+        let (responses_tx, mut responses_rx) = mpsc::channel(1);
+        tx.send(IncomingQuery {
+            bytes: make_query(9),
+            src: "127.0.0.1:5300".parse().unwrap(),
+            responder: Box::new(MockResponder {
+                tx: responses_tx,
+                transport: "mock",
+            }),
+            tcp_keepalive: None,
+            datagram: false,
+        })
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        server.shutdown();
+        serving.await.unwrap().unwrap();
+        let (_, bytes) = responses_rx.try_recv().unwrap();
+        assert_eq!(Message::from_vec(&bytes).unwrap().id(), 9);
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_identical_queries() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        struct SlowAnswer {
+            calls: AtomicUsize,
+        }
+
+        #[async_trait]
+        impl crate::plugins::Plugin for SlowAnswer {
+            fn name(&self) -> &str {
+                "slow"
+            }
+
+            async fn next(&self, ctx: &mut Context) -> Result<()> {
+                self.calls.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                ctx.response = Some(dns::empty_response(&ctx.request, ResponseCode::NoError));
+                Ok(())
+            }
+        }
+
+        let plugin = Arc::new(SlowAnswer {
+            calls: AtomicUsize::new(0),
+        });
+        let stats = Arc::new(RwLock::new(Statistics::new()));
+        let server = Server::new("127.0.0.1:0".parse().unwrap(), plugin.clone(), stats)
+            .with_options(ServerOptions {
+                coalesce: true,
+                ..Default::default()
+            });
+
+        let mut handles = Vec::new();
+        for id in 0..20u16 {
+            let server = server.clone();
+            // Mixed case still coalesces onto the same question.
+            let name = if id % 2 == 0 {
+                "example.com."
+            } else {
+                "EXAMPLE.com."
+            };
+            handles.push(tokio::spawn(async move {
+                let src = "127.0.0.1:5300".parse().unwrap();
+                let bytes = make_named_query(id, name);
+                (id, name, server.process_query(&bytes, src).await.unwrap())
+            }));
+        }
+
+        let mut coalesced = 0;
+        for handle in handles {
+            let (id, name, outcome) = handle.await.unwrap();
+            let response = outcome.response.unwrap();
+            assert_eq!(response.id(), id);
+            assert_eq!(response.queries()[0].name().to_string(), name);
+            coalesced += outcome.coalesced as usize;
+        }
+        assert_eq!(plugin.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(coalesced, 19);
+
+        // Once finished, the next query runs the chain again.
+        let src = "127.0.0.1:5300".parse().unwrap();
+        server.process_query(&make_query(99), src).await.unwrap();
+        assert_eq!(plugin.calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_without_response() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::time::Duration;
+
+        #[derive(Default)]
+        struct SlowSilence {
+            calls: AtomicUsize,
+        }
+
+        #[async_trait]
+        impl crate::plugins::Plugin for SlowSilence {
+            fn name(&self) -> &str {
+                "silent"
+            }
+
+            async fn next(&self, _ctx: &mut Context) -> Result<()> {
+                self.calls.fetch_add(1, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                Ok(())
+            }
+        }
+
+        let plugin = Arc::new(SlowSilence::default());
+        let stats = Arc::new(RwLock::new(Statistics::new()));
+        let server = Server::new("127.0.0.1:0".parse().unwrap(), plugin.clone(), stats)
+            .with_options(ServerOptions {
+                coalesce: true,
+                servfail_on_empty: false,
+                ..Default::default()
+            });
+        let src: SocketAddr = "127.0.0.1:5300".parse().unwrap();
+        let first = make_query(1);
+        let (leader, waiter) = tokio::join!(server.process_query(&first, src), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            server.process_query(&make_query(2), src).await
+        });
+        assert!(leader.unwrap().response.is_none());
+        // The waiter resolved on its own instead of inheriting a SERVFAIL.
+        let waiter = waiter.unwrap();
+        assert!(!waiter.coalesced);
+        assert!(waiter.response.is_none());
+        assert_eq!(plugin.calls.load(Ordering::SeqCst), 2);
+
+        // A finished leader's guard leaves the entry of the next leader alone.
+        let request = Message::from_vec(&make_query(3)).unwrap();
+        let Flight::Leader(first) = server.in_flight.join(&request) else {
+            panic!("Expected leader");
+        };
+        first.in_flight.queries.lock().unwrap().clear();
+        let Flight::Leader(second) = server.in_flight.join(&request) else {
+            panic!("Expected leader");
+        };
+        drop(first);
+        assert!(matches!(server.in_flight.join(&request), Flight::Waiter(_)));
+        second.finish(None);
+        assert!(server.in_flight.queries.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_coalesce_key_flags() {
+        use hickory_proto::op::Edns;
+        use hickory_proto::rr::rdata::opt::{ClientSubnet, EdnsOption};
+
+        let in_flight = Arc::new(InFlight::default());
+        let plain = Message::from_vec(&make_query(1)).unwrap();
+        let Flight::Leader(_leader) = in_flight.join(&plain) else {
+            panic!("Expected leader");
+        };
+        assert!(matches!(in_flight.join(&plain), Flight::Waiter(_)));
+
+        // DO and CD each get their own flight.
+        let mut dnssec = plain.clone();
+        let mut edns = Edns::new();
+        edns.set_dnssec_ok(true);
+        dnssec.set_edns(edns);
+        assert!(matches!(in_flight.join(&dnssec), Flight::Leader(_)));
+        let mut unchecked = plain.clone();
+        unchecked.set_checking_disabled(true);
+        assert!(matches!(in_flight.join(&unchecked), Flight::Leader(_)));
+
+        // Subnet-scoped queries never coalesce.
+        let mut scoped = plain.clone();
+        let mut edns = Edns::new();
+        edns.options_mut()
+            .insert(EdnsOption::Subnet(ClientSubnet::new(
+                "198.51.100.0".parse().unwrap(),
+                24,
+                0,
+            )));
+        scoped.set_edns(edns);
+        assert!(matches!(in_flight.join(&scoped), Flight::Alone));
+        assert!(matches!(in_flight.join(&scoped), Flight::Alone));
+    }
+
+    #[tokio::test]
+    async fn test_malformed_packets_sampled() {
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let config: serde_yaml::Value = serde_yaml::from_str("rcode: 3").unwrap();
+        let plugin = Arc::new(RejectPlugin::new(Some(&config)).unwrap());
+        let stats = Arc::new(RwLock::new(Statistics::new()));
+        let server = Server::new("127.0.0.1:0".parse().unwrap(), plugin, stats.clone())
+            .with_options(ServerOptions {
+                malformed_log_every: 10,
+                ..Default::default()
+            });
+
+        let src = "127.0.0.1:5300".parse().unwrap();
+        let mut truncated = make_query(1);
+        truncated.truncate(truncated.len() - 3);
+        let mut response = make_query(2);
+        response[2] |= 0x80;
+        let packets = [vec![0xff; 5], truncated, response];
+        for i in 0..25 {
+            let outcome = server
+                .process_query(&packets[i % packets.len()], src)
+                .await
+                .unwrap();
+            assert!(outcome.response.is_none());
+        }
+        // Well-formed queries are neither counted nor dropped.
+        let outcome = server.process_query(&make_query(3), src).await.unwrap();
+        assert!(outcome.response.is_some());
+
+        assert_eq!(stats.read().unwrap().malformed_packets, 25);
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert_eq!(logs.matches("Dropping malformed packet").count(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_refuse_disallowed_class() {
+        let config: serde_yaml::Value = serde_yaml::from_str("rcode: 3").unwrap();
+        let plugin = Arc::new(RejectPlugin::new(Some(&config)).unwrap());
+        let stats = Arc::new(RwLock::new(Statistics::new()));
+        let server = Server::new("127.0.0.1:0".parse().unwrap(), plugin, stats).with_options(
+            ServerOptions {
+                allowed_classes: Some(vec![DNSClass::IN, DNSClass::CH]),
+                ..Default::default()
+            },
+        );
+
+        let query = |class: DNSClass| {
+            let mut msg = Message::new();
+            msg.set_id(9);
+            let mut query = Query::query(Name::from_ascii("example.com.").unwrap(), RecordType::A);
+            query.set_query_class(class);
+            msg.add_query(query);
+            msg.to_vec().unwrap()
+        };
+        let src = "127.0.0.1:5300".parse().unwrap();
+
+        let outcome = server
+            .process_query(&query(DNSClass::HS), src)
+            .await
+            .unwrap();
+        let response = outcome.response.unwrap();
+        assert_eq!(response.response_code(), ResponseCode::Refused);
+        assert_eq!(response.id(), 9);
+
+        // Allowed classes reach the plugin chain.
+        for class in [DNSClass::IN, DNSClass::CH] {
+            let outcome = server.process_query(&query(class), src).await.unwrap();
+            assert_eq!(
+                outcome.response.unwrap().response_code(),
+                ResponseCode::NXDomain
+            );
+        }
+    }
+}
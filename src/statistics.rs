@@ -1,20 +1,201 @@
+use crate::dns::{canonical_name, rcode_name};
+use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
-use serde::Serialize;
-use std::collections::{HashMap, HashSet};
+use hickory_proto::op::ResponseCode;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use tracing::{error, info};
 
-#[derive(Debug, Default, Serialize, Clone)]
+// Persisted snapshots may come from older or newer versions: missing fields
+// take their defaults and unknown fields are ignored.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct Statistics {
+    /// Queries with a question, across all domains.
+    pub total_queries: u64,
+    pub total_cache_hits: u64,
+    /// Responses sent, keyed by rcode mnemonic (`NOERROR`, `NXDOMAIN`, ...).
+    pub responses: BTreeMap<String, u64>,
     pub domains: HashMap<String, DomainStats>,
+    /// Upstream exchanges keyed by protocol (`udp`, `tcp`, `dot`, `doh`).
+    pub upstream_protocols: BTreeMap<String, ProtocolStats>,
+    /// Plugin execution times keyed by tag; only filled with `profile` enabled.
+    pub plugin_timings: BTreeMap<String, Histogram>,
+    /// Packets dropped because they were not a parseable query.
+    pub malformed_packets: u64,
+    /// Recent query rates; computed when serialized and not restored from snapshots.
+    #[serde(skip_deserializing, serialize_with = "serialize_qps")]
+    pub qps: QpsTracker,
+    /// Queries being handled right now; not restored from snapshots.
+    #[serde(skip_deserializing)]
+    pub in_flight: InFlightGauge,
 }
 
-#[derive(Debug, Serialize, Clone)]
+/// Count of handler tasks currently running, shared by every clone of the
+/// statistics (and so by every server).
+#[derive(Debug, Clone, Default)]
+pub struct InFlightGauge(Arc<AtomicUsize>);
+
+/// Keeps one query counted in its [`InFlightGauge`] until dropped.
+pub struct InFlightGuard(Arc<AtomicUsize>);
+
+impl InFlightGauge {
+    pub fn get(&self) -> usize {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Count one more query, unless `limit` (if any) are already in flight.
+    pub fn enter(&self, limit: Option<usize>) -> Option<InFlightGuard> {
+        let limit = limit.unwrap_or(usize::MAX);
+        self.0
+            .fetch_update(Ordering::AcqRel, Ordering::Relaxed, |n| {
+                (n < limit).then_some(n + 1)
+            })
+            .ok()?;
+        Some(InFlightGuard(self.0.clone()))
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl Serialize for InFlightGauge {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(self.get() as u64)
+    }
+}
+
+/// Sliding windows, in seconds, reported by [`QpsTracker::rates`].
+pub const QPS_WINDOWS: &[(&str, u64)] = &[("1s", 1), ("1m", 60), ("5m", 300)];
+
+/// One slot per second of the longest window, plus the second in progress.
+const QPS_SLOTS: usize = 301;
+
+/// Queries per second over the `QPS_WINDOWS`, from a ring buffer of per-second
+/// counts. Windows cover whole seconds before the current one, so a rate does
+/// not dip each time a new second starts.
+#[derive(Debug, Clone)]
+pub struct QpsTracker {
+    start: Instant,
+    /// Second (since `start`) each slot currently counts.
+    seconds: [u64; QPS_SLOTS],
+    counts: [u64; QPS_SLOTS],
+}
+
+impl Default for QpsTracker {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+            seconds: [u64::MAX; QPS_SLOTS],
+            counts: [0; QPS_SLOTS],
+        }
+    }
+}
+
+impl QpsTracker {
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_secs()
+    }
+
+    pub fn record(&mut self) {
+        self.record_at(self.now());
+    }
+
+    fn record_at(&mut self, second: u64) {
+        let slot = (second % QPS_SLOTS as u64) as usize;
+        if self.seconds[slot] != second {
+            self.seconds[slot] = second;
+            self.counts[slot] = 0;
+        }
+        self.counts[slot] += 1;
+    }
+
+    /// Average rate for each of `QPS_WINDOWS`, by window label.
+    pub fn rates(&self) -> Vec<(&'static str, f64)> {
+        self.rates_at(self.now())
+    }
+
+    fn rates_at(&self, now: u64) -> Vec<(&'static str, f64)> {
+        QPS_WINDOWS
+            .iter()
+            .map(|&(label, window)| {
+                let total: u64 = self
+                    .seconds
+                    .iter()
+                    .zip(&self.counts)
+                    .filter(|(&second, _)| second < now && now - second <= window)
+                    .map(|(_, &count)| count)
+                    .sum();
+                (label, total as f64 / window as f64)
+            })
+            .collect()
+    }
+}
+
+fn serialize_qps<S: serde::Serializer>(qps: &QpsTracker, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(qps.rates())
+}
+
+/// Upper bounds, in seconds, of the latency histogram buckets.
+pub const TIMING_BUCKETS: &[f64] = &[0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Histogram {
+    /// Observations per bucket of `TIMING_BUCKETS` (not cumulative); the last
+    /// slot counts those above every bound.
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum_seconds: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, value: Duration) {
+        let secs = value.as_secs_f64();
+        self.buckets.resize(TIMING_BUCKETS.len() + 1, 0);
+        let slot = TIMING_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(TIMING_BUCKETS.len());
+        self.buckets[slot] += 1;
+        self.count += 1;
+        self.sum_seconds += secs;
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct ProtocolStats {
+    pub queries: u64,
+    pub failures: u64,
+    pub total_latency_ms: f64,
+    pub avg_latency_ms: f64,
+    pub latency: Histogram,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DomainStats {
+    #[serde(default)]
     pub count: usize,
+    #[serde(default = "Utc::now")]
     pub last_resolved_at: DateTime<Utc>,
+    #[serde(default)]
     pub last_resolved_remote: bool,
+    #[serde(default)]
     pub ips: HashSet<IpAddr>,
+    #[serde(default)]
     pub cache_hits: usize,
+    /// Queries by record type mnemonic (`A`, `AAAA`, ...).
+    #[serde(default)]
+    pub qtypes: HashMap<String, usize>,
 }
 
 impl Statistics {
@@ -22,19 +203,33 @@ impl Statistics {
         Self::default()
     }
 
+    // Domains are keyed by `dns::canonical_name`, whatever form callers pass.
+
     pub fn record_request(&mut self, domain: String) {
+        self.total_queries += 1;
+        self.qps.record();
+        let domain = canonical_name(&domain);
         let entry = self.domains.entry(domain).or_insert(DomainStats {
             count: 0,
             last_resolved_at: Utc::now(),
             last_resolved_remote: false,
             ips: HashSet::new(),
             cache_hits: 0,
+            qtypes: HashMap::new(),
         });
         entry.count += 1;
         entry.last_resolved_at = Utc::now();
     }
 
+    pub fn record_qtype(&mut self, domain: &str, qtype: &str) {
+        if let Some(entry) = self.domains.get_mut(&canonical_name(domain)) {
+            *entry.qtypes.entry(qtype.to_string()).or_default() += 1;
+        }
+    }
+
     pub fn record_cache_hit(&mut self, domain: String) {
+        self.total_cache_hits += 1;
+        let domain = canonical_name(&domain);
         if let Some(entry) = self.domains.get_mut(&domain) {
             entry.cache_hits += 1;
         } else {
@@ -51,17 +246,85 @@ impl Statistics {
                 last_resolved_remote: false,
                 ips: HashSet::new(),
                 cache_hits: 0,
+                qtypes: HashMap::new(),
             });
             entry.cache_hits += 1;
         }
     }
 
+    pub fn record_upstream(&mut self, protocol: &str, latency: Duration, success: bool) {
+        let entry = self
+            .upstream_protocols
+            .entry(protocol.to_string())
+            .or_default();
+        entry.queries += 1;
+        if !success {
+            entry.failures += 1;
+        }
+        entry.total_latency_ms += latency.as_secs_f64() * 1000.0;
+        entry.avg_latency_ms = entry.total_latency_ms / entry.queries as f64;
+        entry.latency.observe(latency);
+    }
+
+    pub fn record_response(&mut self, rcode: ResponseCode) {
+        *self.responses.entry(rcode_name(rcode)).or_default() += 1;
+    }
+
+    pub fn record_plugin_time(&mut self, tag: &str, elapsed: Duration) {
+        self.plugin_timings
+            .entry(tag.to_string())
+            .or_default()
+            .observe(elapsed);
+    }
+
     pub fn record_resolved_ip(&mut self, domain: &str, ip: IpAddr, is_remote: bool) {
-        if let Some(entry) = self.domains.get_mut(domain) {
+        if let Some(entry) = self.domains.get_mut(&canonical_name(domain)) {
             entry.ips.insert(ip);
             entry.last_resolved_remote = is_remote;
         }
     }
+
+    /// Load a snapshot written by [`Statistics::save`]; a missing file yields empty stats.
+    pub fn load(path: &Path) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(bytes) => serde_json::from_slice(&bytes)
+                .with_context(|| format!("Invalid statistics file {}", path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        }
+    }
+
+    /// Write the statistics as JSON, replacing `path` atomically.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+        Ok(())
+    }
+}
+
+/// Periodically saves shared statistics to disk.
+pub fn spawn_persist(
+    stats: Arc<RwLock<Statistics>>,
+    path: PathBuf,
+    interval: Duration,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.tick().await; // The first tick fires immediately.
+        loop {
+            ticker.tick().await;
+            let snapshot = stats.read().unwrap().clone();
+            let path = path.clone();
+            match tokio::task::spawn_blocking(move || snapshot.save(&path)).await {
+                Ok(Ok(())) => info!("Saved statistics"),
+                Ok(Err(e)) => error!("Failed to save statistics: {:#}", e),
+                Err(e) => error!("Statistics save task failed: {}", e),
+            }
+        }
+    })
 }
 
 #[cfg(test)]
@@ -74,9 +337,45 @@ mod tests {
         let mut stats = Statistics::new();
         stats.record_request("example.com.".to_string());
 
-        assert_eq!(stats.domains.get("example.com.").unwrap().count, 1);
+        assert_eq!(stats.domains.get("example.com").unwrap().count, 1);
         stats.record_request("example.com.".to_string());
-        assert_eq!(stats.domains.get("example.com.").unwrap().count, 2);
+        assert_eq!(stats.domains.get("example.com").unwrap().count, 2);
+    }
+
+    #[test]
+    fn test_record_qtype() {
+        let mut stats = Statistics::new();
+        for qtype in ["A", "A", "AAAA"] {
+            stats.record_request("example.com.".to_string());
+            stats.record_qtype("example.com.", qtype);
+        }
+
+        let qtypes = &stats.domains["example.com"].qtypes;
+        assert_eq!(qtypes.len(), 2);
+        assert_eq!(qtypes["A"], 2);
+        assert_eq!(qtypes["AAAA"], 1);
+    }
+
+    #[test]
+    fn test_qps_windows() {
+        let mut qps = QpsTracker::default();
+        for _ in 0..50 {
+            qps.record_at(10);
+        }
+        // The second in progress is not reported yet.
+        assert_eq!(qps.rates_at(10)[0], ("1s", 0.0));
+
+        let rates = qps.rates_at(11);
+        assert_eq!(rates[0], ("1s", 50.0));
+        assert!((rates[1].1 - 50.0 / 60.0).abs() < 1e-9);
+        assert!((rates[2].1 - 50.0 / 300.0).abs() < 1e-9);
+
+        // The burst ages out of each window, and slots are reused.
+        assert_eq!(qps.rates_at(12)[0].1, 0.0);
+        assert_eq!(qps.rates_at(80)[1].1, 0.0);
+        qps.record_at(10 + QPS_SLOTS as u64);
+        assert_eq!(qps.rates_at(11 + QPS_SLOTS as u64)[0].1, 1.0);
+        assert!((qps.rates_at(11 + QPS_SLOTS as u64)[2].1 - 1.0 / 300.0).abs() < 1e-9);
     }
 
     #[test]
@@ -86,7 +385,7 @@ mod tests {
         stats.record_request("example.com.".to_string());
         stats.record_cache_hit("example.com.".to_string());
 
-        assert_eq!(stats.domains.get("example.com.").unwrap().cache_hits, 1);
+        assert_eq!(stats.domains.get("example.com").unwrap().cache_hits, 1);
     }
 
     #[test]
@@ -98,17 +397,67 @@ mod tests {
         // Test local resolution
         stats.record_resolved_ip("example.com.", ip, false);
 
-        let entry = stats.domains.get("example.com.").unwrap();
+        let entry = stats.domains.get("example.com").unwrap();
         assert_eq!(entry.ips.len(), 1);
         assert!(entry.ips.contains(&ip));
-        assert_eq!(entry.last_resolved_remote, false);
+        assert!(!entry.last_resolved_remote);
 
         // Duplicate IP should not increase count, but update remote status?
         // Logic says yes.
         stats.record_resolved_ip("example.com.", ip, true);
 
-        let entry = stats.domains.get("example.com.").unwrap();
+        let entry = stats.domains.get("example.com").unwrap();
         assert_eq!(entry.ips.len(), 1);
-        assert_eq!(entry.last_resolved_remote, true);
+        assert!(entry.last_resolved_remote);
+    }
+
+    #[test]
+    fn test_record_upstream() {
+        let mut stats = Statistics::new();
+        stats.record_upstream("udp", Duration::from_millis(10), true);
+        stats.record_upstream("udp", Duration::from_millis(30), false);
+
+        let udp = &stats.upstream_protocols["udp"];
+        assert_eq!(udp.queries, 2);
+        assert_eq!(udp.failures, 1);
+        assert!((udp.avg_latency_ms - 20.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_persist_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("stats.json");
+
+        // Missing file starts from scratch.
+        assert!(Statistics::load(&path).unwrap().domains.is_empty());
+
+        let mut stats = Statistics::new();
+        stats.record_request("example.com.".to_string());
+        stats.record_request("example.com.".to_string());
+        stats.record_cache_hit("example.com.".to_string());
+        stats.record_resolved_ip("example.com.", IpAddr::V4(Ipv4Addr::new(1, 2, 3, 4)), true);
+        stats.record_upstream("udp", Duration::from_millis(10), true);
+        stats.save(&path).unwrap();
+
+        let mut loaded = Statistics::load(&path).unwrap();
+        let entry = &loaded.domains["example.com"];
+        assert_eq!(entry.count, 2);
+        assert_eq!(entry.cache_hits, 1);
+        assert!(entry.last_resolved_remote);
+        assert_eq!(loaded.upstream_protocols["udp"].queries, 1);
+
+        // Counting continues from the loaded values.
+        loaded.record_request("example.com".to_string());
+        assert_eq!(loaded.domains["example.com"].count, 3);
+
+        // Unknown and missing fields are tolerated.
+        std::fs::write(
+            &path,
+            r#"{"domains": {"a.com": {"count": 5, "future": 1}}, "future_section": {}}"#,
+        )
+        .unwrap();
+        let loaded = Statistics::load(&path).unwrap();
+        assert_eq!(loaded.domains["a.com"].count, 5);
+        assert!(loaded.upstream_protocols.is_empty());
     }
 }
@@ -0,0 +1,194 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream, UdpSocket};
+use tokio::sync::{mpsc, Mutex};
+use tracing::debug;
+
+/// Sends a serialized DNS response back to the client that asked.
+#[async_trait]
+pub trait Responder: Send + Sync {
+    async fn respond(&self, response: Vec<u8>) -> Result<()>;
+}
+
+/// A raw DNS query received by a transport, together with the means to answer it.
+pub struct IncomingQuery {
+    pub bytes: Vec<u8>,
+    pub src: SocketAddr,
+    pub responder: Box<dyn Responder>,
+    /// Idle timeout of the connection the query arrived on, for stream transports.
+    /// Advertised to clients that ask via EDNS TCP keepalive (RFC 7828).
+    pub tcp_keepalive: Option<Duration>,
+    /// The query came over UDP, so the response must fit the client's
+    /// advertised payload size.
+    pub datagram: bool,
+}
+
+/// A listener that yields DNS queries (UDP, TCP, ...).
+#[async_trait]
+pub trait Transport: Send + Sync {
+    /// Wait for the next query. `Ok(None)` means the transport is closed.
+    async fn accept(&self) -> Result<Option<IncomingQuery>>;
+}
+
+/// Receive buffer for UDP queries; EDNS queries may exceed the classic 512 bytes.
+const UDP_RECV_BUF: usize = 4096;
+
+pub struct UdpTransport {
+    socket: Arc<UdpSocket>,
+}
+
+impl UdpTransport {
+    pub async fn bind(addr: SocketAddr) -> Result<Self> {
+        let socket = UdpSocket::bind(addr).await?;
+        Ok(Self {
+            socket: Arc::new(socket),
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.socket.local_addr()?)
+    }
+}
+
+#[async_trait]
+impl Transport for UdpTransport {
+    async fn accept(&self) -> Result<Option<IncomingQuery>> {
+        let mut buf = [0u8; UDP_RECV_BUF];
+        let (size, src) = self.socket.recv_from(&mut buf).await?;
+        Ok(Some(IncomingQuery {
+            bytes: buf[..size].to_vec(),
+            src,
+            responder: Box::new(UdpResponder {
+                socket: self.socket.clone(),
+                dst: src,
+            }),
+            tcp_keepalive: None,
+            datagram: true,
+        }))
+    }
+}
+
+struct UdpResponder {
+    socket: Arc<UdpSocket>,
+    dst: SocketAddr,
+}
+
+#[async_trait]
+impl Responder for UdpResponder {
+    async fn respond(&self, response: Vec<u8>) -> Result<()> {
+        self.socket.send_to(&response, self.dst).await?;
+        Ok(())
+    }
+}
+
+/// DNS over TCP (RFC 1035 length-prefixed messages).
+///
+/// Connections may carry several queries and stay open until they have been idle
+/// for `idle_timeout`.
+pub struct TcpTransport {
+    rx: Mutex<mpsc::Receiver<IncomingQuery>>,
+    local_addr: SocketAddr,
+}
+
+impl TcpTransport {
+    pub async fn bind(addr: SocketAddr, idle_timeout: Duration) -> Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        let (tx, rx) = mpsc::channel(128);
+
+        tokio::spawn(async move {
+            loop {
+                let accepted = tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    // Transport dropped; stop accepting.
+                    _ = tx.closed() => return,
+                };
+                match accepted {
+                    Ok((stream, src)) => {
+                        tokio::spawn(Self::read_queries(stream, src, idle_timeout, tx.clone()));
+                    }
+                    Err(e) => debug!("Failed to accept TCP connection: {}", e),
+                }
+            }
+        });
+
+        Ok(Self {
+            rx: Mutex::new(rx),
+            local_addr,
+        })
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+
+    async fn read_queries(
+        stream: TcpStream,
+        src: SocketAddr,
+        idle_timeout: Duration,
+        tx: mpsc::Sender<IncomingQuery>,
+    ) {
+        let (mut reader, writer) = stream.into_split();
+        let writer = Arc::new(Mutex::new(writer));
+        loop {
+            // The whole frame must arrive in time, so a client trickling bytes
+            // cannot hold the connection open.
+            let frame = async {
+                let mut len_buf = [0u8; 2];
+                reader.read_exact(&mut len_buf).await?;
+                let mut bytes = vec![0u8; u16::from_be_bytes(len_buf) as usize];
+                reader.read_exact(&mut bytes).await?;
+                Ok::<_, std::io::Error>(bytes)
+            };
+            let bytes = match tokio::time::timeout(idle_timeout, frame).await {
+                Ok(Ok(bytes)) => bytes,
+                Ok(Err(_)) => return, // Client closed the connection.
+                Err(_) => {
+                    debug!("Closing idle TCP connection from {}", src);
+                    return;
+                }
+            };
+            let query = IncomingQuery {
+                bytes,
+                src,
+                responder: Box::new(TcpResponder {
+                    writer: writer.clone(),
+                }),
+                tcp_keepalive: Some(idle_timeout),
+                datagram: false,
+            };
+            if tx.send(query).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Transport for TcpTransport {
+    async fn accept(&self) -> Result<Option<IncomingQuery>> {
+        Ok(self.rx.lock().await.recv().await)
+    }
+}
+
+struct TcpResponder {
+    writer: Arc<Mutex<OwnedWriteHalf>>,
+}
+
+#[async_trait]
+impl Responder for TcpResponder {
+    async fn respond(&self, response: Vec<u8>) -> Result<()> {
+        let len = u16::try_from(response.len())
+            .map_err(|_| anyhow::anyhow!("Response too large for TCP: {} bytes", response.len()))?;
+        // Hold the lock across both writes so pipelined responses don't interleave.
+        let mut writer = self.writer.lock().await;
+        writer.write_all(&len.to_be_bytes()).await?;
+        writer.write_all(&response).await?;
+        Ok(())
+    }
+}